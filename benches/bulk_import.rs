@@ -0,0 +1,111 @@
+//! Building a 100k-key trie one `insert` at a time versus bottom-up with
+//! `build_from_sorted`, and `Database::bulk_import` on top of a non-empty
+//! HEAD
+//!
+//! Both builds end with the same root hash. The bulk build stores each node
+//! once, as soon as it is complete, instead of keeping every changed path
+//! in memory until the commit. An import into a HEAD of comparable or
+//! larger size inserts the batch, so it costs about as much as the batch's
+//! own paths; only a batch that dwarfs HEAD rebuilds the tree.
+//!
+//! Run with:
+//! ```bash
+//! cargo bench --bench bulk_import
+//! ```
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use indra_db::trie::MerkleTrie;
+use indra_db::{Database, Hash, MemoryStore, Thought};
+
+const KEYS: usize = 100_000;
+
+fn bench_build(c: &mut Criterion) {
+    let mut entries: Vec<(Vec<u8>, Hash)> = (0..KEYS)
+        .map(|i| {
+            let key = format!(
+                "t:thought-{:08x}",
+                i.wrapping_mul(2_654_435_761) % (1 << 32)
+            );
+            let value = Hash::digest(key.as_bytes());
+            (key.into_bytes(), value)
+        })
+        .collect();
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut group = c.benchmark_group("build_100k");
+    group.throughput(Throughput::Elements(KEYS as u64));
+
+    group.bench_function("insert_and_commit", |b| {
+        b.iter(|| {
+            let store = MemoryStore::new();
+            let mut trie = MerkleTrie::new(&store);
+            for (key, value) in &entries {
+                trie.insert(key, *value).unwrap();
+            }
+            criterion::black_box(trie.commit().unwrap())
+        })
+    });
+
+    group.bench_function("build_from_sorted", |b| {
+        b.iter(|| {
+            let store = MemoryStore::new();
+            let sorted = entries.iter().cloned().map(Ok);
+            let trie = MerkleTrie::build_from_sorted(&store, sorted).unwrap();
+            criterion::black_box(trie.root_hash())
+        })
+    });
+
+    group.finish();
+}
+
+/// `count` thoughts with ids starting at `first`
+fn thoughts(first: usize, count: usize) -> Vec<Thought> {
+    (first..first + count)
+        .map(|i| Thought::with_id(format!("thought-{:08}", i), format!("Thought {}", i)))
+        .collect()
+}
+
+/// A database whose HEAD holds `count` thoughts
+fn base_database(count: usize) -> Database {
+    let mut db = Database::in_memory();
+    db.bulk_import(thoughts(0, count), Vec::new(), "Base")
+        .unwrap();
+    db
+}
+
+fn bench_import_on_head(c: &mut Criterion) {
+    let mut group = c.benchmark_group("import_on_head");
+
+    // Inserted along its paths; the growing HEAD barely matters
+    let mut db = base_database(KEYS);
+    let mut next = KEYS;
+    group.throughput(Throughput::Elements(100));
+    group.bench_function("100_into_100k", |b| {
+        b.iter(|| {
+            let batch = thoughts(next, 100);
+            next += 100;
+            criterion::black_box(db.bulk_import(batch, Vec::new(), "Import").unwrap())
+        })
+    });
+
+    // Fresh HEADs every time: the first is inserted, the second rebuilt
+    for (head, batch) in [(KEYS, KEYS / 4), (KEYS / 100, KEYS)] {
+        group.throughput(Throughput::Elements(batch as u64));
+        group.bench_function(format!("{}k_into_{}k", batch / 1000, head / 1000), |b| {
+            b.iter_batched(
+                || (base_database(head), thoughts(head, batch)),
+                |(mut db, batch)| db.bulk_import(batch, Vec::new(), "Import").unwrap(),
+                BatchSize::PerIteration,
+            )
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = bench_build, bench_import_on_head
+}
+criterion_main!(benches);
@@ -0,0 +1,68 @@
+//! `diff_trees` between neighbouring trees of a 100k-thought graph
+//!
+//! The new tree changes 1 to 1,000 thoughts of the old one. Subtrees with
+//! the same hash on both sides are skipped, so the time should grow with the
+//! number of changes and stay far below a full listing of either tree.
+//!
+//! Run with:
+//! ```bash
+//! cargo bench --bench diff
+//! ```
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use indra_db::ops::diff_trees;
+use indra_db::trie::MerkleTrie;
+use indra_db::{Hash, MemoryStore};
+
+const THOUGHTS: usize = 100_000;
+
+fn key(i: usize) -> String {
+    format!(
+        "t:thought-{:08x}",
+        i.wrapping_mul(2_654_435_761) % (1 << 32)
+    )
+}
+
+fn bench_diff(c: &mut Criterion) {
+    let store = MemoryStore::new();
+    let mut trie = MerkleTrie::new(&store);
+    for i in 0..THOUGHTS {
+        trie.insert(key(i).as_bytes(), Hash::digest(key(i).as_bytes()))
+            .unwrap();
+    }
+    let old = trie.commit().unwrap();
+
+    let mut group = c.benchmark_group("diff_100k");
+    for changes in [1, 10, 100, 1000] {
+        let mut trie = MerkleTrie::from_root(&store, old).unwrap();
+        for i in 0..changes {
+            let i = i * (THOUGHTS / changes);
+            trie.insert(key(i).as_bytes(), Hash::digest(&i.to_le_bytes()))
+                .unwrap();
+        }
+        let new = trie.commit().unwrap();
+
+        group.bench_with_input(BenchmarkId::new("changed", changes), &new, |b, new| {
+            b.iter(|| {
+                let diff = diff_trees(&store, old, *new).unwrap();
+                assert_eq!(diff.modified_count(), changes);
+            })
+        });
+    }
+    group.finish();
+
+    // For scale: the full listing the old implementation did on both sides
+    c.bench_function("list_100k", |b| {
+        b.iter(|| {
+            let trie = MerkleTrie::from_root(&store, old).unwrap();
+            criterion::black_box(trie.list_prefix(b"t:").unwrap())
+        })
+    });
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(20);
+    targets = bench_diff
+}
+criterion_main!(benches);
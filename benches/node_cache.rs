@@ -0,0 +1,83 @@
+//! Repeated `get_thought` on a 10k-thought database, with and without the
+//! trie node cache
+//!
+//! Every lookup walks the trie from the root. With the cache, the upper
+//! levels are decoded once and shared by every lookup; without it, each
+//! lookup reads and deserializes every node on its path.
+//!
+//! Run with:
+//! ```bash
+//! cargo bench --bench node_cache
+//! ```
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use indra_db::{Database, StoreOptions, ThoughtId};
+use std::path::Path;
+use tempfile::TempDir;
+
+const THOUGHTS: usize = 10_000;
+
+/// Lookups per iteration
+const READS: usize = 1_000;
+
+fn build_database() -> TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    let mut db = Database::create(dir.path().join("bench.indra")).unwrap();
+    for i in 0..THOUGHTS {
+        db.create_thought_with_id(
+            format!("t{}", i),
+            format!("Thought number {} with a little padding to look real", i),
+        )
+        .unwrap();
+    }
+    db.commit("Bench").unwrap();
+    db.sync().unwrap();
+    dir
+}
+
+fn open(path: &Path, cache_size: usize) -> Database {
+    let options = StoreOptions::default()
+        .with_read_only(true)
+        .with_node_cache_size(cache_size);
+    Database::open_with_options(path, options).unwrap()
+}
+
+fn bench_get_thought(c: &mut Criterion) {
+    let dir = build_database();
+    let path = dir.path().join("bench.indra");
+    let ids: Vec<ThoughtId> = (0..READS)
+        .map(|i| ThoughtId::new(format!("t{}", (i * 7919) % THOUGHTS)))
+        .collect();
+
+    let mut group = c.benchmark_group("get_thought_10k");
+    group.throughput(Throughput::Elements(READS as u64));
+
+    for (name, cache_size) in [("uncached", 0), ("cached", THOUGHTS * 2)] {
+        let db = open(&path, cache_size);
+        group.bench_with_input(BenchmarkId::new("cache", name), &ids, |b, ids| {
+            b.iter(|| {
+                for id in ids {
+                    criterion::black_box(db.get_thought(id).unwrap());
+                }
+            })
+        });
+        if let Some(stats) = db.node_cache_stats() {
+            eprintln!(
+                "{}: {} hits, {} misses ({:.1}% hit rate)",
+                name,
+                stats.hits,
+                stats.misses,
+                stats.hit_rate() * 100.0
+            );
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(20);
+    targets = bench_get_thought
+}
+criterion_main!(benches);
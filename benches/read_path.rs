@@ -0,0 +1,84 @@
+//! Object store read throughput on a 100k-thought database
+//!
+//! Measures single-threaded and multi-threaded `ObjectStore::get`. Reads are
+//! served from a memory map, so the parallel case should scale with the
+//! thread count instead of serializing on the file handle.
+//!
+//! Run with:
+//! ```bash
+//! cargo bench --bench read_path
+//! ```
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use indra_db::{Hash, ObjectStore, Storage, Thought};
+use tempfile::TempDir;
+
+const THOUGHTS: usize = 100_000;
+
+/// Reads per iteration, split evenly across threads
+const READS: usize = 10_000;
+
+fn build_store() -> (TempDir, ObjectStore, Vec<Hash>) {
+    let dir = tempfile::tempdir().unwrap();
+    let store = ObjectStore::create(dir.path().join("bench.indra")).unwrap();
+    let hashes = (0..THOUGHTS)
+        .map(|i| {
+            let thought = Thought::with_id(
+                format!("t{}", i),
+                format!("Thought number {} with a little padding to look real", i),
+            );
+            store.put_thought(&thought).unwrap()
+        })
+        .collect();
+    store.sync().unwrap();
+    (dir, store, hashes)
+}
+
+/// Spread reads over the whole file so they don't all hit one region
+fn sample(hashes: &[Hash], count: usize, seed: usize) -> Vec<Hash> {
+    let stride = 7919;
+    (0..count)
+        .map(|i| hashes[(seed + i * stride) % hashes.len()])
+        .collect()
+}
+
+fn bench_get(c: &mut Criterion) {
+    let (_dir, store, hashes) = build_store();
+
+    let mut group = c.benchmark_group("get_100k");
+    group.throughput(Throughput::Elements(READS as u64));
+
+    for threads in [1, 2, 4, 8] {
+        let batches: Vec<Vec<Hash>> = (0..threads)
+            .map(|t| sample(&hashes, READS / threads, t))
+            .collect();
+
+        group.bench_with_input(
+            BenchmarkId::new("threads", threads),
+            &batches,
+            |b, batches| {
+                b.iter(|| {
+                    std::thread::scope(|scope| {
+                        for batch in batches {
+                            let store = &store;
+                            scope.spawn(move || {
+                                for hash in batch {
+                                    criterion::black_box(store.get(hash).unwrap());
+                                }
+                            });
+                        }
+                    });
+                })
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(20);
+    targets = bench_get
+}
+criterion_main!(benches);
@@ -0,0 +1,120 @@
+//! Commit cost on a 1M-object database and after a long history
+//!
+//! Measures a small write followed by `ObjectStore::sync`, which is what
+//! every mutating CLI command does, and `ObjectStore::open`. Syncs only
+//! append the index entries added since the previous record, so the write
+//! case should stay flat as the database grows; open pays for merging the
+//! segments.
+//!
+//! The history case makes thousands of commits, with a repack part way
+//! through so later records run with trained dictionaries, and prints the
+//! bytes each commit adds to the file. Reflog entries and dictionaries are
+//! written once rather than into every record, so apart from the occasional
+//! consolidation of index and reflog segments that figure should not grow
+//! with the number of commits.
+//!
+//! Run with:
+//! ```bash
+//! cargo bench --bench sync_path
+//! ```
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use indra_db::store::{Blob, BlobType};
+use indra_db::{Database, ObjectStore, Storage};
+use std::path::PathBuf;
+use tempfile::TempDir;
+
+const OBJECTS: usize = 1_000_000;
+
+/// Objects written before each sync
+const BATCH: usize = 10;
+
+/// A small object, distinct for every `i`
+fn blob(i: usize) -> Blob {
+    Blob::new(BlobType::Thought, format!("object {}", i).into_bytes())
+}
+
+fn build_store() -> (TempDir, PathBuf) {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("bench.indra");
+    let store = ObjectStore::create(&path).unwrap();
+    for i in 0..OBJECTS {
+        store.put(&blob(i)).unwrap();
+    }
+    store.sync().unwrap();
+    (dir, path)
+}
+
+fn bench_sync(c: &mut Criterion) {
+    let (_dir, path) = build_store();
+    let mut group = c.benchmark_group("sync_1m");
+
+    {
+        let store = ObjectStore::open(&path).unwrap();
+        let mut next = OBJECTS;
+        group.bench_function("put_then_sync", |b| {
+            b.iter(|| {
+                for _ in 0..BATCH {
+                    store.put(&blob(next)).unwrap();
+                    next += 1;
+                }
+                store.sync().unwrap();
+            })
+        });
+    }
+
+    group.bench_function("open", |b| {
+        b.iter(|| criterion::black_box(ObjectStore::open(&path).unwrap()))
+    });
+
+    group.finish();
+}
+
+/// Commits made before the repack, and after it
+const HISTORY: (usize, usize) = (2_000, 3_000);
+
+/// One thought per commit, synced as the CLI does
+fn commit_one(db: &mut Database, i: usize) {
+    db.create_thought(format!("thought {}", i)).unwrap();
+    db.commit(&format!("commit {}", i)).unwrap();
+    db.sync().unwrap();
+}
+
+fn bench_history(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("bench.indra");
+    let mut db = Database::create(&path).unwrap();
+    let file_len = || std::fs::metadata(&path).unwrap().len();
+
+    for i in 0..HISTORY.0 {
+        commit_one(&mut db, i);
+    }
+    db.repack().unwrap();
+    let start = file_len();
+    for i in HISTORY.0..HISTORY.0 + HISTORY.1 {
+        commit_one(&mut db, i);
+    }
+    println!(
+        "history: {} commits, {} bytes, {} bytes per commit after the repack",
+        HISTORY.0 + HISTORY.1,
+        file_len(),
+        (file_len() - start) / HISTORY.1 as u64
+    );
+
+    let mut group = c.benchmark_group("history_5k");
+    let mut next = HISTORY.0 + HISTORY.1;
+    group.bench_function("commit_then_sync", |b| {
+        b.iter(|| {
+            commit_one(&mut db, next);
+            next += 1;
+        })
+    });
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(20);
+    targets = bench_sync, bench_history
+}
+criterion_main!(benches);
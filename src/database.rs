@@ -3,16 +3,28 @@
 //! This module provides the main entry point for interacting with indra_db.
 
 use crate::embedding::Embedder;
-use crate::graph::GraphView;
-use crate::model::{Commit, Edge, EdgeType, Hash, JsonValue, Thought, ThoughtId};
-use crate::ops::{diff_trees, BranchManager, Diff};
-use crate::search::{SearchResult, VectorSearch};
-use crate::store::ObjectStore;
-use crate::trie::MerkleTrie;
+use crate::model::{AttachmentRef, Commit, Edge, EdgeType, Hash, JsonValue, Thought, ThoughtId};
+use crate::ops::{
+    checkout, BackupReport, BranchManager, BundleReport, Diff, FsckReport, StoreStats,
+};
+use crate::search::SearchResult;
+use crate::snapshot::Snapshot;
+use crate::store::{
+    put_attachment, AttachmentReader, GcStats, Head, MemoryStore, ObjectStore, RefLogEntry,
+    RepackStats, Storage, StoreOptions,
+};
+use crate::trie::{cursor_token, page_start, CacheStats, MerkleTrie, Page, Proof};
 use crate::Result;
+use std::collections::{BTreeMap, HashSet};
+use std::io::{Read, Write};
+use std::ops::Bound;
 use std::path::Path;
 use std::sync::Arc;
 
+/// `bulk_import` rebuilds the tree when HEAD holds at most one entry per
+/// this many imported ones, and inserts the batch into it otherwise
+const REBUILD_RATIO: usize = 64;
+
 /// The main database interface
 ///
 /// Provides a convenient API for:
@@ -21,7 +33,7 @@ use std::sync::Arc;
 /// - Semantic search
 /// - Version control (branches, commits, history)
 pub struct Database {
-    store: ObjectStore,
+    store: Arc<dyn Storage>,
     embedder: Option<Arc<dyn Embedder>>,
     /// Current working state (uncommitted changes)
     working_tree: WorkingTree,
@@ -64,32 +76,66 @@ impl WorkingTree {
 impl Database {
     /// Create a new database at the given path
     pub fn create(path: impl AsRef<Path>) -> Result<Self> {
-        let store = ObjectStore::create(path)?;
-        Ok(Database {
-            store,
-            embedder: None,
-            working_tree: WorkingTree::new(),
-        })
+        Self::create_with_options(path, StoreOptions::default())
+    }
+
+    /// Create a new database with explicit store options
+    pub fn create_with_options(path: impl AsRef<Path>, options: StoreOptions) -> Result<Self> {
+        let store = ObjectStore::create_with_options(path, options)?;
+        Ok(Self::with_storage(store))
     }
 
     /// Open an existing database
+    ///
+    /// Takes an exclusive lock, waiting up to the default lock timeout for
+    /// other processes to finish. Returns `Error::Locked` on timeout.
     pub fn open(path: impl AsRef<Path>) -> Result<Self> {
-        let store = ObjectStore::open(path)?;
-        Ok(Database {
-            store,
-            embedder: None,
-            working_tree: WorkingTree::new(),
-        })
+        Self::open_with_options(path, StoreOptions::default())
+    }
+
+    /// Open an existing database for reading only
+    ///
+    /// Any number of read-only handles can be open at once; they wait for a
+    /// writer to finish and block writers while open.
+    pub fn open_read_only(path: impl AsRef<Path>) -> Result<Self> {
+        Self::open_with_options(path, StoreOptions::default().with_read_only(true))
+    }
+
+    /// Open an existing database with explicit store options
+    pub fn open_with_options(path: impl AsRef<Path>, options: StoreOptions) -> Result<Self> {
+        let store = ObjectStore::open_with_options(path, options)?;
+        Ok(Self::with_storage(store))
     }
 
     /// Open or create a database
     pub fn open_or_create(path: impl AsRef<Path>) -> Result<Self> {
-        let store = ObjectStore::open_or_create(path)?;
-        Ok(Database {
-            store,
+        Self::open_or_create_with_options(path, StoreOptions::default())
+    }
+
+    /// Open or create a database with explicit store options
+    pub fn open_or_create_with_options(
+        path: impl AsRef<Path>,
+        options: StoreOptions,
+    ) -> Result<Self> {
+        let store = ObjectStore::open_or_create_with_options(path, options)?;
+        Ok(Self::with_storage(store))
+    }
+
+    /// Create a database that lives only in memory
+    ///
+    /// Nothing is written to disk; everything is lost when the database is
+    /// dropped.
+    pub fn in_memory() -> Self {
+        Self::with_storage(MemoryStore::new())
+    }
+
+    /// Create a database on top of any storage backend
+    pub fn with_storage(store: impl Storage + 'static) -> Self {
+        Database {
+            store: Arc::new(store),
             embedder: None,
             working_tree: WorkingTree::new(),
-        })
+        }
     }
 
     /// Set the embedder to use for semantic search
@@ -110,13 +156,7 @@ impl Database {
         let mut thought = Thought::new(content);
 
         // Generate embedding if we have an embedder
-        if let Some(ref embedder) = self.embedder {
-            thought.embedding = Some(embedder.embed(&thought.content)?);
-            thought.attrs.insert(
-                "embedder_model".to_string(),
-                JsonValue::new(serde_json::Value::String(embedder.model_name().to_string())),
-            );
-        }
+        self.embed(&mut thought)?;
 
         let id = thought.id.clone();
         self.working_tree.thoughts.insert(id.clone(), thought);
@@ -135,13 +175,7 @@ impl Database {
         let id = id.into();
         let mut thought = Thought::with_id(id.clone(), content);
 
-        if let Some(ref embedder) = self.embedder {
-            thought.embedding = Some(embedder.embed(&thought.content)?);
-            thought.attrs.insert(
-                "embedder_model".to_string(),
-                JsonValue::new(serde_json::Value::String(embedder.model_name().to_string())),
-            );
-        }
+        self.embed(&mut thought)?;
 
         self.working_tree.thoughts.insert(id.clone(), thought);
         self.working_tree.removed_thoughts.remove(&id);
@@ -162,14 +196,7 @@ impl Database {
             return Ok(Some(thought.clone()));
         }
 
-        // Check committed state
-        let tree_hash = self.head_tree()?;
-        if tree_hash.is_zero() {
-            return Ok(None);
-        }
-
-        let view = GraphView::new(&self.store, tree_hash)?;
-        view.get_thought(id)
+        self.snapshot()?.get_thought(id)
     }
 
     /// Update a thought's content
@@ -181,13 +208,7 @@ impl Database {
         thought.update_content(content);
 
         // Re-embed if we have an embedder
-        if let Some(ref embedder) = self.embedder {
-            thought.embedding = Some(embedder.embed(&thought.content)?);
-            thought.attrs.insert(
-                "embedder_model".to_string(),
-                JsonValue::new(serde_json::Value::String(embedder.model_name().to_string())),
-            );
-        }
+        self.embed(&mut thought)?;
 
         self.working_tree.thoughts.insert(id.clone(), thought);
         self.working_tree.dirty = true;
@@ -203,28 +224,131 @@ impl Database {
         Ok(())
     }
 
-    /// List all thoughts (committed + working tree changes)
+    /// List all thoughts (committed + working tree changes) in ID order
     pub fn list_thoughts(&self) -> Result<Vec<Thought>> {
-        let tree_hash = self.head_tree()?;
-        let mut thoughts: std::collections::HashMap<ThoughtId, Thought> = if tree_hash.is_zero() {
-            std::collections::HashMap::new()
-        } else {
-            let view = GraphView::new(&self.store, tree_hash)?;
-            view.all_thoughts()?
-                .into_iter()
-                .map(|t| (t.id.clone(), t))
-                .collect()
-        };
+        Ok(self.list_thoughts_page(None, usize::MAX)?.items)
+    }
 
-        // Apply working tree changes
-        for (id, thought) in &self.working_tree.thoughts {
-            thoughts.insert(id.clone(), thought.clone());
-        }
-        for id in &self.working_tree.removed_thoughts {
-            thoughts.remove(id);
+    /// Up to `limit` thoughts (committed + working tree changes) in ID
+    /// order, starting after `cursor` from the previous page
+    ///
+    /// Committed thoughts are read from the trie as the page fills, so
+    /// paging through a large graph does not load all of it. A `limit` of
+    /// zero fails with `Error::InvalidLimit`.
+    pub fn list_thoughts_page(&self, cursor: Option<&str>, limit: usize) -> Result<Page<Thought>> {
+        let start = page_start(cursor, limit)?;
+        let key = |id: &ThoughtId| format!("t:{}", id.0).into_bytes();
+        let removed: HashSet<Vec<u8>> =
+            self.working_tree.removed_thoughts.iter().map(key).collect();
+        let changed: BTreeMap<Vec<u8>, &Thought> = self
+            .working_tree
+            .thoughts
+            .iter()
+            .map(|(id, thought)| (key(id), thought))
+            .filter(|(key, _)| match &start {
+                Bound::Excluded(after) => key > after,
+                _ => true,
+            })
+            .collect();
+
+        let snapshot = self.snapshot()?;
+        let view = snapshot.view()?;
+        let mut committed = view
+            .iter()
+            .flat_map(|view| view.thought_keys(start.clone()));
+        let mut changed = changed.into_iter();
+        let mut next_committed = committed.next().transpose()?;
+        let mut next_changed = changed.next();
+
+        let mut items = Vec::new();
+        let mut last = None;
+        loop {
+            // Merge both in key order; working tree versions win
+            let take_changed = match (&next_committed, &next_changed) {
+                (None, None) => break,
+                (Some(_), None) => false,
+                (None, Some(_)) => true,
+                (Some((committed_key, _)), Some((changed_key, _))) => changed_key <= committed_key,
+            };
+            let (key, thought) = if take_changed {
+                let (key, thought) = next_changed.take().expect("checked above");
+                next_changed = changed.next();
+                if next_committed.as_ref().is_some_and(|(k, _)| *k == key) {
+                    next_committed = committed.next().transpose()?;
+                }
+                (key, Ok(thought))
+            } else {
+                let (key, hash) = next_committed.take().expect("checked above");
+                next_committed = committed.next().transpose()?;
+                (key, Err(hash))
+            };
+            if removed.contains(&key) {
+                continue;
+            }
+            if items.len() == limit {
+                return Ok(Page {
+                    items,
+                    next: last.map(|key: Vec<u8>| cursor_token(&key)),
+                });
+            }
+            items.push(match thought {
+                Ok(thought) => thought.clone(),
+                Err(hash) => self.store.get_thought(&hash)?,
+            });
+            last = Some(key);
         }
+        Ok(Page { items, next: None })
+    }
+
+    // === Attachment Operations ===
+
+    /// Attach content read from `reader` to a thought under `name`
+    ///
+    /// The content is streamed into the store in chunks right away; the
+    /// thought's updated attachment list is part of the next commit. An
+    /// existing attachment with the same name is replaced. Content stored
+    /// this way is unreachable until committed, so `gc` would drop it.
+    pub fn attach(
+        &mut self,
+        id: &ThoughtId,
+        name: impl Into<String>,
+        mime_type: impl Into<String>,
+        reader: impl Read,
+    ) -> Result<AttachmentRef> {
+        let mut thought = self
+            .get_thought(id)?
+            .ok_or_else(|| crate::Error::NotFound(id.to_string()))?;
+
+        let (hash, size) = put_attachment(self.store.as_ref(), reader)?;
+        let attachment = AttachmentRef {
+            name: name.into(),
+            mime_type: mime_type.into(),
+            size,
+            hash,
+        };
+        thought.set_attachment(attachment.clone());
+
+        self.working_tree.thoughts.insert(id.clone(), thought);
+        self.working_tree.dirty = true;
+
+        Ok(attachment)
+    }
+
+    /// Stream the content of an attachment
+    pub fn read_attachment(&self, attachment: &AttachmentRef) -> Result<AttachmentReader<'_>> {
+        AttachmentReader::open(self.store.as_ref(), &attachment.hash)
+    }
 
-        Ok(thoughts.into_values().collect())
+    /// Embed a thought's content if we have an embedder
+    fn embed(&self, thought: &mut Thought) -> Result<()> {
+        if let Some(ref embedder) = self.embedder {
+            thought.embedding = Some(embedder.embed(&thought.content)?);
+            thought.attrs.insert(
+                "embedder_model".to_string(),
+                JsonValue::new(serde_json::Value::String(embedder.model_name().to_string())),
+            );
+        }
+        Ok(())
     }
 
     // === Edge Operations ===
@@ -289,34 +413,14 @@ impl Database {
         id: &ThoughtId,
         direction: crate::graph::TraversalDirection,
     ) -> Result<Vec<(Thought, Edge)>> {
-        let tree_hash = self.head_tree()?;
-        if tree_hash.is_zero() {
-            return Ok(vec![]);
-        }
-
-        let view = GraphView::new(&self.store, tree_hash)?;
-        view.neighbors(id, direction, None)
+        self.snapshot()?.neighbors(id, direction)
     }
 
     // === Search Operations ===
 
     /// Semantic search for thoughts similar to the query
     pub fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchResult>> {
-        let embedder = self
-            .embedder
-            .as_ref()
-            .ok_or_else(|| crate::Error::Embedding("No embedder configured".into()))?;
-
-        let query_embedding = embedder.embed(query)?;
-
-        let tree_hash = self.head_tree()?;
-        if tree_hash.is_zero() {
-            return Ok(vec![]);
-        }
-
-        let view = GraphView::new(&self.store, tree_hash)?;
-        let search = VectorSearch::new(&view);
-        search.search(&query_embedding, limit)
+        self.snapshot()?.search(query, limit)
     }
 
     /// Search with a minimum similarity threshold
@@ -326,21 +430,8 @@ impl Database {
         threshold: f32,
         limit: usize,
     ) -> Result<Vec<SearchResult>> {
-        let embedder = self
-            .embedder
-            .as_ref()
-            .ok_or_else(|| crate::Error::Embedding("No embedder configured".into()))?;
-
-        let query_embedding = embedder.embed(query)?;
-
-        let tree_hash = self.head_tree()?;
-        if tree_hash.is_zero() {
-            return Ok(vec![]);
-        }
-
-        let view = GraphView::new(&self.store, tree_hash)?;
-        let search = VectorSearch::new(&view);
-        search.search_with_threshold(&query_embedding, threshold, limit)
+        self.snapshot()?
+            .search_with_threshold(query, threshold, limit)
     }
 
     // === Version Control Operations ===
@@ -354,7 +445,7 @@ impl Database {
     pub fn commit_with_author(&mut self, message: &str, author: &str) -> Result<Hash> {
         // Build tree from current state
         let base_tree = self.head_tree()?;
-        let mut trie = MerkleTrie::from_root(&self.store, base_tree)?;
+        let mut trie = MerkleTrie::from_root(self.store.as_ref(), base_tree)?;
 
         // Check if there are any changes to commit
         let has_changes = !self.working_tree.thoughts.is_empty()
@@ -391,7 +482,7 @@ impl Database {
         // Commit the tree
         let tree_hash = trie.commit()?;
 
-        let manager = BranchManager::new(&self.store);
+        let manager = BranchManager::new(self.store.as_ref());
         let commit_hash = manager.commit(tree_hash, message, author)?;
 
         // Clear working tree
@@ -400,32 +491,118 @@ impl Database {
         Ok(commit_hash)
     }
 
+    /// Import thoughts and edges as one commit on top of HEAD
+    ///
+    /// Each blob is written as it is read. Into an empty HEAD, or one that
+    /// is small next to the batch, the new tree is then built bottom-up
+    /// from the keys of HEAD merged with the imported ones, which stores
+    /// each node once instead of rewriting a path per key. A smaller batch
+    /// is inserted into HEAD's tree, so only the paths to its keys are
+    /// stored. Either way the tree is the same one committing the entries
+    /// one by one would give. Imported entries replace committed ones with
+    /// the same key, and uncommitted changes stay in the working tree.
+    pub fn bulk_import(
+        &mut self,
+        thoughts: impl IntoIterator<Item = Thought>,
+        edges: impl IntoIterator<Item = Edge>,
+        message: &str,
+    ) -> Result<Hash> {
+        let mut imported = BTreeMap::new();
+        for mut thought in thoughts {
+            if thought.embedding.is_none() {
+                self.embed(&mut thought)?;
+            }
+            let hash = self.store.put_thought(&thought)?;
+            imported.insert(format!("t:{}", thought.id.0).into_bytes(), hash);
+        }
+        for edge in edges {
+            let hash = self.store.put_edge(&edge)?;
+            imported.insert(format!("e:{}", edge_key(&edge)).into_bytes(), hash);
+        }
+        if imported.is_empty() {
+            return Err(crate::Error::NotFound("Nothing to import".into()));
+        }
+
+        // Count HEAD's entries only as far as it takes to choose
+        let mut base = MerkleTrie::from_root(self.store.as_ref(), self.head_tree()?)?;
+        let limit = imported.len() / REBUILD_RATIO;
+        let mut committed = 0;
+        for entry in base
+            .range(Bound::Unbounded, Bound::Unbounded)
+            .take(limit + 1)
+        {
+            entry?;
+            committed += 1;
+        }
+        let tree_hash = if committed > limit {
+            for (key, hash) in imported {
+                base.insert(&key, hash)?;
+            }
+            base.commit()?
+        } else {
+            self.build_merged(&base, imported)?
+        };
+
+        let manager = BranchManager::new(self.store.as_ref());
+        manager.commit(tree_hash, message, "indra_db")
+    }
+
+    /// Build the tree holding `base`'s entries and `imported`, which win
+    /// where both have a key
+    fn build_merged(&self, base: &MerkleTrie, imported: BTreeMap<Vec<u8>, Hash>) -> Result<Hash> {
+        // Both sides are in key order, so a merge keeps the result sorted
+        let mut committed = base.range(Bound::Unbounded, Bound::Unbounded).peekable();
+        let mut imported = imported.into_iter().peekable();
+        let merged = std::iter::from_fn(|| {
+            let take_committed = match (committed.peek(), imported.peek()) {
+                (None, None) => return None,
+                (Some(Ok((key, _))), Some((new_key, _))) => key < new_key,
+                (Some(_), _) => true,
+                (None, Some(_)) => false,
+            };
+            if take_committed {
+                return committed.next();
+            }
+            let (key, hash) = imported.next()?;
+            if matches!(committed.peek(), Some(Ok((old_key, _))) if *old_key == key) {
+                committed.next();
+            }
+            Some(Ok((key, hash)))
+        });
+        Ok(MerkleTrie::build_from_sorted(self.store.as_ref(), merged)?.root_hash())
+    }
+
     /// Check if there are uncommitted changes
     pub fn is_dirty(&self) -> bool {
         self.working_tree.dirty
     }
 
-    /// Get the current branch name
-    pub fn current_branch(&self) -> String {
+    /// Get what HEAD points at
+    pub fn head(&self) -> Head {
         self.store.head()
     }
 
+    /// Get the current branch name, unless HEAD is detached
+    pub fn current_branch(&self) -> Option<String> {
+        self.store.head().branch().map(str::to_string)
+    }
+
     /// Create a new branch at current HEAD
     pub fn create_branch(&self, name: &str) -> Result<()> {
-        let manager = BranchManager::new(&self.store);
+        let manager = BranchManager::new(self.store.as_ref());
         manager.create_branch(name)
     }
 
-    /// Switch to a branch
-    pub fn checkout(&mut self, branch: &str) -> Result<()> {
+    /// Switch to a branch, or detach HEAD at a commit hash
+    pub fn checkout(&mut self, target: &str) -> Result<()> {
         if self.working_tree.dirty {
             return Err(crate::Error::BranchNotFound(
                 "Cannot checkout with uncommitted changes".into(),
             ));
         }
 
-        let manager = BranchManager::new(&self.store);
-        manager.switch_branch(branch)
+        checkout(self.store.as_ref(), target)?;
+        Ok(())
     }
 
     /// List all branches
@@ -435,25 +612,56 @@ impl Database {
 
     /// Get commit history
     pub fn log(&self, limit: Option<usize>) -> Result<Vec<(Hash, Commit)>> {
-        let manager = BranchManager::new(&self.store);
+        let manager = BranchManager::new(self.store.as_ref());
         manager.log(limit)
     }
 
+    /// Get ref movements, newest first, optionally for a single ref
+    pub fn reflog(&self, name: Option<&str>, limit: Option<usize>) -> Vec<RefLogEntry> {
+        let manager = BranchManager::new(self.store.as_ref());
+        manager.reflog(name, limit)
+    }
+
     /// Diff between two commits
     pub fn diff(&self, from: Hash, to: Hash) -> Result<Diff> {
-        let from_tree = if from.is_zero() {
-            Hash::ZERO
-        } else {
-            self.store.get_commit(&from)?.tree
-        };
+        self.snapshot()?.diff(from, to)
+    }
 
-        let to_tree = if to.is_zero() {
-            Hash::ZERO
-        } else {
-            self.store.get_commit(&to)?.tree
-        };
+    /// Prove that a thought, as stored at `commit`, is part of that commit
+    ///
+    /// Returns `None` if the commit does not hold the thought. The proof
+    /// checks with `verify_proof` against the commit's tree hash.
+    pub fn prove_thought(&self, id: &ThoughtId, commit: Hash) -> Result<Option<Proof>> {
+        self.prove_key(&format!("t:{}", id.0), commit)
+    }
+
+    /// Prove that an edge is part of `commit`
+    pub fn prove_edge(
+        &self,
+        source: impl Into<ThoughtId>,
+        target: impl Into<ThoughtId>,
+        edge_type: impl Into<EdgeType>,
+        commit: Hash,
+    ) -> Result<Option<Proof>> {
+        let edge = Edge::new(source, target, edge_type);
+        self.prove_key(&format!("e:{}", edge_key(&edge)), commit)
+    }
 
-        diff_trees(&self.store, from_tree, to_tree)
+    fn prove_key(&self, key: &str, commit: Hash) -> Result<Option<Proof>> {
+        let tree = self.store.get_commit(&commit)?.tree;
+        if tree.is_zero() {
+            return Ok(None);
+        }
+        MerkleTrie::from_root(self.store.as_ref(), tree)?.prove(key.as_bytes())
+    }
+
+    /// Pin the committed state at HEAD for reading
+    ///
+    /// The snapshot ignores uncommitted changes and keeps answering from
+    /// the same commit while this handle goes on committing. It can be
+    /// cloned and sent to other threads.
+    pub fn snapshot(&self) -> Result<Snapshot> {
+        Snapshot::at_head(self.store.clone(), self.embedder.clone())
     }
 
     /// Get the current HEAD tree hash
@@ -470,6 +678,83 @@ impl Database {
     pub fn sync(&self) -> Result<()> {
         self.store.sync()
     }
+
+    /// Drop objects no longer reachable from any branch and compact the file
+    ///
+    /// Attachments added to the working tree are stored right away, so
+    /// they are kept even though no commit points at them yet.
+    pub fn gc(&self) -> Result<GcStats> {
+        let keep: Vec<Hash> = self
+            .working_tree
+            .thoughts
+            .values()
+            .flat_map(|thought| thought.attachments.iter().map(|a| a.hash))
+            .collect();
+        self.store.gc(&keep)
+    }
+
+    /// Store similar objects as deltas against each other
+    ///
+    /// Worth running after heavy editing: each version of a thought is
+    /// otherwise stored whole, embedding included.
+    pub fn repack(&self) -> Result<RepackStats> {
+        self.store.repack()
+    }
+
+    /// Verify objects, trie structure and refs
+    ///
+    /// With `repair`, index entries that are unreadable or fail to re-hash
+    /// are dropped. See `ops::fsck`.
+    pub fn fsck(&self, repair: bool) -> Result<FsckReport> {
+        crate::ops::fsck(self.store.as_ref(), repair)
+    }
+
+    /// Write a verified point-in-time copy of the committed state to `path`
+    ///
+    /// Copies every ref, or only `branches` if any are named, and creates
+    /// the new file with `options`. Safe while other handles keep writing.
+    /// See `ops::backup`.
+    pub fn backup_to(
+        &self,
+        path: impl AsRef<Path>,
+        branches: &[String],
+        options: StoreOptions,
+    ) -> Result<BackupReport> {
+        crate::ops::backup(self.store.as_ref(), path.as_ref(), branches, options)
+    }
+
+    /// Write the history of `refs` to a bundle, leaving out what the
+    /// `since` commits reach
+    ///
+    /// See `ops::bundle` for the format.
+    pub fn create_bundle(
+        &self,
+        out: impl Write,
+        refs: &[String],
+        since: &[Hash],
+    ) -> Result<BundleReport> {
+        crate::ops::create_bundle(self.store.as_ref(), out, refs, since)
+    }
+
+    /// Import a bundle and point its branches at the bundled commits
+    ///
+    /// Branches only move forward unless `force`. Every object is checked
+    /// against its hash before anything moves.
+    pub fn unbundle(&mut self, input: impl Read, force: bool) -> Result<BundleReport> {
+        crate::ops::unbundle(self.store.as_ref(), input, force)
+    }
+
+    /// Object, branch, embedding and commit statistics
+    ///
+    /// Covers committed state only. See `ops::stats`.
+    pub fn stats(&self) -> Result<StoreStats> {
+        crate::ops::stats(self.store.as_ref())
+    }
+
+    /// Hits, misses and size of the store's decoded trie node cache
+    pub fn node_cache_stats(&self) -> Option<CacheStats> {
+        self.store.node_cache().map(|cache| cache.stats())
+    }
 }
 
 fn edge_key(edge: &Edge) -> String {
@@ -480,6 +765,7 @@ fn edge_key(edge: &Edge) -> String {
 mod tests {
     use super::*;
     use crate::embedding::MockEmbedder;
+    use crate::trie::verify_proof;
     use tempfile::tempdir;
 
     #[test]
@@ -573,7 +859,7 @@ mod tests {
         // Create and switch to feature branch
         db.create_branch("feature").unwrap();
         db.checkout("feature").unwrap();
-        assert_eq!(db.current_branch(), "feature");
+        assert_eq!(db.current_branch().as_deref(), Some("feature"));
 
         // Add thought on feature
         db.create_thought("Feature thought").unwrap();
@@ -581,7 +867,44 @@ mod tests {
 
         // Switch back to main
         db.checkout("main").unwrap();
-        assert_eq!(db.current_branch(), "main");
+        assert_eq!(db.current_branch().as_deref(), Some("main"));
+    }
+
+    #[test]
+    fn test_long_branch_name_and_detached_head_survive_reopen() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.indra");
+        let branch = "experiments/try-mongodb/with-a-much-longer-name";
+        let first;
+        {
+            let mut db = Database::create(&path).unwrap();
+            db.create_thought("Initial thought").unwrap();
+            first = db.commit("Initial commit").unwrap();
+            db.create_branch(branch).unwrap();
+            db.checkout(branch).unwrap();
+        }
+        {
+            let mut db = Database::open(&path).unwrap();
+            assert_eq!(db.current_branch().as_deref(), Some(branch));
+            db.checkout(&first.to_hex()).unwrap();
+        }
+
+        let db = Database::open(&path).unwrap();
+        assert_eq!(db.head(), Head::Detached(first));
+        assert_eq!(db.list_thoughts().unwrap().len(), 1);
+
+        let reasons: Vec<_> = db
+            .reflog(Some(crate::store::HEAD_REF), None)
+            .into_iter()
+            .map(|e| e.reason)
+            .collect();
+        assert_eq!(
+            reasons,
+            vec![
+                format!("checkout: moving to {}", first.short()),
+                format!("checkout: moving to {}", branch),
+            ]
+        );
     }
 
     #[test]
@@ -601,4 +924,293 @@ mod tests {
         assert_eq!(log[0].1.message, "Second commit");
         assert_eq!(log[1].1.message, "First commit");
     }
+
+    #[test]
+    fn test_in_memory_database() {
+        let mut db = Database::in_memory();
+
+        let id = db.create_thought_with_id("note", "Scratch").unwrap();
+        db.commit("First").unwrap();
+        db.update_thought(&id, "Revised").unwrap();
+        db.commit("Second").unwrap();
+
+        assert_eq!(db.get_thought(&id).unwrap().unwrap().content, "Revised");
+        assert_eq!(db.log(None).unwrap().len(), 2);
+        db.sync().unwrap();
+        assert!(db.fsck(false).unwrap().is_ok());
+    }
+
+    #[test]
+    fn test_repeated_reads_hit_the_node_cache() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.indra");
+        let mut db = Database::create(&path).unwrap();
+        for i in 0..50 {
+            db.create_thought_with_id(format!("t{}", i), "Note")
+                .unwrap();
+        }
+        db.commit("Notes").unwrap();
+
+        let id = ThoughtId::new("t7");
+        db.get_thought(&id).unwrap().unwrap();
+        let before = db.node_cache_stats().unwrap();
+        for _ in 0..10 {
+            db.get_thought(&id).unwrap().unwrap();
+        }
+        let after = db.node_cache_stats().unwrap();
+        assert!(after.hits >= before.hits + 10);
+        assert_eq!(after.misses, before.misses);
+
+        // Nothing is cached when the cache is turned off
+        drop(db);
+        let options = StoreOptions::default().with_node_cache_size(0);
+        let db = Database::open_with_options(&path, options).unwrap();
+        db.get_thought(&id).unwrap().unwrap();
+        let stats = db.node_cache_stats().unwrap();
+        assert_eq!((stats.hits, stats.len), (0, 0));
+    }
+
+    #[test]
+    fn test_prove_thought_and_edge_at_a_commit() {
+        let mut db = Database::in_memory();
+        db.create_thought_with_id("a", "Alpha").unwrap();
+        db.create_thought_with_id("b", "Beta").unwrap();
+        db.relate("a", "b", EdgeType::RELATES_TO).unwrap();
+        let first = db.commit("First").unwrap();
+        db.update_thought(&"a".into(), "Alpha, revised").unwrap();
+        let second = db.commit("Second").unwrap();
+
+        let a = ThoughtId::new("a");
+        let old_tree = db.store.get_commit(&first).unwrap().tree;
+        let old_version = db.prove_thought(&a, first).unwrap().unwrap();
+        let value = old_version.value().unwrap();
+        assert_eq!(db.store.get_thought(&value).unwrap().content, "Alpha");
+        assert!(verify_proof(old_tree, b"t:a", value, &old_version));
+
+        // The old version is not part of the newer commit
+        let new_tree = db.store.get_commit(&second).unwrap().tree;
+        assert!(!verify_proof(new_tree, b"t:a", value, &old_version));
+        let new_version = db.prove_thought(&a, second).unwrap().unwrap();
+        assert_ne!(new_version.value(), Some(value));
+
+        let edge = db
+            .prove_edge("a", "b", EdgeType::RELATES_TO, second)
+            .unwrap()
+            .unwrap();
+        let key = b"e:a:b:relates_to";
+        assert!(verify_proof(new_tree, key, edge.value().unwrap(), &edge));
+        assert!(db.prove_thought(&"zz".into(), second).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_list_thoughts_pages_through_commits_and_working_tree() {
+        let mut db = Database::in_memory();
+        for i in 0..20 {
+            db.create_thought_with_id(format!("t{:02}", i), format!("Thought {}", i))
+                .unwrap();
+        }
+        db.commit("Twenty").unwrap();
+
+        // Uncommitted: one added between committed ids, one at the end,
+        // one changed and one removed
+        db.create_thought_with_id("t05a", "Inserted").unwrap();
+        db.create_thought_with_id("t99", "Last").unwrap();
+        db.update_thought(&"t03".into(), "Changed").unwrap();
+        db.delete_thought(&"t07".into()).unwrap();
+
+        let all = db.list_thoughts().unwrap();
+        let ids: Vec<_> = all.iter().map(|t| t.id.0.clone()).collect();
+        let mut sorted = ids.clone();
+        sorted.sort();
+        assert_eq!(ids, sorted);
+        assert_eq!(all.len(), 21);
+        assert!(!ids.contains(&"t07".to_string()));
+        assert!(all
+            .iter()
+            .any(|t| t.id.0 == "t03" && t.content == "Changed"));
+
+        for limit in [1, 3, 7, 21, 50] {
+            let mut paged = Vec::new();
+            let mut cursor = None;
+            loop {
+                let page = db.list_thoughts_page(cursor.as_deref(), limit).unwrap();
+                assert!(page.items.len() <= limit);
+                paged.extend(page.items.into_iter().map(|t| t.id.0));
+                match page.next {
+                    Some(next) => cursor = Some(next),
+                    None => break,
+                }
+            }
+            assert_eq!(paged, ids, "limit {}", limit);
+        }
+
+        // The committed snapshot pages on its own
+        let snapshot = db.snapshot().unwrap();
+        let page = snapshot.list_thoughts_page(None, 5).unwrap();
+        assert_eq!(page.items.len(), 5);
+        let rest = snapshot
+            .list_thoughts_page(page.next.as_deref(), 100)
+            .unwrap();
+        assert_eq!(rest.items.len(), 15);
+        assert!(rest.next.is_none());
+
+        assert!(matches!(
+            db.list_thoughts_page(Some("not hex"), 5),
+            Err(crate::Error::InvalidCursor(_))
+        ));
+        assert!(matches!(
+            db.list_thoughts_page(page.next.as_deref(), 0),
+            Err(crate::Error::InvalidLimit(_))
+        ));
+        assert!(matches!(
+            snapshot.list_thoughts_page(None, 0),
+            Err(crate::Error::InvalidLimit(_))
+        ));
+    }
+
+    #[test]
+    fn test_bulk_import_matches_committing_one_by_one() {
+        let mut db = Database::in_memory().with_embedder(MockEmbedder::default());
+        db.create_thought_with_id("a", "Alpha").unwrap();
+        db.create_thought_with_id("m", "Mu").unwrap();
+        db.relate("a", "m", EdgeType::RELATES_TO).unwrap();
+        let base = db.commit("Base").unwrap();
+        db.create_thought_with_id("pending", "Not committed yet")
+            .unwrap();
+
+        // New thoughts on both sides of the committed ones, one replaced
+        let thoughts: Vec<_> = ["0", "b", "m", "z"]
+            .iter()
+            .map(|id| Thought::with_id(*id, format!("Imported {}", id)))
+            .collect();
+        let edges = vec![
+            Edge::new("b", "z", EdgeType::RELATES_TO),
+            Edge::new("a", "m", EdgeType::SUPPORTS),
+        ];
+        let commit = db
+            .bulk_import(thoughts.clone(), edges.clone(), "Import")
+            .unwrap();
+
+        let base_tree = db.store.get_commit(&base).unwrap().tree;
+        let mut expected = MerkleTrie::from_root(db.store.as_ref(), base_tree).unwrap();
+        for thought in &thoughts {
+            let mut thought = thought.clone();
+            db.embed(&mut thought).unwrap();
+            let key = format!("t:{}", thought.id.0);
+            expected
+                .insert(key.as_bytes(), db.store.put_thought(&thought).unwrap())
+                .unwrap();
+        }
+        for edge in &edges {
+            let key = format!("e:{}", edge_key(edge));
+            expected
+                .insert(key.as_bytes(), db.store.put_edge(edge).unwrap())
+                .unwrap();
+        }
+        let imported = db.store.get_commit(&commit).unwrap();
+        assert_eq!(imported.tree, expected.root_hash());
+        assert_eq!(db.log(Some(1)).unwrap()[0].0, commit);
+
+        let m = db.get_thought(&"m".into()).unwrap().unwrap();
+        assert_eq!(m.content, "Imported m");
+        assert!(m.embedding.is_some());
+        assert_eq!(db.list_thoughts().unwrap().len(), 6);
+        assert!(db.is_dirty());
+
+        // A batch small next to the tree is inserted into it instead
+        let mut single = Thought::with_id("y", "Imported y");
+        let commit = db
+            .bulk_import(vec![single.clone()], vec![], "One more")
+            .unwrap();
+        let mut expected = MerkleTrie::from_root(db.store.as_ref(), imported.tree).unwrap();
+        db.embed(&mut single).unwrap();
+        expected
+            .insert(b"t:y", db.store.put_thought(&single).unwrap())
+            .unwrap();
+        let imported = db.store.get_commit(&commit).unwrap();
+        assert_eq!(imported.tree, expected.root_hash());
+        assert_eq!(db.list_thoughts().unwrap().len(), 7);
+
+        assert!(matches!(
+            db.bulk_import(vec![], vec![], "Nothing"),
+            Err(crate::Error::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_gc_keeps_history_and_drops_garbage() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.indra");
+
+        let id;
+        {
+            let mut db = Database::create(&path).unwrap();
+            id = db.create_thought_with_id("note", "v1").unwrap();
+            db.commit("v1").unwrap();
+
+            // A deleted branch leaves its commits behind as garbage
+            db.create_branch("scratch").unwrap();
+            db.checkout("scratch").unwrap();
+            db.create_thought("Scratch work").unwrap();
+            db.commit("Scratch").unwrap();
+            db.checkout("main").unwrap();
+            BranchManager::new(db.store.as_ref())
+                .delete_branch("scratch")
+                .unwrap();
+
+            db.update_thought(&id, "v2").unwrap();
+            db.commit("v2").unwrap();
+            db.sync().unwrap();
+
+            let stats = db.gc().unwrap();
+            assert!(stats.objects_removed() > 0);
+            assert!(stats.bytes_reclaimed() > 0);
+            assert_eq!(stats.objects_after, db.store.object_count());
+        }
+
+        let db = Database::open(&path).unwrap();
+        assert_eq!(db.get_thought(&id).unwrap().unwrap().content, "v2");
+        assert_eq!(db.log(None).unwrap().len(), 2);
+        assert_eq!(db.list_thoughts().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_attachments_survive_commit_and_gc() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.indra");
+        let content: Vec<u8> = (0..crate::store::ATTACHMENT_CHUNK_SIZE + 100)
+            .map(|i| (i % 253) as u8)
+            .collect();
+
+        {
+            let mut db = Database::create(&path).unwrap();
+            let id = db.create_thought_with_id("note", "See attached").unwrap();
+            db.commit("Note").unwrap();
+
+            let attachment = db
+                .attach(&id, "data.bin", "application/octet-stream", &content[..])
+                .unwrap();
+            assert_eq!(attachment.size, content.len() as u64);
+            assert!(db.is_dirty());
+            // The chunks are stored before any commit points at them
+            db.gc().unwrap();
+            db.commit("Attach").unwrap();
+            db.sync().unwrap();
+
+            db.gc().unwrap();
+            assert!(db.fsck(false).unwrap().is_ok());
+        }
+
+        let db = Database::open(&path).unwrap();
+        let thought = db.get_thought(&ThoughtId::new("note")).unwrap().unwrap();
+        let attachment = thought.attachment("data.bin").unwrap();
+        assert_eq!(attachment.mime_type, "application/octet-stream");
+
+        let mut read = Vec::new();
+        db.read_attachment(attachment)
+            .unwrap()
+            .read_to_end(&mut read)
+            .unwrap();
+        assert_eq!(read, content);
+    }
 }
@@ -1,5 +1,6 @@
 //! Error types for indra_db
 
+use crate::model::Hash;
 use thiserror::Error;
 
 /// Result type alias for indra_db operations
@@ -26,6 +27,13 @@ pub enum Error {
     #[error("Corruption detected: {0}")]
     Corruption(String),
 
+    #[error("Corruption detected: Object {hash} at offset {offset} {reason}")]
+    CorruptObject {
+        hash: Hash,
+        offset: u64,
+        reason: String,
+    },
+
     #[error("Invalid database file: {0}")]
     InvalidFile(String),
 
@@ -35,6 +43,9 @@ pub enum Error {
     #[error("Ref not found: {0}")]
     RefNotFound(String),
 
+    #[error("Invalid ref name {0}")]
+    InvalidRefName(String),
+
     #[error("Merge conflict: {0}")]
     MergeConflict(String),
 
@@ -44,9 +55,24 @@ pub enum Error {
     #[error("Database is locked")]
     Locked,
 
+    #[error("Database is opened read-only")]
+    ReadOnly,
+
     #[error("Version mismatch: expected {expected}, found {found}")]
     VersionMismatch { expected: u32, found: u32 },
 
+    #[error("Database format version {found} must be migrated to {current}; run `indra migrate`")]
+    MigrationRequired { found: u32, current: u32 },
+
+    #[error("Database is encrypted; a key is required to open it")]
+    KeyRequired,
+
+    #[error("Wrong encryption key")]
+    WrongKey,
+
+    #[error("Encryption error: {0}")]
+    Encryption(String),
+
     #[error("Remote error: {0}")]
     Remote(String),
 
@@ -55,4 +81,13 @@ pub enum Error {
 
     #[error("Config error: {0}")]
     Config(String),
+
+    #[error("Invalid cursor: {0}")]
+    InvalidCursor(String),
+
+    #[error("Invalid page limit: {0}")]
+    InvalidLimit(String),
+
+    #[error("Keys out of order: {0}")]
+    UnsortedKeys(String),
 }
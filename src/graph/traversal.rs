@@ -1,10 +1,19 @@
 //! Graph traversal operations
 
 use crate::model::{Edge, EdgeType, Hash, Thought, ThoughtId};
-use crate::store::ObjectStore;
-use crate::trie::MerkleTrie;
+use crate::store::Storage;
+use crate::trie::{cursor_token, page_start, MerkleTrie, Page, Range};
 use crate::Result;
 use std::collections::{HashMap, HashSet, VecDeque};
+use std::ops::Bound;
+use std::sync::OnceLock;
+
+/// Keys of thoughts in the trie start with this
+const THOUGHT_PREFIX: &[u8] = b"t:";
+
+fn thought_key(id: &ThoughtId) -> Vec<u8> {
+    format!("t:{}", id.0).into_bytes()
+}
 
 /// Direction for traversing edges
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -20,84 +29,117 @@ pub enum TraversalDirection {
 /// A view into the graph at a specific commit
 ///
 /// This provides read-only access to the graph state at a point in time.
+/// Thoughts are looked up in the trie as needed; the edge indices are built
+/// on the first neighbor query.
 pub struct GraphView<'a> {
-    store: &'a ObjectStore,
+    store: &'a dyn Storage,
     trie: MerkleTrie<'a>,
-    /// Cache: ThoughtId → content hash
-    thought_index: HashMap<ThoughtId, Hash>,
-    /// Cache: source ThoughtId → edge hashes
-    edges_from: HashMap<ThoughtId, Vec<Hash>>,
-    /// Cache: target ThoughtId → edge hashes  
-    edges_to: HashMap<ThoughtId, Vec<Hash>>,
+    edges: OnceLock<EdgeIndex>,
+}
+
+/// Edge hashes by the thoughts they connect
+#[derive(Default)]
+struct EdgeIndex {
+    /// source ThoughtId → edge hashes
+    from: HashMap<ThoughtId, Vec<Hash>>,
+    /// target ThoughtId → edge hashes
+    to: HashMap<ThoughtId, Vec<Hash>>,
 }
 
 impl<'a> GraphView<'a> {
     /// Create a view at the given tree root
-    pub fn new(store: &'a ObjectStore, root_hash: Hash) -> Result<Self> {
-        let trie = MerkleTrie::from_root(store, root_hash)?;
-
-        // Build indices from trie
-        let mut thought_index = HashMap::new();
-        let mut edges_from: HashMap<ThoughtId, Vec<Hash>> = HashMap::new();
-        let mut edges_to: HashMap<ThoughtId, Vec<Hash>> = HashMap::new();
-
-        // Load all thoughts
-        for (key, hash) in trie.list_prefix(b"t:")? {
-            let id_bytes = &key[2..]; // Skip "t:" prefix
-            if let Ok(id_str) = std::str::from_utf8(id_bytes) {
-                thought_index.insert(ThoughtId::new(id_str), hash);
-            }
-        }
-
-        // Load all edges and build indices
-        for (_, hash) in trie.list_prefix(b"e:")? {
-            let edge = store.get_edge(&hash)?;
-            edges_from
-                .entry(edge.source.clone())
-                .or_default()
-                .push(hash);
-            edges_to.entry(edge.target.clone()).or_default().push(hash);
-        }
-
+    pub fn new(store: &'a dyn Storage, root_hash: Hash) -> Result<Self> {
         Ok(GraphView {
             store,
-            trie,
-            thought_index,
-            edges_from,
-            edges_to,
+            trie: MerkleTrie::from_root(store, root_hash)?,
+            edges: OnceLock::new(),
         })
     }
 
     /// Create an empty view
-    pub fn empty(store: &'a ObjectStore) -> Result<Self> {
+    pub fn empty(store: &'a dyn Storage) -> Result<Self> {
         Self::new(store, Hash::ZERO)
     }
 
     /// Get a thought by ID
     pub fn get_thought(&self, id: &ThoughtId) -> Result<Option<Thought>> {
-        if let Some(hash) = self.thought_index.get(id) {
-            Ok(Some(self.store.get_thought(hash)?))
-        } else {
-            Ok(None)
+        match self.trie.get(&thought_key(id))? {
+            Some(hash) => Ok(Some(self.store.get_thought(&hash)?)),
+            None => Ok(None),
         }
     }
 
     /// Check if a thought exists
-    pub fn has_thought(&self, id: &ThoughtId) -> bool {
-        self.thought_index.contains_key(id)
+    pub fn has_thought(&self, id: &ThoughtId) -> Result<bool> {
+        Ok(self.trie.get(&thought_key(id))?.is_some())
+    }
+
+    /// Iterate over all thoughts in ID order, loading each as it is reached
+    pub fn thoughts(&self) -> impl Iterator<Item = Result<Thought>> + '_ {
+        self.thought_keys(Bound::Unbounded)
+            .map(|entry| entry.and_then(|(_, hash)| self.store.get_thought(&hash)))
     }
 
-    /// Get all thoughts
+    /// Get all thoughts, in ID order
     pub fn all_thoughts(&self) -> Result<Vec<Thought>> {
-        self.thought_index
-            .values()
-            .map(|hash| self.store.get_thought(hash))
-            .collect()
+        self.thoughts().collect()
+    }
+
+    /// Up to `limit` thoughts in ID order, starting after the `cursor`
+    /// returned with the previous page
+    pub fn thoughts_page(&self, cursor: Option<&str>, limit: usize) -> Result<Page<Thought>> {
+        let start = page_start(cursor, limit)?;
+        let mut keys = self.thought_keys(start);
+        let mut items = Vec::new();
+        let mut last = None;
+        for entry in keys.by_ref().take(limit) {
+            let (key, hash) = entry?;
+            items.push(self.store.get_thought(&hash)?);
+            last = Some(key);
+        }
+        let next = match keys.next() {
+            Some(_) => last.map(|key| cursor_token(&key)),
+            None => None,
+        };
+        Ok(Page { items, next })
     }
 
     /// Count thoughts
-    pub fn thought_count(&self) -> usize {
-        self.thought_index.len()
+    pub fn thought_count(&self) -> Result<usize> {
+        self.thought_keys(Bound::Unbounded)
+            .try_fold(0, |count, entry| entry.map(|_| count + 1))
+    }
+
+    /// Thought keys and hashes from `start`, which defaults to the first
+    pub(crate) fn thought_keys(&self, start: Bound<Vec<u8>>) -> Range<'_> {
+        let start = match &start {
+            Bound::Unbounded => Bound::Included(THOUGHT_PREFIX),
+            Bound::Included(key) => Bound::Included(key.as_slice().max(THOUGHT_PREFIX)),
+            Bound::Excluded(key) if key.as_slice() < THOUGHT_PREFIX => {
+                Bound::Included(THOUGHT_PREFIX)
+            }
+            Bound::Excluded(key) => Bound::Excluded(key.as_slice()),
+        };
+        // ';' is the byte after ':', so this ends with the last thought key
+        self.trie.range(start, Bound::Excluded(b"t;"))
+    }
+
+    fn edge_index(&self) -> Result<&EdgeIndex> {
+        if let Some(index) = self.edges.get() {
+            return Ok(index);
+        }
+        let mut index = EdgeIndex::default();
+        for entry in self.trie.scan_prefix(b"e:") {
+            let (_, hash) = entry?;
+            let edge = self.store.get_edge(&hash)?;
+            index
+                .from
+                .entry(edge.source.clone())
+                .or_default()
+                .push(hash);
+            index.to.entry(edge.target).or_default().push(hash);
+        }
+        Ok(self.edges.get_or_init(|| index))
     }
 
     /// Get neighbors of a thought
@@ -109,12 +151,13 @@ impl<'a> GraphView<'a> {
     ) -> Result<Vec<(Thought, Edge)>> {
         let mut results = Vec::new();
 
+        let index = self.edge_index()?;
         let edge_hashes: Vec<Hash> = match direction {
-            TraversalDirection::Outgoing => self.edges_from.get(id).cloned().unwrap_or_default(),
-            TraversalDirection::Incoming => self.edges_to.get(id).cloned().unwrap_or_default(),
+            TraversalDirection::Outgoing => index.from.get(id).cloned().unwrap_or_default(),
+            TraversalDirection::Incoming => index.to.get(id).cloned().unwrap_or_default(),
             TraversalDirection::Both => {
-                let mut edges = self.edges_from.get(id).cloned().unwrap_or_default();
-                edges.extend(self.edges_to.get(id).cloned().unwrap_or_default());
+                let mut edges = index.from.get(id).cloned().unwrap_or_default();
+                edges.extend(index.to.get(id).cloned().unwrap_or_default());
                 edges
             }
         };
@@ -154,7 +197,7 @@ impl<'a> GraphView<'a> {
     pub fn edges_between(&self, source: &ThoughtId, target: &ThoughtId) -> Result<Vec<Edge>> {
         let mut results = Vec::new();
 
-        if let Some(edge_hashes) = self.edges_from.get(source) {
+        if let Some(edge_hashes) = self.edge_index()?.from.get(source) {
             for hash in edge_hashes {
                 let edge = self.store.get_edge(hash)?;
                 if &edge.target == target {
@@ -258,6 +301,7 @@ impl<'a> GraphView<'a> {
 mod tests {
     use super::*;
     use crate::model::EdgeType;
+    use crate::store::ObjectStore;
     use tempfile::tempdir;
 
     fn setup() -> (tempfile::TempDir, ObjectStore) {
@@ -272,7 +316,7 @@ mod tests {
         let (_dir, store) = setup();
         let view = GraphView::empty(&store).unwrap();
 
-        assert_eq!(view.thought_count(), 0);
+        assert_eq!(view.thought_count().unwrap(), 0);
         assert!(view
             .get_thought(&ThoughtId::new("nonexistent"))
             .unwrap()
@@ -299,9 +343,9 @@ mod tests {
         // Create view
         let view = GraphView::new(&store, root).unwrap();
 
-        assert_eq!(view.thought_count(), 2);
-        assert!(view.has_thought(&ThoughtId::new("t1")));
-        assert!(view.has_thought(&ThoughtId::new("t2")));
+        assert_eq!(view.thought_count().unwrap(), 2);
+        assert!(view.has_thought(&ThoughtId::new("t1")).unwrap());
+        assert!(view.has_thought(&ThoughtId::new("t2")).unwrap());
 
         let retrieved = view.get_thought(&ThoughtId::new("t1")).unwrap().unwrap();
         assert_eq!(retrieved.content, "First thought");
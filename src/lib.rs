@@ -35,12 +35,13 @@ pub mod viz;
 
 mod database;
 mod error;
+mod snapshot;
 
 pub use database::Database;
 pub use embedding::{Embedder, MockEmbedder};
 pub use error::{Error, Result};
 pub use graph::TraversalDirection;
-pub use model::{Commit, Edge, EdgeType, Hash, Thought, ThoughtId};
+pub use model::{AttachmentRef, Commit, Edge, EdgeType, Hash, Thought, ThoughtId};
 #[cfg(feature = "sync")]
 pub use remote::refresh_access_token;
 pub use remote::{
@@ -48,11 +49,12 @@ pub use remote::{
     SyncState, UserInfo, DEFAULT_API_URL,
 };
 pub use search::SearchResult;
-pub use store::ObjectStore;
+pub use snapshot::Snapshot;
+pub use store::{EncryptionKey, MemoryStore, ObjectStore, Storage, StoreOptions};
 pub use viz::{VizCommit, VizExport, VizMeta, VizThought};
 
 /// Database version for format compatibility
-pub const VERSION: u32 = 1;
+pub const VERSION: u32 = 2;
 
 /// Magic bytes for file identification
 pub const MAGIC: &[u8; 8] = b"INDRA_DB";
@@ -4,8 +4,9 @@
 //! Designed to be wrapped by MCP servers in other languages (e.g., TypeScript/Bun).
 
 use clap::{Parser, Subcommand};
-use indra_db::{Database, EdgeType, TraversalDirection};
+use indra_db::{Database, EdgeType, EncryptionKey, ObjectStore, StoreOptions, TraversalDirection};
 use std::path::PathBuf;
+use std::time::Duration;
 
 #[cfg(feature = "hf-embeddings")]
 use indra_db::embedding::HFEmbedder;
@@ -44,6 +45,15 @@ struct Cli {
     #[arg(long)]
     dimension: Option<usize>,
 
+    /// Seconds to wait for other indra processes to release the database
+    #[arg(long, default_value_t = 10)]
+    lock_timeout: u64,
+
+    /// File holding the key of an encrypted database (otherwise the
+    /// passphrase is read from INDRA_PASSPHRASE)
+    #[arg(long)]
+    key_file: Option<PathBuf>,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -57,7 +67,11 @@ enum OutputFormat {
 #[derive(Subcommand)]
 enum Commands {
     /// Initialize a new database
-    Init,
+    Init {
+        /// Encrypt the database with the key from --key-file or INDRA_PASSPHRASE
+        #[arg(long)]
+        encrypt: bool,
+    },
 
     // === Thought Commands ===
     /// Create a new thought
@@ -89,11 +103,41 @@ enum Commands {
         id: String,
     },
 
-    /// List all thoughts
+    /// List all thoughts in ID order
     List {
         /// Maximum number of thoughts to return
         #[arg(short, long)]
         limit: Option<usize>,
+
+        /// Continue from the next_cursor of a previous page
+        #[arg(long)]
+        after: Option<String>,
+    },
+
+    // === Attachment Commands ===
+    /// Attach a file to a thought
+    Attach {
+        /// The thought ID
+        id: String,
+        /// File to attach
+        path: PathBuf,
+        /// Name to attach it under (defaults to the file name)
+        #[arg(short, long)]
+        name: Option<String>,
+        /// MIME type (guessed from the file extension if not given)
+        #[arg(short, long)]
+        mime: Option<String>,
+    },
+
+    /// Write an attachment's content to stdout or a file
+    CatAttachment {
+        /// The thought ID
+        id: String,
+        /// The attachment name
+        name: String,
+        /// Write to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
     },
 
     // === Relationship Commands ===
@@ -167,12 +211,21 @@ enum Commands {
         name: String,
     },
 
-    /// Switch to a branch
+    /// Switch to a branch, or detach HEAD at a commit
     Checkout {
-        /// Branch name
+        /// Branch name or commit hash
         name: String,
     },
 
+    /// Show the history of ref movements, newest first
+    Reflog {
+        /// Only show movements of this branch (or HEAD)
+        name: Option<String>,
+        /// Maximum number of entries to show
+        #[arg(short, long)]
+        limit: Option<usize>,
+    },
+
     /// List all branches
     Branches,
 
@@ -186,9 +239,75 @@ enum Commands {
         to: String,
     },
 
+    /// Prove that a thought version, or with --to an edge, is part of a
+    /// commit
+    ///
+    /// Prints the nodes from the commit's tree root down to the entry.
+    Prove {
+        /// The thought ID, or the edge's source with --to
+        id: String,
+        /// Commit hash, branch name, "HEAD" or "HEAD~N"
+        #[arg(default_value = "HEAD")]
+        commit: String,
+        /// Prove the edge from ID to this thought instead
+        #[arg(long)]
+        to: Option<String>,
+        /// Relationship type of the edge
+        #[arg(short = 't', long, default_value = "relates_to")]
+        edge_type: String,
+    },
+
+    /// Check a proof printed by `indra prove`, without the database
+    Verify {
+        /// File holding the proof's JSON output
+        file: PathBuf,
+        /// Tree hash the proof must start from, instead of the one it names
+        #[arg(long)]
+        tree: Option<String>,
+    },
+
     /// Show database status
     Status,
 
+    /// Remove unreachable objects and compact the database file
+    Gc,
+
+    /// Store similar objects as deltas to shrink the database file
+    Repack,
+
+    /// Verify the integrity of objects, trees and refs
+    Fsck {
+        /// Drop index entries that are unreadable or fail to re-hash
+        #[arg(long)]
+        repair: bool,
+    },
+
+    /// Show object, branch, embedding and commit statistics
+    Stats,
+
+    /// Write a verified point-in-time copy of the database
+    ///
+    /// The copy is encrypted if the database is, or with --encrypt. Holds a
+    /// shared lock on the database through the copy and its fsck, so other
+    /// indra commands that write wait for it and fail after --lock-timeout.
+    Backup {
+        /// Path of the new database file
+        path: PathBuf,
+        /// Only copy this branch (repeatable)
+        #[arg(short, long = "branch")]
+        branches: Vec<String>,
+        /// Encrypt the copy with the key from --key-file or INDRA_PASSPHRASE
+        #[arg(long)]
+        encrypt: bool,
+    },
+
+    /// Upgrade the database file to the current format version
+    Migrate,
+
+    /// Move history between databases as a single file
+    #[command(subcommand)]
+    Bundle(BundleCommands),
+
     // === Remote Commands ===
     /// Manage remote repositories
     #[command(subcommand)]
@@ -250,6 +369,31 @@ enum Commands {
     },
 }
 
+#[derive(Subcommand)]
+enum BundleCommands {
+    /// Write the history of some branches to a bundle file
+    Create {
+        /// Bundle file to write
+        out: PathBuf,
+        /// Branches to include
+        #[arg(required = true)]
+        refs: Vec<String>,
+        /// Leave out what this commit or branch reaches; the receiving
+        /// database must already have it (repeatable)
+        #[arg(long)]
+        since: Vec<String>,
+    },
+
+    /// Import a bundle file and update its branches
+    Unbundle {
+        /// Bundle file to read
+        file: PathBuf,
+        /// Move branches even if they have diverged from the bundle
+        #[arg(long)]
+        force: bool,
+    },
+}
+
 #[derive(Subcommand)]
 enum RemoteCommands {
     /// Add a new remote
@@ -287,15 +431,32 @@ enum RemoteCommands {
 fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
 
+    // Mutating commands lock the database exclusively, others share it
+    let mut writer =
+        StoreOptions::default().with_lock_timeout(Duration::from_secs(cli.lock_timeout));
+    if let Some(path) = &cli.key_file {
+        writer = writer.with_key(EncryptionKey::File(path.clone()));
+    } else if let Ok(passphrase) = std::env::var("INDRA_PASSPHRASE") {
+        writer = writer.with_key(EncryptionKey::Passphrase(passphrase));
+    }
+    let reader = writer.clone().with_read_only(true);
+
     match cli.command {
-        Commands::Init => {
-            let db = Database::create(&cli.database)?;
+        Commands::Init { encrypt } => {
+            if encrypt && writer.key.is_none() {
+                anyhow::bail!("--encrypt needs a key: pass --key-file or set INDRA_PASSPHRASE");
+            }
+            if !encrypt {
+                writer.key = None;
+            }
+            let db = Database::create_with_options(&cli.database, writer)?;
             db.sync()?;
             output(
                 &cli.format,
                 &serde_json::json!({
                     "status": "ok",
-                    "message": format!("Created database at {}", cli.database.display())
+                    "message": format!("Created database at {}", cli.database.display()),
+                    "encrypted": encrypt
                 }),
             );
         }
@@ -303,6 +464,7 @@ fn main() -> anyhow::Result<()> {
         Commands::Create { content, id } => {
             let mut db = open_db(
                 &cli.database,
+                &writer,
                 &cli.embedder,
                 cli.model.clone(),
                 cli.dimension,
@@ -329,6 +491,7 @@ fn main() -> anyhow::Result<()> {
         Commands::Get { id } => {
             let db = open_db(
                 &cli.database,
+                &reader,
                 &cli.embedder,
                 cli.model.clone(),
                 cli.dimension,
@@ -348,6 +511,7 @@ fn main() -> anyhow::Result<()> {
                             "created_at": thought.created_at,
                             "modified_at": thought.modified_at,
                             "attrs": attrs,
+                            "attachments": thought.attachments.iter().map(attachment_json).collect::<Vec<_>>(),
                             "has_embedding": thought.embedding.is_some()
                         }),
                     );
@@ -368,6 +532,7 @@ fn main() -> anyhow::Result<()> {
         Commands::Update { id, content } => {
             let mut db = open_db(
                 &cli.database,
+                &writer,
                 &cli.embedder,
                 cli.model.clone(),
                 cli.dimension,
@@ -389,6 +554,7 @@ fn main() -> anyhow::Result<()> {
         Commands::Delete { id } => {
             let mut db = open_db(
                 &cli.database,
+                &writer,
                 &cli.embedder,
                 cli.model.clone(),
                 cli.dimension,
@@ -407,18 +573,17 @@ fn main() -> anyhow::Result<()> {
             );
         }
 
-        Commands::List { limit } => {
+        Commands::List { limit, after } => {
             let db = open_db(
                 &cli.database,
+                &reader,
                 &cli.embedder,
                 cli.model.clone(),
                 cli.dimension,
             )?;
-            let mut thoughts = db.list_thoughts()?;
-            if let Some(limit) = limit {
-                thoughts.truncate(limit);
-            }
-            let items: Vec<_> = thoughts
+            let page = db.list_thoughts_page(after.as_deref(), limit.unwrap_or(usize::MAX))?;
+            let items: Vec<_> = page
+                .items
                 .iter()
                 .map(|t| {
                     serde_json::json!({
@@ -433,11 +598,90 @@ fn main() -> anyhow::Result<()> {
                 &cli.format,
                 &serde_json::json!({
                     "count": items.len(),
-                    "thoughts": items
+                    "thoughts": items,
+                    "next_cursor": page.next
                 }),
             );
         }
 
+        Commands::Attach {
+            id,
+            path,
+            name,
+            mime,
+        } => {
+            let mut db = open_db(
+                &cli.database,
+                &writer,
+                &cli.embedder,
+                cli.model.clone(),
+                cli.dimension,
+            )?;
+            let name = match name {
+                Some(name) => name,
+                None => path
+                    .file_name()
+                    .ok_or_else(|| anyhow::anyhow!("No file name in {}", path.display()))?
+                    .to_string_lossy()
+                    .into_owned(),
+            };
+            let mime = mime.unwrap_or_else(|| guess_mime_type(&path).to_string());
+            let file = std::fs::File::open(&path)?;
+            let thought_id = indra_db::ThoughtId::new(&id);
+            let attachment = db.attach(&thought_id, name, mime, std::io::BufReader::new(file))?;
+            if !cli.no_auto_commit {
+                db.commit_with_author("Auto-commit: attach file", "indra-cli")?;
+            }
+            db.sync()?;
+            output(
+                &cli.format,
+                &serde_json::json!({
+                    "status": "ok",
+                    "id": id,
+                    "attachment": attachment_json(&attachment)
+                }),
+            );
+        }
+
+        Commands::CatAttachment {
+            id,
+            name,
+            output: output_path,
+        } => {
+            let db = open_db(
+                &cli.database,
+                &reader,
+                &cli.embedder,
+                cli.model.clone(),
+                cli.dimension,
+            )?;
+            let thought_id = indra_db::ThoughtId::new(&id);
+            let thought = db
+                .get_thought(&thought_id)?
+                .ok_or_else(|| anyhow::anyhow!("Thought not found: {}", id))?;
+            let attachment = thought
+                .attachment(&name)
+                .ok_or_else(|| anyhow::anyhow!("No attachment {} on {}", name, id))?;
+            let mut content = db.read_attachment(attachment)?;
+            match output_path {
+                Some(path) => {
+                    let mut file = std::fs::File::create(&path)?;
+                    let written = std::io::copy(&mut content, &mut file)?;
+                    output(
+                        &cli.format,
+                        &serde_json::json!({
+                            "status": "ok",
+                            "path": path.display().to_string(),
+                            "size": written
+                        }),
+                    );
+                }
+                None => {
+                    std::io::copy(&mut content, &mut std::io::stdout().lock())?;
+                }
+            }
+        }
+
         Commands::Relate {
             source,
             target,
@@ -446,6 +690,7 @@ fn main() -> anyhow::Result<()> {
         } => {
             let mut db = open_db(
                 &cli.database,
+                &writer,
                 &cli.embedder,
                 cli.model.clone(),
                 cli.dimension,
@@ -476,6 +721,7 @@ fn main() -> anyhow::Result<()> {
         } => {
             let mut db = open_db(
                 &cli.database,
+                &writer,
                 &cli.embedder,
                 cli.model.clone(),
                 cli.dimension,
@@ -498,6 +744,7 @@ fn main() -> anyhow::Result<()> {
         Commands::Neighbors { id, direction } => {
             let db = open_db(
                 &cli.database,
+                &reader,
                 &cli.embedder,
                 cli.model.clone(),
                 cli.dimension,
@@ -542,6 +789,7 @@ fn main() -> anyhow::Result<()> {
         } => {
             let db = open_db(
                 &cli.database,
+                &reader,
                 &cli.embedder,
                 cli.model.clone(),
                 cli.dimension,
@@ -574,6 +822,7 @@ fn main() -> anyhow::Result<()> {
         Commands::Commit { message, author } => {
             let mut db = open_db(
                 &cli.database,
+                &writer,
                 &cli.embedder,
                 cli.model.clone(),
                 cli.dimension,
@@ -592,6 +841,7 @@ fn main() -> anyhow::Result<()> {
         Commands::Log { limit } => {
             let db = open_db(
                 &cli.database,
+                &reader,
                 &cli.embedder,
                 cli.model.clone(),
                 cli.dimension,
@@ -621,6 +871,7 @@ fn main() -> anyhow::Result<()> {
         Commands::Branch { name } => {
             let db = open_db(
                 &cli.database,
+                &writer,
                 &cli.embedder,
                 cli.model.clone(),
                 cli.dimension,
@@ -638,6 +889,7 @@ fn main() -> anyhow::Result<()> {
         Commands::Checkout { name } => {
             let mut db = open_db(
                 &cli.database,
+                &writer,
                 &cli.embedder,
                 cli.model.clone(),
                 cli.dimension,
@@ -647,7 +899,38 @@ fn main() -> anyhow::Result<()> {
                 &cli.format,
                 &serde_json::json!({
                     "status": "ok",
-                    "branch": name
+                    "branch": db.current_branch(),
+                    "head": db.head().to_string()
+                }),
+            );
+        }
+
+        Commands::Reflog { name, limit } => {
+            let db = open_db(
+                &cli.database,
+                &reader,
+                &cli.embedder,
+                cli.model.clone(),
+                cli.dimension,
+            )?;
+            let entries = db.reflog(name.as_deref(), limit);
+            let items: Vec<_> = entries
+                .iter()
+                .map(|entry| {
+                    serde_json::json!({
+                        "name": entry.name,
+                        "old": if entry.old.is_zero() { "".to_string() } else { entry.old.to_hex() },
+                        "new": if entry.new.is_zero() { "".to_string() } else { entry.new.to_hex() },
+                        "timestamp": entry.timestamp,
+                        "reason": entry.reason
+                    })
+                })
+                .collect();
+            output(
+                &cli.format,
+                &serde_json::json!({
+                    "count": items.len(),
+                    "entries": items
                 }),
             );
         }
@@ -655,6 +938,7 @@ fn main() -> anyhow::Result<()> {
         Commands::Branches => {
             let db = open_db(
                 &cli.database,
+                &reader,
                 &cli.embedder,
                 cli.model.clone(),
                 cli.dimension,
@@ -667,7 +951,7 @@ fn main() -> anyhow::Result<()> {
                     serde_json::json!({
                         "name": name,
                         "hash": if hash.is_zero() { "".to_string() } else { hash.to_hex() },
-                        "current": name == &current
+                        "current": current.as_deref() == Some(name.as_str())
                     })
                 })
                 .collect();
@@ -683,14 +967,13 @@ fn main() -> anyhow::Result<()> {
         Commands::Diff { from, to } => {
             let db = open_db(
                 &cli.database,
+                &reader,
                 &cli.embedder,
                 cli.model.clone(),
                 cli.dimension,
             )?;
-            let log = db.log(None)?;
-
-            let from_hash = resolve_ref(&from, &log)?;
-            let to_hash = resolve_ref(&to, &log)?;
+            let from_hash = resolve_ref(&db, &from)?;
+            let to_hash = resolve_ref(&db, &to)?;
 
             let diff = db.diff(from_hash, to_hash)?;
             let entries: Vec<_> = diff
@@ -738,9 +1021,107 @@ fn main() -> anyhow::Result<()> {
             );
         }
 
+        Commands::Prove {
+            id,
+            commit,
+            to,
+            edge_type,
+        } => {
+            let db = open_db(
+                &cli.database,
+                &reader,
+                &cli.embedder,
+                cli.model.clone(),
+                cli.dimension,
+            )?;
+            let commit_hash = resolve_ref(&db, &commit)?;
+
+            let (key, proof) = match &to {
+                Some(target) => (
+                    format!("e:{}:{}:{}", id, target, edge_type),
+                    db.prove_edge(
+                        id.as_str(),
+                        target.as_str(),
+                        edge_type.as_str(),
+                        commit_hash,
+                    )?,
+                ),
+                None => (
+                    format!("t:{}", id),
+                    db.prove_thought(&indra_db::ThoughtId::new(&id), commit_hash)?,
+                ),
+            };
+            match proof {
+                Some(proof) => {
+                    let nodes: Vec<_> = proof.encode()?.iter().map(hex::encode).collect();
+                    output(
+                        &cli.format,
+                        &serde_json::json!({
+                            "id": id,
+                            "key": key,
+                            "commit": commit_hash.to_hex(),
+                            "tree": proof.root().map(|h| h.to_hex()),
+                            "value": proof.value().map(|h| h.to_hex()),
+                            "nodes": nodes
+                        }),
+                    );
+                }
+                None => {
+                    output(
+                        &cli.format,
+                        &serde_json::json!({
+                            "status": "error",
+                            "message": format!("{} is not in commit {}", key, commit_hash.to_hex())
+                        }),
+                    );
+                    std::process::exit(1);
+                }
+            }
+        }
+
+        Commands::Verify { file, tree } => {
+            let proof: serde_json::Value = serde_json::from_slice(&std::fs::read(&file)?)?;
+            let field = |name: &str| {
+                proof[name]
+                    .as_str()
+                    .ok_or_else(|| anyhow::anyhow!("Proof has no {}", name))
+            };
+            let key = field("key")?;
+            let tree = indra_db::Hash::from_hex(tree.as_deref().unwrap_or(field("tree")?))?;
+            let value = indra_db::Hash::from_hex(field("value")?)?;
+            let nodes = proof["nodes"]
+                .as_array()
+                .ok_or_else(|| anyhow::anyhow!("Proof has no nodes"))?
+                .iter()
+                .map(|node| {
+                    let node = node
+                        .as_str()
+                        .ok_or_else(|| anyhow::anyhow!("Proof node is not a string"))?;
+                    Ok(hex::decode(node)?)
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+
+            let valid = indra_db::trie::Proof::decode(&nodes).is_ok_and(|nodes| {
+                indra_db::trie::verify_proof(tree, key.as_bytes(), value, &nodes)
+            });
+            output(
+                &cli.format,
+                &serde_json::json!({
+                    "status": if valid { "ok" } else { "invalid" },
+                    "key": key,
+                    "tree": tree.to_hex(),
+                    "value": value.to_hex()
+                }),
+            );
+            if !valid {
+                std::process::exit(1);
+            }
+        }
+
         Commands::Status => {
             let db = open_db(
                 &cli.database,
+                &reader,
                 &cli.embedder,
                 cli.model.clone(),
                 cli.dimension,
@@ -764,12 +1145,140 @@ fn main() -> anyhow::Result<()> {
                 &serde_json::json!({
                     "database": cli.database.display().to_string(),
                     "branch": db.current_branch(),
+                    "head": db.head().to_string(),
                     "dirty": db.is_dirty(),
                     "remotes": remotes
                 }),
             );
         }
 
+        Commands::Gc => {
+            let db = Database::open_with_options(&cli.database, writer)?;
+            let stats = db.gc()?;
+            output(
+                &cli.format,
+                &serde_json::json!({
+                    "status": "ok",
+                    "objects_before": stats.objects_before,
+                    "objects_after": stats.objects_after,
+                    "objects_removed": stats.objects_removed(),
+                    "bytes_before": stats.bytes_before,
+                    "bytes_after": stats.bytes_after,
+                    "bytes_reclaimed": stats.bytes_reclaimed()
+                }),
+            );
+        }
+
+        Commands::Repack => {
+            let db = Database::open_with_options(&cli.database, writer)?;
+            let stats = db.repack()?;
+            output(
+                &cli.format,
+                &serde_json::json!({
+                    "status": "ok",
+                    "objects": stats.objects,
+                    "deltas": stats.deltas,
+                    "dictionaries": stats.dictionaries,
+                    "ratio_before": stats.ratio_before(),
+                    "ratio_after": stats.ratio_after(),
+                    "bytes_before": stats.bytes_before,
+                    "bytes_after": stats.bytes_after,
+                    "bytes_reclaimed": stats.bytes_reclaimed()
+                }),
+            );
+        }
+
+        Commands::Fsck { repair } => {
+            let options = if repair { writer } else { reader };
+            let db = Database::open_with_options(&cli.database, options)?;
+            let report = db.fsck(repair)?;
+            let mut value = serde_json::to_value(&report)?;
+            value["status"] = if report.is_ok() { "ok" } else { "corrupt" }.into();
+            output(&cli.format, &value);
+            if !report.is_ok() {
+                std::process::exit(1);
+            }
+        }
+
+        Commands::Stats => {
+            let db = Database::open_with_options(&cli.database, reader)?;
+            let stats = db.stats()?;
+            let mut value = serde_json::to_value(&stats)?;
+            value["object_count"] = stats.object_count().into();
+            value["stored_bytes"] = stats.stored_bytes().into();
+            value["uncompressed_bytes"] = stats.uncompressed_bytes().into();
+            output(&cli.format, &value);
+        }
+
+        Commands::Backup {
+            path,
+            branches,
+            encrypt,
+        } => {
+            if encrypt && writer.key.is_none() {
+                anyhow::bail!("--encrypt needs a key: pass --key-file or set INDRA_PASSPHRASE");
+            }
+            let store = ObjectStore::open_with_options(&cli.database, reader)?;
+            // As with init, a key alone does not encrypt a plaintext database
+            if !encrypt && !store.is_encrypted() {
+                writer.key = None;
+            }
+            let encrypted = writer.key.is_some();
+            let db = Database::with_storage(store);
+            let report = db.backup_to(&path, &branches, writer)?;
+            let mut value = serde_json::to_value(&report)?;
+            value["status"] = "ok".into();
+            value["path"] = path.display().to_string().into();
+            value["encrypted"] = encrypted.into();
+            output(&cli.format, &value);
+        }
+
+        Commands::Migrate => {
+            let report = indra_db::store::migrate(&cli.database, writer)?;
+            output(
+                &cli.format,
+                &serde_json::json!({
+                    "status": if report.is_noop() { "up_to_date" } else { "migrated" },
+                    "from": report.from,
+                    "to": report.to,
+                    "backup": report.backup.map(|p| p.display().to_string()),
+                    "applied": report.applied
+                }),
+            );
+        }
+
+        Commands::Bundle(bundle_cmd) => match bundle_cmd {
+            BundleCommands::Create { out, refs, since } => {
+                let db = Database::open_with_options(&cli.database, reader)?;
+                let since = since
+                    .iter()
+                    .map(|s| {
+                        db.list_branches()
+                            .into_iter()
+                            .find(|(name, _)| name == s)
+                            .map(|(_, hash)| Ok(hash))
+                            .unwrap_or_else(|| indra_db::Hash::from_hex(s))
+                            .map_err(|_| anyhow::anyhow!("Unknown commit or branch: {}", s))
+                    })
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                let file = std::io::BufWriter::new(std::fs::File::create(&out)?);
+                let report = db.create_bundle(file, &refs, &since)?;
+                let mut value = serde_json::to_value(&report)?;
+                value["status"] = "ok".into();
+                value["path"] = out.display().to_string().into();
+                output(&cli.format, &value);
+            }
+            BundleCommands::Unbundle { file, force } => {
+                let mut db = Database::open_with_options(&cli.database, writer)?;
+                let input = std::io::BufReader::new(std::fs::File::open(&file)?);
+                let report = db.unbundle(input, force)?;
+                db.sync()?;
+                let mut value = serde_json::to_value(&report)?;
+                value["status"] = "ok".into();
+                output(&cli.format, &value);
+            }
+        },
+
         // === Remote Commands ===
         Commands::Remote(remote_cmd) => {
             let mut remote_config = indra_db::RemoteConfig::load(&cli.database)?;
@@ -870,6 +1379,7 @@ fn main() -> anyhow::Result<()> {
             // Get local head for reporting
             let db = open_db(
                 &cli.database,
+                &reader,
                 &cli.embedder,
                 cli.model.clone(),
                 cli.dimension,
@@ -884,7 +1394,8 @@ fn main() -> anyhow::Result<()> {
             #[cfg(feature = "sync")]
             {
                 let sync_config = indra_db::SyncConfig::from_env();
-                let client = indra_db::SyncClient::new(sync_config)?;
+                let client =
+                    indra_db::SyncClient::new(sync_config)?.with_store_options(reader.clone());
 
                 match client.push(&cli.database, &remote_info, force) {
                     Ok(result) => {
@@ -961,7 +1472,8 @@ fn main() -> anyhow::Result<()> {
             #[cfg(feature = "sync")]
             {
                 let sync_config = indra_db::SyncConfig::from_env();
-                let client = indra_db::SyncClient::new(sync_config)?;
+                let client =
+                    indra_db::SyncClient::new(sync_config)?.with_store_options(writer.clone());
 
                 match client.pull_smart(&cli.database, &remote_info, force) {
                     Ok(result) => {
@@ -1097,7 +1609,8 @@ fn main() -> anyhow::Result<()> {
             #[cfg(feature = "sync")]
             {
                 let sync_config = indra_db::SyncConfig::from_env();
-                let client = indra_db::SyncClient::new(sync_config)?;
+                let client =
+                    indra_db::SyncClient::new(sync_config)?.with_store_options(reader.clone());
 
                 match client.compare(&cli.database, &remote_info) {
                     Ok(state) => {
@@ -1218,7 +1731,8 @@ fn main() -> anyhow::Result<()> {
             {
                 let remote = indra_db::Remote::new("origin", &url);
                 let sync_config = indra_db::SyncConfig::from_env();
-                let client = indra_db::SyncClient::new(sync_config)?;
+                let client =
+                    indra_db::SyncClient::new(sync_config)?.with_store_options(writer.clone());
 
                 match client.pull(&local_path, &remote) {
                     Ok(size_bytes) => {
@@ -1579,6 +2093,7 @@ fn main() -> anyhow::Result<()> {
         } => {
             let db = open_db(
                 &cli.database,
+                &reader,
                 &cli.embedder,
                 cli.model.clone(),
                 cli.dimension,
@@ -1651,11 +2166,17 @@ fn main() -> anyhow::Result<()> {
 
 fn open_db(
     path: &PathBuf,
+    options: &StoreOptions,
     embedder_type: &str,
     model: Option<String>,
     _dimension: Option<usize>,
 ) -> anyhow::Result<Database> {
-    let db = Database::open_or_create(path)?;
+    let db = if options.read_only && path.exists() {
+        Database::open_with_options(path, options.clone())?
+    } else {
+        // A read of a missing database creates it, as before
+        Database::open_or_create_with_options(path, options.clone().with_read_only(false))?
+    };
 
     match embedder_type {
         "mock" => Ok(db.with_embedder(MockEmbedder::default())),
@@ -1713,6 +2234,37 @@ fn open_db(
     }
 }
 
+fn attachment_json(attachment: &indra_db::AttachmentRef) -> serde_json::Value {
+    serde_json::json!({
+        "name": attachment.name,
+        "mime_type": attachment.mime_type,
+        "size": attachment.size,
+        "hash": attachment.hash.to_hex()
+    })
+}
+
+/// MIME type for common file extensions
+fn guess_mime_type(path: &std::path::Path) -> &'static str {
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("svg") => "image/svg+xml",
+        Some("webp") => "image/webp",
+        Some("pdf") => "application/pdf",
+        Some("json") => "application/json",
+        Some("zip") => "application/zip",
+        Some("txt" | "rs" | "py" | "ts" | "js" | "toml" | "yaml" | "yml") => "text/plain",
+        Some("md") => "text/markdown",
+        Some("html" | "htm") => "text/html",
+        Some("csv") => "text/csv",
+        _ => "application/octet-stream",
+    }
+}
+
 fn output(format: &OutputFormat, value: &serde_json::Value) {
     match format {
         OutputFormat::Json => {
@@ -1724,12 +2276,13 @@ fn output(format: &OutputFormat, value: &serde_json::Value) {
     }
 }
 
-fn resolve_ref(
-    reference: &str,
-    log: &[(indra_db::Hash, indra_db::Commit)],
-) -> anyhow::Result<indra_db::Hash> {
+/// Resolve "HEAD", "HEAD~N", a branch name or a commit hash
+///
+/// Walks no further back than the reference asks for.
+fn resolve_ref(db: &Database, reference: &str) -> anyhow::Result<indra_db::Hash> {
     if reference == "HEAD" {
-        return log
+        return db
+            .log(Some(1))?
             .first()
             .map(|(h, _)| *h)
             .ok_or_else(|| anyhow::anyhow!("No commits yet"));
@@ -1737,12 +2290,24 @@ fn resolve_ref(
 
     if let Some(suffix) = reference.strip_prefix("HEAD~") {
         let n: usize = suffix.parse()?;
-        return log
+        return db
+            .log(Some(n.saturating_add(1)))?
             .get(n)
             .map(|(h, _)| *h)
             .ok_or_else(|| anyhow::anyhow!("Not enough commits in history"));
     }
 
+    if let Some((_, hash)) = db
+        .list_branches()
+        .into_iter()
+        .find(|(name, _)| name == reference)
+    {
+        if hash.is_zero() {
+            anyhow::bail!("Branch {} has no commits yet", reference);
+        }
+        return Ok(hash);
+    }
+
     // Try as hash
     indra_db::Hash::from_hex(reference)
         .map_err(|_| anyhow::anyhow!("Invalid reference: {}", reference))
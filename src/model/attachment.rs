@@ -0,0 +1,20 @@
+//! Attachment references - binary content kept next to a thought
+
+use super::Hash;
+use serde::{Deserialize, Serialize};
+
+/// A thought's reference to an attachment (screenshot, PDF, source file...)
+///
+/// The content itself is stored as `Attachment` blobs (see
+/// `store::attachment`); `hash` addresses its root object.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AttachmentRef {
+    /// Name the attachment was added under, unique per thought
+    pub name: String,
+    /// MIME type, e.g. "image/png"
+    pub mime_type: String,
+    /// Content size in bytes
+    pub size: u64,
+    /// Hash of the attachment's root object
+    pub hash: Hash,
+}
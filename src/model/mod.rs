@@ -1,10 +1,12 @@
 //! Core data model types for indra_db
 
+mod attachment;
 mod commit;
 mod edge;
 mod hash;
 mod thought;
 
+pub use attachment::AttachmentRef;
 pub use commit::Commit;
 pub use edge::{Edge, EdgeType};
 pub use hash::Hash;
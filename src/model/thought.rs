@@ -1,8 +1,11 @@
 //! Thought (node) type - the fundamental unit of knowledge
 
-use super::Hash;
+use super::{AttachmentRef, Hash};
+use serde::de::{self, SeqAccess, Visitor};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::collections::HashMap;
+use std::fmt;
+use std::marker::PhantomData;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 /// Wrapper type for storing JSON values that is compatible with bincode.
@@ -139,6 +142,56 @@ pub struct Thought {
 
     /// Last modified timestamp (unix millis)
     pub modified_at: u64,
+
+    /// Binary content attached to the thought
+    ///
+    /// Thoughts written before attachments existed end before this field.
+    #[serde(default, deserialize_with = "missing_as_empty")]
+    pub attachments: Vec<AttachmentRef>,
+}
+
+/// Decode a trailing list that older encodings do not have
+///
+/// bincode reports data that ends early as an error rather than a missing
+/// field, so `#[serde(default)]` alone does not cover it. The list is read
+/// as its length followed by the items, the way bincode writes a `Vec`, so
+/// that only input ending where the length would start counts as missing;
+/// a list that is cut short or corrupted is still an error.
+fn missing_as_empty<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    if deserializer.is_human_readable() {
+        return Vec::deserialize(deserializer);
+    }
+    deserializer.deserialize_tuple(usize::MAX, TrailingList(PhantomData))
+}
+
+/// Visitor for [`missing_as_empty`]
+struct TrailingList<T>(PhantomData<T>);
+
+impl<'de, T: Deserialize<'de>> Visitor<'de> for TrailingList<T> {
+    type Value = Vec<T>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a length-prefixed list")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<T>, A::Error> {
+        // Reading a length can only fail at the end of the input
+        let Ok(Some(len)) = seq.next_element::<u64>() else {
+            return Ok(Vec::new());
+        };
+        let mut items = Vec::with_capacity(len.min(1024) as usize);
+        for i in 0..len {
+            let item = seq
+                .next_element()?
+                .ok_or_else(|| de::Error::invalid_length(i as usize, &self))?;
+            items.push(item);
+        }
+        Ok(items)
+    }
 }
 
 impl Thought {
@@ -157,6 +210,7 @@ impl Thought {
             attrs: HashMap::new(),
             created_at: now,
             modified_at: now,
+            attachments: Vec::new(),
         }
     }
 
@@ -175,6 +229,7 @@ impl Thought {
             attrs: HashMap::new(),
             created_at: now,
             modified_at: now,
+            attachments: Vec::new(),
         }
     }
 
@@ -205,6 +260,27 @@ impl Thought {
         self.attrs.get(key).map(|v| &v.0)
     }
 
+    /// Get an attachment by name
+    pub fn attachment(&self, name: &str) -> Option<&AttachmentRef> {
+        self.attachments.iter().find(|a| a.name == name)
+    }
+
+    /// Add an attachment, replacing any existing one with the same name
+    pub fn set_attachment(&mut self, attachment: AttachmentRef) {
+        match self
+            .attachments
+            .iter_mut()
+            .find(|a| a.name == attachment.name)
+        {
+            Some(existing) => *existing = attachment,
+            None => self.attachments.push(attachment),
+        }
+        self.modified_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+    }
+
     /// Compute the content hash of this thought
     /// This determines the blob's address in content-addressed storage
     pub fn content_hash(&self) -> Hash {
@@ -255,6 +331,64 @@ mod tests {
         assert_ne!(t1.content_hash(), t2.content_hash());
     }
 
+    #[test]
+    fn test_thought_attachments() {
+        let mut thought = Thought::new("With a screenshot");
+        let screenshot = AttachmentRef {
+            name: "screen.png".to_string(),
+            mime_type: "image/png".to_string(),
+            size: 3,
+            hash: Hash::digest(b"png"),
+        };
+        thought.set_attachment(screenshot.clone());
+        thought.set_attachment(AttachmentRef {
+            size: 4,
+            ..screenshot
+        });
+        assert_eq!(thought.attachments.len(), 1);
+        assert_eq!(thought.attachment("screen.png").unwrap().size, 4);
+
+        let decoded: Thought =
+            bincode::deserialize(&bincode::serialize(&thought).unwrap()).unwrap();
+        assert_eq!(decoded.attachments, thought.attachments);
+
+        // Thoughts encoded before attachments existed lack the list entirely
+        let plain = Thought::new("Old thought");
+        let mut old = bincode::serialize(&plain).unwrap();
+        old.truncate(old.len() - 8);
+        let decoded: Thought = bincode::deserialize(&old).unwrap();
+        assert!(decoded.attachments.is_empty());
+        assert_eq!(decoded.content, "Old thought");
+    }
+
+    #[test]
+    fn test_corrupted_attachment_list_fails_to_decode() {
+        let mut thought = Thought::new("With a screenshot");
+        thought.set_attachment(AttachmentRef {
+            name: "screen.png".to_string(),
+            mime_type: "image/png".to_string(),
+            size: 3,
+            hash: Hash::digest(b"png"),
+        });
+        let encoded = bincode::serialize(&thought).unwrap();
+
+        // Cut short inside the list
+        let truncated = &encoded[..encoded.len() - 5];
+        assert!(bincode::deserialize::<Thought>(truncated).is_err());
+
+        // A length claiming more items than there are
+        let list_start = bincode::serialize(&Thought {
+            attachments: Vec::new(),
+            ..thought.clone()
+        })
+        .unwrap()
+        .len()
+            - 8;
+        let mut inflated = encoded.clone();
+        inflated[list_start..list_start + 8].copy_from_slice(&2u64.to_le_bytes());
+        assert!(bincode::deserialize::<Thought>(&inflated).is_err());
+    }
+
     #[test]
     fn test_thought_builder() {
         let thought = Thought::new("Test thought")
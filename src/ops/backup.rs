@@ -0,0 +1,284 @@
+//! Point-in-time backups of a live store
+//!
+//! `backup` snapshots the refs once, then copies every object reachable
+//! from them into a new database file. Objects are immutable, so writes
+//! that land while the copy runs only add objects and move refs the
+//! snapshot no longer looks at; the backup reflects the instant the refs
+//! were read. Nothing is read from the source file directly, so a sync in
+//! progress cannot tear the copy.
+//!
+//! The finished file is reopened and checked before `backup` returns. The
+//! check covers what the copy is responsible for: every object re-hashes,
+//! everything the refs reach is present, and refs, HEAD and the object
+//! count match the snapshot. Graph-level findings such as an edge to a
+//! deleted thought come along from the source and do not fail the backup.
+//! A copy that fails, or that could not be finished, is deleted together
+//! with its lock file.
+
+use crate::model::Hash;
+use crate::ops::{fsck, Problem};
+use crate::store::{
+    lock_path, reachable_objects, Head, ObjectStore, Storage, StoreOptions, HEAD_REF,
+};
+use crate::{Error, Result};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::path::Path;
+
+/// Outcome of a backup
+#[derive(Clone, Debug, Serialize)]
+pub struct BackupReport {
+    /// Refs copied, with the commits they pointed at
+    pub refs: Vec<(String, String)>,
+    /// What HEAD points at in the copy
+    pub head: String,
+    /// Objects copied
+    pub objects: usize,
+    /// Size of the backup file
+    pub bytes: u64,
+}
+
+/// Copy the state of `source` to a new database file at `path`
+///
+/// With `branches` empty, every ref and a detached HEAD are copied;
+/// otherwise only the named branches. The reflog is not copied. The new
+/// file is created with `options`, so it is encrypted if they carry a key.
+/// Fails if `path` already exists.
+pub fn backup(
+    source: &dyn Storage,
+    path: &Path,
+    branches: &[String],
+    options: StoreOptions,
+) -> Result<BackupReport> {
+    if path.exists() {
+        return Err(Error::Io(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("{} already exists", path.display()),
+        )));
+    }
+
+    let (refs, head) = snapshot_refs(source, branches)?;
+    let mut roots: Vec<Hash> = refs.iter().map(|(_, hash)| *hash).collect();
+    if let Head::Detached(hash) = head {
+        roots.push(hash);
+    }
+    let objects = reachable_objects(source, roots)?;
+
+    let copied = copy(source, path, options.clone(), &objects, &refs, &head)
+        .and_then(|()| verify(path, options, &refs, &head, objects.len()));
+    if let Err(e) = copied {
+        let _ = std::fs::remove_file(path);
+        let _ = std::fs::remove_file(lock_path(path));
+        return Err(e);
+    }
+
+    Ok(BackupReport {
+        refs: refs
+            .into_iter()
+            .map(|(name, hash)| (name, hash.to_hex()))
+            .collect(),
+        head: head.to_string(),
+        objects: objects.len(),
+        bytes: std::fs::metadata(path)?.len(),
+    })
+}
+
+/// The refs to copy and the HEAD the copy should have
+fn snapshot_refs(source: &dyn Storage, branches: &[String]) -> Result<(Vec<(String, Hash)>, Head)> {
+    let mut refs = source.list_refs();
+    let head = source.head();
+    refs.sort_by(|a, b| a.0.cmp(&b.0));
+    if branches.is_empty() {
+        return Ok((refs, head));
+    }
+
+    for name in branches {
+        if !refs.iter().any(|(r, _)| r == name) {
+            return Err(Error::BranchNotFound(name.clone()));
+        }
+    }
+    refs.retain(|(name, _)| branches.contains(name));
+    let head = match head.branch() {
+        Some(name) if branches.iter().any(|b| b == name) => head,
+        _ => Head::Branch(refs[0].0.clone()),
+    };
+    Ok((refs, head))
+}
+
+/// Write the snapshot to a new store at `path`
+fn copy(
+    source: &dyn Storage,
+    path: &Path,
+    options: StoreOptions,
+    objects: &HashSet<Hash>,
+    refs: &[(String, Hash)],
+    head: &Head,
+) -> Result<()> {
+    let target = ObjectStore::create_with_options(path, options)?;
+    for hash in objects {
+        target.put(&source.get(hash)?)?;
+    }
+    for (name, hash) in refs {
+        target.set_ref(name, *hash, "backup")?;
+    }
+    target.set_head(head.clone(), "backup")?;
+    if !refs.iter().any(|(name, _)| name == "main") {
+        target.delete_branch("main")?;
+    }
+    target.sync()
+}
+
+/// Reopen the backup and check it holds exactly the snapshot
+fn verify(
+    path: &Path,
+    options: StoreOptions,
+    refs: &[(String, Hash)],
+    head: &Head,
+    objects: usize,
+) -> Result<()> {
+    let store = ObjectStore::open_with_options(path, options.with_read_only(true))?;
+    let fail = |reason: String| {
+        Err(Error::Corruption(format!(
+            "Backup failed verification: {}",
+            reason
+        )))
+    };
+
+    let mut copied = store.list_refs();
+    copied.sort_by(|a, b| a.0.cmp(&b.0));
+    if copied != refs {
+        return fail("refs differ from the snapshot".into());
+    }
+    if store.head() != *head {
+        return fail(format!(
+            "{} is {}, expected {}",
+            HEAD_REF,
+            store.head(),
+            head
+        ));
+    }
+    if store.object_count() != objects {
+        return fail(format!(
+            "{} objects, expected {}",
+            store.object_count(),
+            objects
+        ));
+    }
+    // Objects that do not decode or keys outside the namespaces were
+    // copied byte for byte, so they are the source's problem, not the copy's
+    let report = fsck(&store, false)?;
+    let damaged = report
+        .problems
+        .iter()
+        .filter(|problem| {
+            matches!(
+                problem,
+                Problem::Unreadable { .. } | Problem::HashMismatch { .. } | Problem::Missing { .. }
+            )
+        })
+        .count();
+    if damaged > 0 {
+        return fail(format!("{} objects damaged or missing", damaged));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Database;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_backup_is_a_point_in_time_copy() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("live.indra");
+        let mut db = Database::create(&path).unwrap();
+        db.create_thought_with_id("a", "Alpha").unwrap();
+        db.commit("First").unwrap();
+        db.create_branch("idea").unwrap();
+        db.checkout("idea").unwrap();
+        db.create_thought_with_id("b", "Beta").unwrap();
+        db.commit("Idea").unwrap();
+        db.checkout("main").unwrap();
+
+        let full = dir.path().join("full.indra");
+        let report = db.backup_to(&full, &[], StoreOptions::default()).unwrap();
+        assert_eq!(report.refs.len(), 2);
+        assert_eq!(report.head, "main");
+
+        // Writes after the snapshot do not reach the copy
+        db.create_thought_with_id("c", "Gamma").unwrap();
+        db.commit("Later").unwrap();
+
+        let copy = Database::open(&full).unwrap();
+        assert!(copy.get_thought(&"a".into()).unwrap().is_some());
+        assert!(copy.get_thought(&"c".into()).unwrap().is_none());
+        assert_eq!(copy.list_branches().len(), 2);
+        assert!(copy.fsck(false).unwrap().is_ok());
+        drop(copy);
+
+        // Only the selected branch, which becomes HEAD
+        let partial = dir.path().join("idea.indra");
+        let report = db
+            .backup_to(&partial, &["idea".to_string()], StoreOptions::default())
+            .unwrap();
+        assert_eq!(report.head, "idea");
+        let copy = Database::open(&partial).unwrap();
+        assert_eq!(copy.current_branch().as_deref(), Some("idea"));
+        assert_eq!(copy.list_branches().len(), 1);
+        assert!(copy.get_thought(&"b".into()).unwrap().is_some());
+        drop(copy);
+
+        assert!(matches!(
+            db.backup_to(&partial, &[], StoreOptions::default()),
+            Err(Error::Io(_))
+        ));
+        assert!(matches!(
+            db.backup_to(
+                dir.path().join("none.indra"),
+                &["nope".to_string()],
+                StoreOptions::default()
+            ),
+            Err(Error::BranchNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_backup_tolerates_graph_findings_and_cleans_up_failures() {
+        use crate::model::{Edge, EdgeType, Thought};
+        use crate::ops::BranchManager;
+        use crate::store::{EncryptionKey, MemoryStore};
+        use crate::trie::MerkleTrie;
+
+        // A key outside the namespaces and an edge to a thought that is
+        // gone: fsck flags both, but the copy is faithful
+        let source = MemoryStore::new();
+        let a = source.put_thought(&Thought::with_id("a", "Alpha")).unwrap();
+        let edge = source
+            .put_edge(&Edge::new("a", "b", EdgeType::RELATES_TO))
+            .unwrap();
+        let mut trie = MerkleTrie::new(&source);
+        for (key, hash) in [("t:a", a), ("e:a:b:relates_to", edge), ("x:odd", a)] {
+            trie.insert(key.as_bytes(), hash).unwrap();
+        }
+        let tree = trie.commit().unwrap();
+        BranchManager::new(&source)
+            .commit(tree, "Odd", "test")
+            .unwrap();
+        assert!(!fsck(&source, false).unwrap().is_ok());
+
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("copy.indra");
+        let report = backup(&source, &path, &[], StoreOptions::default()).unwrap();
+        assert_eq!(report.objects, source.object_count());
+
+        // A backup that cannot be written leaves nothing behind
+        let failed = dir.path().join("failed.indra");
+        let options =
+            StoreOptions::default().with_key(EncryptionKey::File(dir.path().join("no.key")));
+        assert!(backup(&source, &failed, &[], options).is_err());
+        assert!(!failed.exists());
+        assert!(!lock_path(&failed).exists());
+    }
+}
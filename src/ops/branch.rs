@@ -1,24 +1,29 @@
 //! Branch and checkout operations
 
 use crate::model::{Commit, Hash};
-use crate::store::ObjectStore;
+use crate::store::{Head, RefLogEntry, Storage};
 use crate::Result;
 
 /// Manages branches and refs
 pub struct BranchManager<'a> {
-    store: &'a ObjectStore,
+    store: &'a dyn Storage,
 }
 
 impl<'a> BranchManager<'a> {
-    pub fn new(store: &'a ObjectStore) -> Self {
+    pub fn new(store: &'a dyn Storage) -> Self {
         BranchManager { store }
     }
 
-    /// Get the current branch name
-    pub fn current_branch(&self) -> String {
+    /// Get what HEAD points at
+    pub fn head(&self) -> Head {
         self.store.head()
     }
 
+    /// Get the current branch name, unless HEAD is detached
+    pub fn current_branch(&self) -> Option<String> {
+        self.store.head().branch().map(str::to_string)
+    }
+
     /// List all branches
     pub fn list_branches(&self) -> Vec<(String, Hash)> {
         self.store.list_refs()
@@ -42,7 +47,10 @@ impl<'a> BranchManager<'a> {
 
     /// Switch to a branch
     pub fn switch_branch(&self, name: &str) -> Result<()> {
-        self.store.set_head(name)
+        self.store.set_head(
+            Head::Branch(name.to_string()),
+            &format!("checkout: moving to {}", name),
+        )
     }
 
     /// Get the commit at a branch
@@ -72,9 +80,12 @@ impl<'a> BranchManager<'a> {
 
         let commit_hash = self.store.put_commit(&commit)?;
 
-        // Update the current branch ref
-        let branch = self.current_branch();
-        self.store.set_ref(&branch, commit_hash);
+        // Advance the current branch, or HEAD itself when detached
+        let reason = format!("commit: {}", message);
+        match self.store.head() {
+            Head::Branch(branch) => self.store.set_ref(&branch, commit_hash, &reason)?,
+            Head::Detached(_) => self.store.set_head(Head::Detached(commit_hash), &reason)?,
+        }
 
         Ok(commit_hash)
     }
@@ -98,13 +109,30 @@ impl<'a> BranchManager<'a> {
 
         Ok(result)
     }
+
+    /// Get ref movements, newest first
+    ///
+    /// With `name`, only movements of that branch (or of HEAD for
+    /// `HEAD_REF`) are returned.
+    pub fn reflog(&self, name: Option<&str>, limit: Option<usize>) -> Vec<RefLogEntry> {
+        self.store
+            .reflog()
+            .into_iter()
+            .rev()
+            .filter(|entry| name.is_none_or(|n| entry.name == n))
+            .take(limit.unwrap_or(usize::MAX))
+            .collect()
+    }
 }
 
 /// Checkout a specific commit or branch
-pub fn checkout(store: &ObjectStore, target: &str) -> Result<Hash> {
+pub fn checkout(store: &dyn Storage, target: &str) -> Result<Hash> {
     // Try as branch first
     if let Some(commit_hash) = store.get_ref(target) {
-        store.set_head(target)?;
+        store.set_head(
+            Head::Branch(target.to_string()),
+            &format!("checkout: moving to {}", target),
+        )?;
         if commit_hash.is_zero() {
             return Ok(Hash::ZERO);
         }
@@ -116,8 +144,10 @@ pub fn checkout(store: &ObjectStore, target: &str) -> Result<Hash> {
     if let Ok(hash) = Hash::from_hex(target) {
         if store.contains(&hash) {
             let commit = store.get_commit(&hash)?;
-            // Create a detached HEAD (we'll use a special ref name)
-            store.set_ref("HEAD_DETACHED", hash);
+            store.set_head(
+                Head::Detached(hash),
+                &format!("checkout: moving to {}", hash.short()),
+            )?;
             return Ok(commit.tree);
         }
     }
@@ -128,6 +158,7 @@ pub fn checkout(store: &ObjectStore, target: &str) -> Result<Hash> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::store::ObjectStore;
     use tempfile::tempdir;
 
     fn setup() -> (tempfile::TempDir, ObjectStore) {
@@ -143,14 +174,14 @@ mod tests {
         let manager = BranchManager::new(&store);
 
         // Default branch is main
-        assert_eq!(manager.current_branch(), "main");
+        assert_eq!(manager.current_branch().as_deref(), Some("main"));
 
         // Create a new branch
         manager.create_branch("feature").unwrap();
 
         // Switch to it
         manager.switch_branch("feature").unwrap();
-        assert_eq!(manager.current_branch(), "feature");
+        assert_eq!(manager.current_branch().as_deref(), Some("feature"));
 
         // List branches
         let branches = manager.list_branches();
@@ -174,4 +205,39 @@ mod tests {
         assert_eq!(log[0].0, c2);
         assert_eq!(log[1].0, c1);
     }
+
+    #[test]
+    fn test_detached_commit_and_reflog() {
+        let (_dir, store) = setup();
+        let manager = BranchManager::new(&store);
+
+        let c1 = manager
+            .commit(Hash::digest(b"tree1"), "First", "test")
+            .unwrap();
+        manager
+            .commit(Hash::digest(b"tree2"), "Second", "test")
+            .unwrap();
+
+        checkout(&store, &c1.to_hex()).unwrap();
+        assert_eq!(manager.head(), Head::Detached(c1));
+        assert_eq!(manager.current_branch(), None);
+
+        // Commits on a detached HEAD leave branches alone
+        let main_before = manager.branch_commit("main");
+        let c3 = manager
+            .commit(Hash::digest(b"tree3"), "Detached", "test")
+            .unwrap();
+        assert_eq!(manager.head(), Head::Detached(c3));
+        assert_eq!(manager.branch_commit("main"), main_before);
+
+        let head_moves = manager.reflog(Some(crate::store::HEAD_REF), None);
+        assert_eq!(head_moves.len(), 2);
+        assert_eq!(head_moves[0].reason, "commit: Detached");
+        assert_eq!((head_moves[0].old, head_moves[0].new), (c1, c3));
+
+        let main_moves = manager.reflog(Some("main"), Some(1));
+        assert_eq!(main_moves.len(), 1);
+        assert_eq!(main_moves[0].reason, "commit: Second");
+        assert_eq!(manager.reflog(None, None).len(), 4);
+    }
 }
@@ -0,0 +1,366 @@
+//! Bundles: history in a single file, for moving it between databases
+//!
+//! A bundle holds the objects reachable from some refs, minus those
+//! reachable from prerequisite commits the receiving database must already
+//! have, like a git bundle. Layout:
+//! ```text
+//! Header:
+//!   - magic: "INDRABDL"
+//!   - version: 4 bytes (u32 LE)
+//! Refs: count (u32 LE), then per ref:
+//!   - name_len (u16 LE), name
+//!   - commit: 32 bytes
+//! Prerequisites: count (u32 LE), then 32-byte commit hashes
+//! Objects: count (u64 LE), then per object:
+//!   - hash: 32 bytes
+//!   - len: 4 bytes (u32 LE)
+//!   - blob: type byte + zstd data, as `Blob::compress` writes it
+//! Checksum: 32-byte BLAKE3 of everything before it
+//! ```
+//!
+//! Unbundling checks every object against its hash as it goes, then the
+//! checksum, then that the refs' history is complete, and only then moves
+//! refs. Objects imported from a bundle that fails a later check are left
+//! unreachable for `gc`.
+
+use crate::model::{Commit, Hash};
+use crate::store::{reachable_objects, validate_ref_name, Blob, Storage};
+use crate::{Error, Result};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::io::{Read, Write};
+
+const BUNDLE_MAGIC: &[u8; 8] = b"INDRABDL";
+const BUNDLE_VERSION: u32 = 1;
+
+/// What a bundle holds
+#[derive(Clone, Debug, Serialize)]
+pub struct BundleReport {
+    /// Refs, with the commits they point at
+    pub refs: Vec<(String, String)>,
+    /// Commits the receiving database must already have
+    pub prerequisites: Vec<String>,
+    /// Objects in the bundle
+    pub objects: u64,
+}
+
+/// Write the history of `refs` to `out`, leaving out what `since` reaches
+///
+/// Every name in `refs` must be an existing ref with at least one commit.
+pub fn create_bundle(
+    store: &dyn Storage,
+    out: impl Write,
+    refs: &[String],
+    since: &[Hash],
+) -> Result<BundleReport> {
+    let mut tips = Vec::with_capacity(refs.len());
+    for name in refs {
+        match store.get_ref(name) {
+            Some(hash) if !hash.is_zero() => tips.push((name.clone(), hash)),
+            _ => return Err(Error::RefNotFound(name.clone())),
+        }
+    }
+
+    let excluded = reachable_objects(store, since.iter().copied())?;
+    let mut objects: Vec<Hash> = reachable_objects(store, tips.iter().map(|(_, h)| *h))?
+        .into_iter()
+        .filter(|hash| !excluded.contains(hash))
+        .collect();
+    objects.sort_by_key(|h| *h.as_bytes());
+
+    let mut out = HashingWriter::new(out);
+    out.write_all(BUNDLE_MAGIC)?;
+    out.write_all(&BUNDLE_VERSION.to_le_bytes())?;
+
+    out.write_all(&(tips.len() as u32).to_le_bytes())?;
+    for (name, hash) in &tips {
+        out.write_all(&(name.len() as u16).to_le_bytes())?;
+        out.write_all(name.as_bytes())?;
+        out.write_all(hash.as_bytes())?;
+    }
+
+    out.write_all(&(since.len() as u32).to_le_bytes())?;
+    for hash in since {
+        out.write_all(hash.as_bytes())?;
+    }
+
+    out.write_all(&(objects.len() as u64).to_le_bytes())?;
+    for hash in &objects {
+        let data = store.get(hash)?.compress()?;
+        out.write_all(hash.as_bytes())?;
+        out.write_all(&(data.len() as u32).to_le_bytes())?;
+        out.write_all(&data)?;
+    }
+
+    let checksum = out.hasher.finalize();
+    out.inner.write_all(checksum.as_bytes())?;
+    out.inner.flush()?;
+
+    Ok(BundleReport {
+        refs: tips.iter().map(|(n, h)| (n.clone(), h.to_hex())).collect(),
+        prerequisites: since.iter().map(Hash::to_hex).collect(),
+        objects: objects.len() as u64,
+    })
+}
+
+/// Import a bundle into `store` and point its refs at the bundled commits
+///
+/// Existing refs only move forward: one whose commit is not an ancestor of
+/// the bundled commit fails with `Error::MergeConflict` unless `force`.
+/// Nothing is moved unless every check passes.
+pub fn unbundle(store: &dyn Storage, input: impl Read, force: bool) -> Result<BundleReport> {
+    let mut input = HashingReader::new(input);
+
+    let mut magic = [0u8; 8];
+    input.read_exact(&mut magic)?;
+    if &magic != BUNDLE_MAGIC {
+        return Err(Error::InvalidFile("Not a bundle".into()));
+    }
+    let version = read_u32(&mut input)?;
+    if version != BUNDLE_VERSION {
+        return Err(Error::InvalidFile(format!(
+            "Unsupported bundle version {}",
+            version
+        )));
+    }
+
+    let mut refs = Vec::new();
+    for _ in 0..read_u32(&mut input)? {
+        let mut len = [0u8; 2];
+        input.read_exact(&mut len)?;
+        let mut name = vec![0u8; u16::from_le_bytes(len) as usize];
+        input.read_exact(&mut name)?;
+        let name = String::from_utf8(name)
+            .map_err(|_| Error::Corruption("Bundle ref name is not UTF-8".into()))?;
+        validate_ref_name(&name)?;
+        refs.push((name, read_hash(&mut input)?));
+    }
+
+    let mut prerequisites = Vec::new();
+    for _ in 0..read_u32(&mut input)? {
+        let hash = read_hash(&mut input)?;
+        if !store.contains(&hash) {
+            return Err(Error::NotFound(format!(
+                "Bundle needs commit {} first",
+                hash.to_hex()
+            )));
+        }
+        prerequisites.push(hash);
+    }
+
+    let mut count = [0u8; 8];
+    input.read_exact(&mut count)?;
+    let objects = u64::from_le_bytes(count);
+    for _ in 0..objects {
+        let hash = read_hash(&mut input)?;
+        let mut data = vec![0u8; read_u32(&mut input)? as usize];
+        input.read_exact(&mut data)?;
+        let blob = Blob::decompress(&data)?;
+        let actual = blob.hash();
+        if actual != hash {
+            return Err(Error::Corruption(format!(
+                "Bundle object {} hashes to {}",
+                hash.to_hex(),
+                actual.to_hex()
+            )));
+        }
+        store.put(&blob)?;
+    }
+
+    let expected = input.hasher.finalize();
+    let mut checksum = [0u8; 32];
+    input.inner.read_exact(&mut checksum)?;
+    if checksum != *expected.as_bytes() {
+        return Err(Error::Corruption("Bundle checksum mismatch".into()));
+    }
+
+    // Fails on anything missing below the refs
+    reachable_objects(store, refs.iter().map(|(_, h)| *h))?;
+
+    if !force {
+        for (name, hash) in &refs {
+            if let Some(current) = store.get_ref(name) {
+                if !current.is_zero() && !is_ancestor(store, current, *hash)? {
+                    return Err(Error::MergeConflict(format!(
+                        "{} has diverged from the bundle",
+                        name
+                    )));
+                }
+            }
+        }
+    }
+    for (name, hash) in &refs {
+        store.set_ref(name, *hash, "unbundle")?;
+    }
+
+    Ok(BundleReport {
+        refs: refs.iter().map(|(n, h)| (n.clone(), h.to_hex())).collect(),
+        prerequisites: prerequisites.iter().map(Hash::to_hex).collect(),
+        objects,
+    })
+}
+
+/// Whether `ancestor` is `commit` or in its history
+fn is_ancestor(store: &dyn Storage, ancestor: Hash, commit: Hash) -> Result<bool> {
+    let mut seen = HashSet::new();
+    let mut stack = vec![commit];
+    while let Some(hash) = stack.pop() {
+        if hash == ancestor {
+            return Ok(true);
+        }
+        if hash.is_zero() || !seen.insert(hash) {
+            continue;
+        }
+        let commit: Commit = store.get_commit(&hash)?;
+        stack.extend(commit.parents);
+    }
+    Ok(false)
+}
+
+fn read_u32(input: &mut impl Read) -> Result<u32> {
+    let mut buf = [0u8; 4];
+    input.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_hash(input: &mut impl Read) -> Result<Hash> {
+    let mut buf = [0u8; 32];
+    input.read_exact(&mut buf)?;
+    Ok(Hash::from_bytes(buf))
+}
+
+/// Hashes everything written through it
+struct HashingWriter<W> {
+    inner: W,
+    hasher: blake3::Hasher,
+}
+
+impl<W: Write> HashingWriter<W> {
+    fn new(inner: W) -> Self {
+        HashingWriter {
+            inner,
+            hasher: blake3::Hasher::new(),
+        }
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Hashes everything read through it
+struct HashingReader<R> {
+    inner: R,
+    hasher: blake3::Hasher,
+}
+
+impl<R: Read> HashingReader<R> {
+    fn new(inner: R) -> Self {
+        HashingReader {
+            inner,
+            hasher: blake3::Hasher::new(),
+        }
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Thought;
+    use crate::ops::BranchManager;
+    use crate::store::MemoryStore;
+    use crate::trie::MerkleTrie;
+
+    /// Commit a thought on top of HEAD
+    fn commit_thought(store: &MemoryStore, id: &str, content: &str) -> Hash {
+        let manager = BranchManager::new(store);
+        let mut trie = MerkleTrie::from_root(store, manager.head_tree().unwrap()).unwrap();
+        let hash = store.put_thought(&Thought::with_id(id, content)).unwrap();
+        trie.insert(format!("t:{}", id).as_bytes(), hash).unwrap();
+        let tree = trie.commit().unwrap();
+        manager.commit(tree, content, "test").unwrap()
+    }
+
+    fn main_bundle(store: &MemoryStore, since: &[Hash]) -> Vec<u8> {
+        let mut bundle = Vec::new();
+        create_bundle(store, &mut bundle, &["main".to_string()], since).unwrap();
+        bundle
+    }
+
+    #[test]
+    fn test_bundle_roundtrip_and_incremental() {
+        let source = MemoryStore::new();
+        let first = commit_thought(&source, "a", "Alpha");
+        let second = commit_thought(&source, "b", "Beta");
+
+        // Full history into an empty store
+        let full = main_bundle(&source, &[]);
+        let target = MemoryStore::new();
+        let report = unbundle(&target, &full[..], false).unwrap();
+        assert_eq!(report.objects as usize, source.object_count());
+        assert_eq!(target.get_ref("main"), Some(second));
+
+        // Only what `first` does not already reach
+        let incremental = main_bundle(&source, &[first]);
+        assert!(incremental.len() < full.len());
+        assert!(matches!(
+            unbundle(&MemoryStore::new(), &incremental[..], false),
+            Err(Error::NotFound(_))
+        ));
+
+        let behind = MemoryStore::new();
+        unbundle(&behind, &main_bundle(&source, &[])[..], true).unwrap();
+        behind.set_ref("main", first, "test").unwrap();
+        let report = unbundle(&behind, &incremental[..], false).unwrap();
+        assert_eq!(report.prerequisites, vec![first.to_hex()]);
+        assert_eq!(behind.get_ref("main"), Some(second));
+    }
+
+    #[test]
+    fn test_unbundle_rejects_damage_and_divergence() {
+        let source = MemoryStore::new();
+        let tip = commit_thought(&source, "a", "Alpha");
+        let bundle = main_bundle(&source, &[]);
+
+        let mut damaged = bundle.clone();
+        let last = damaged.len() - 1;
+        damaged[last] ^= 1;
+        let target = MemoryStore::new();
+        assert!(matches!(
+            unbundle(&target, &damaged[..], false),
+            Err(Error::Corruption(_))
+        ));
+        assert_eq!(target.get_ref("main"), Some(Hash::ZERO));
+
+        // A target whose main has its own history
+        let other = MemoryStore::new();
+        commit_thought(&other, "z", "Elsewhere");
+        assert!(matches!(
+            unbundle(&other, &bundle[..], false),
+            Err(Error::MergeConflict(_))
+        ));
+        unbundle(&other, &bundle[..], true).unwrap();
+        assert_eq!(other.get_ref("main"), Some(tip));
+
+        assert!(matches!(
+            create_bundle(&source, Vec::new(), &["nope".to_string()], &[]),
+            Err(Error::RefNotFound(_))
+        ));
+    }
+}
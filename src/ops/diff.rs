@@ -1,10 +1,11 @@
 //! Diff operations between tree states
 
 use crate::model::Hash;
-use crate::store::ObjectStore;
-use crate::trie::MerkleTrie;
+use crate::store::Storage;
+use crate::trie::{load_node, TrieNode};
 use crate::Result;
-use std::collections::{HashMap, HashSet};
+use std::collections::BTreeMap;
+use std::sync::Arc;
 
 /// Type of change in a diff
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -85,82 +86,197 @@ impl Diff {
 }
 
 /// Compute the diff between two tree states
-pub fn diff_trees(store: &ObjectStore, old_root: Hash, new_root: Hash) -> Result<Diff> {
-    if old_root == new_root {
-        return Ok(Diff::new(vec![]));
-    }
+///
+/// Both tries are walked side by side and any subtree with the same hash on
+/// both sides is skipped, so the cost follows the size of the change rather
+/// than the size of the trees. Entries come out sorted by key.
+pub fn diff_trees(store: &dyn Storage, old_root: Hash, new_root: Hash) -> Result<Diff> {
+    let mut entries = Vec::new();
+    let old = (!old_root.is_zero()).then(|| Cursor::at(old_root));
+    let new = (!new_root.is_zero()).then(|| Cursor::at(new_root));
+    diff_at(store, old, new, &mut Vec::new(), &mut entries)?;
+    Ok(Diff::new(entries))
+}
 
-    // Get all entries from both trees
-    let old_entries = if old_root.is_zero() {
-        HashMap::new()
-    } else {
-        let trie = MerkleTrie::from_root(store, old_root)?;
-        collect_all_entries(&trie)?
-    };
+/// A position in a stored trie: `consumed` bytes into the prefix (or leaf
+/// suffix) of the node `hash`
+///
+/// Tries holding different keys split their prefixes at different points,
+/// so the walk moves one key byte at a time and two positions are only
+/// equal if they sit at the same point of the same node.
+#[derive(Clone)]
+struct Cursor {
+    hash: Hash,
+    consumed: usize,
+    /// The node, once loaded
+    node: Option<Arc<TrieNode>>,
+}
 
-    let new_entries = if new_root.is_zero() {
-        HashMap::new()
-    } else {
-        let trie = MerkleTrie::from_root(store, new_root)?;
-        collect_all_entries(&trie)?
-    };
+/// The value at a position, and its children by key byte
+type Step = (Option<Hash>, Vec<(u8, Cursor)>);
 
-    let mut diff_entries = Vec::new();
+impl Cursor {
+    fn at(hash: Hash) -> Self {
+        Cursor {
+            hash,
+            consumed: 0,
+            node: None,
+        }
+    }
 
-    // Find all keys
-    let all_keys: HashSet<_> = old_entries.keys().chain(new_entries.keys()).collect();
+    fn same_as(&self, other: &Cursor) -> bool {
+        self.hash == other.hash && self.consumed == other.consumed
+    }
 
-    for key in all_keys {
-        match (old_entries.get(key), new_entries.get(key)) {
-            (None, Some(&new_hash)) => {
-                diff_entries.push(DiffEntry::Added {
-                    key: key.clone(),
-                    new_hash,
-                });
-            }
-            (Some(&old_hash), None) => {
-                diff_entries.push(DiffEntry::Removed {
-                    key: key.clone(),
-                    old_hash,
-                });
+    fn node(&self, store: &dyn Storage) -> Result<Arc<TrieNode>> {
+        match &self.node {
+            Some(node) => Ok(node.clone()),
+            None => load_node(store, &self.hash),
+        }
+    }
+
+    /// The value at this position and the positions one key byte further
+    fn step(&self, store: &dyn Storage) -> Result<Step> {
+        let node = self.node(store)?;
+        let (rest, value, children) = match &*node {
+            TrieNode::Branch {
+                prefix,
+                children,
+                value,
+            } => (&prefix[self.consumed..], *value, Some(children)),
+            TrieNode::Leaf { key_suffix, value } => {
+                (&key_suffix[self.consumed..], Some(*value), None)
             }
-            (Some(&old_hash), Some(&new_hash)) if old_hash != new_hash => {
-                diff_entries.push(DiffEntry::Modified {
-                    key: key.clone(),
+            TrieNode::Empty => return Ok((None, vec![])),
+        };
+
+        if let Some(&next) = rest.first() {
+            let within = Cursor {
+                hash: self.hash,
+                consumed: self.consumed + 1,
+                node: Some(node.clone()),
+            };
+            return Ok((None, vec![(next, within)]));
+        }
+        let children = children
+            .into_iter()
+            .flatten()
+            .map(|(byte, hash)| (*byte, Cursor::at(*hash)))
+            .collect();
+        Ok((value, children))
+    }
+}
+
+/// Diff the subtrees at `path`, either of which may be missing
+fn diff_at(
+    store: &dyn Storage,
+    old: Option<Cursor>,
+    new: Option<Cursor>,
+    path: &mut Vec<u8>,
+    entries: &mut Vec<DiffEntry>,
+) -> Result<()> {
+    let (old, new) = match (old, new) {
+        (Some(old), Some(new)) if old.same_as(&new) => return Ok(()),
+        (Some(old), Some(new)) => (old, new),
+        (Some(old), None) => {
+            return walk(store, &old, path, &mut |key, old_hash| {
+                entries.push(DiffEntry::Removed { key, old_hash })
+            })
+        }
+        (None, Some(new)) => {
+            return walk(store, &new, path, &mut |key, new_hash| {
+                entries.push(DiffEntry::Added { key, new_hash })
+            })
+        }
+        (None, None) => return Ok(()),
+    };
+
+    let (old_value, old_children) = old.step(store)?;
+    let (new_value, new_children) = new.step(store)?;
+    if is_tracked(path) {
+        match (old_value, new_value) {
+            (Some(old_hash), None) => entries.push(DiffEntry::Removed {
+                key: path.clone(),
+                old_hash,
+            }),
+            (None, Some(new_hash)) => entries.push(DiffEntry::Added {
+                key: path.clone(),
+                new_hash,
+            }),
+            (Some(old_hash), Some(new_hash)) if old_hash != new_hash => {
+                entries.push(DiffEntry::Modified {
+                    key: path.clone(),
                     old_hash,
                     new_hash,
-                });
+                })
             }
-            _ => {} // Unchanged
+            _ => {}
         }
     }
 
-    // Sort entries for determinism
-    diff_entries.sort_by(|a, b| a.key().cmp(b.key()));
-
-    Ok(Diff::new(diff_entries))
-}
-
-fn collect_all_entries(trie: &MerkleTrie) -> Result<HashMap<Vec<u8>, Hash>> {
-    let mut entries = HashMap::new();
-
-    // Collect thoughts
-    for (key, hash) in trie.list_prefix(b"t:")? {
-        entries.insert(key, hash);
+    let mut children: BTreeMap<u8, (Option<Cursor>, Option<Cursor>)> = BTreeMap::new();
+    for (byte, cursor) in old_children {
+        children.entry(byte).or_default().0 = Some(cursor);
+    }
+    for (byte, cursor) in new_children {
+        children.entry(byte).or_default().1 = Some(cursor);
     }
+    for (byte, (old, new)) in children {
+        path.push(byte);
+        diff_at(store, old, new, path, entries)?;
+        path.pop();
+    }
+    Ok(())
+}
 
-    // Collect edges
-    for (key, hash) in trie.list_prefix(b"e:")? {
-        entries.insert(key, hash);
+/// Visit every tracked key under a position that only one side has
+fn walk(
+    store: &dyn Storage,
+    cursor: &Cursor,
+    path: &mut Vec<u8>,
+    visit: &mut impl FnMut(Vec<u8>, Hash),
+) -> Result<()> {
+    let len = path.len();
+    match &*cursor.node(store)? {
+        TrieNode::Branch {
+            prefix,
+            children,
+            value,
+        } => {
+            path.extend(&prefix[cursor.consumed..]);
+            if let Some(value) = value {
+                if is_tracked(path) {
+                    visit(path.clone(), *value);
+                }
+            }
+            for (byte, hash) in children {
+                path.push(*byte);
+                walk(store, &Cursor::at(*hash), path, visit)?;
+                path.pop();
+            }
+        }
+        TrieNode::Leaf { key_suffix, value } => {
+            path.extend(&key_suffix[cursor.consumed..]);
+            if is_tracked(path) {
+                visit(path.clone(), *value);
+            }
+        }
+        TrieNode::Empty => {}
     }
+    path.truncate(len);
+    Ok(())
+}
 
-    Ok(entries)
+/// Thought and edge keys; anything else in a tree is not part of a diff
+fn is_tracked(key: &[u8]) -> bool {
+    key.starts_with(b"t:") || key.starts_with(b"e:")
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::model::Thought;
+    use crate::store::ObjectStore;
     use crate::trie::MerkleTrie;
     use tempfile::tempdir;
 
@@ -231,4 +347,94 @@ mod tests {
 
         assert!(diff.is_empty());
     }
+
+    /// Entries of the brute-force diff the structural one must match
+    fn diff_by_listing(store: &dyn Storage, old: Hash, new: Hash) -> Vec<DiffEntry> {
+        let list = |root: Hash| -> BTreeMap<Vec<u8>, Hash> {
+            let trie = MerkleTrie::from_root(store, root).unwrap();
+            let mut entries = trie.list_prefix(b"t:").unwrap();
+            entries.extend(trie.list_prefix(b"e:").unwrap());
+            entries.into_iter().collect()
+        };
+        let (old, new) = (list(old), list(new));
+        let mut keys: Vec<_> = old.keys().chain(new.keys()).cloned().collect();
+        keys.sort();
+        keys.dedup();
+        keys.into_iter()
+            .filter_map(|key| match (old.get(&key), new.get(&key)) {
+                (None, Some(&new_hash)) => Some(DiffEntry::Added { key, new_hash }),
+                (Some(&old_hash), None) => Some(DiffEntry::Removed { key, old_hash }),
+                (Some(&old_hash), Some(&new_hash)) if old_hash != new_hash => {
+                    Some(DiffEntry::Modified {
+                        key,
+                        old_hash,
+                        new_hash,
+                    })
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_diff_matches_listing_across_shapes() {
+        let store = crate::store::MemoryStore::new();
+        // Keys that are prefixes of each other, so the two tries split
+        // their prefixes at different points
+        let keys: Vec<Vec<u8>> = ["t:a", "t:ab", "t:abc", "t:abd", "t:b", "e:a:b", "x:other"]
+            .iter()
+            .map(|k| k.as_bytes().to_vec())
+            .collect();
+
+        // Every pair of subsets, with some values changed on the new side
+        for old_mask in 0..(1u32 << keys.len()) {
+            for new_mask in (0..(1u32 << keys.len())).step_by(7) {
+                let build = |mask: u32, salt: u8| {
+                    let mut trie = MerkleTrie::new(&store);
+                    for (i, key) in keys.iter().enumerate() {
+                        if mask & (1 << i) != 0 {
+                            let value = Hash::digest(&[i as u8, salt * (i as u8 % 2)]);
+                            trie.insert(key, value).unwrap();
+                        }
+                    }
+                    trie.commit().unwrap()
+                };
+                let old = build(old_mask, 0);
+                let new = build(new_mask, 1);
+                assert_eq!(
+                    diff_trees(&store, old, new).unwrap().entries,
+                    diff_by_listing(&store, old, new),
+                    "{:b} -> {:b}",
+                    old_mask,
+                    new_mask
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_diff_skips_shared_subtrees() {
+        let store = crate::store::MemoryStore::new();
+        let mut trie = MerkleTrie::new(&store);
+        for i in 0..2000 {
+            let key = format!("t:thought-{}", i);
+            trie.insert(key.as_bytes(), Hash::digest(key.as_bytes()))
+                .unwrap();
+        }
+        let old = trie.commit().unwrap();
+        trie.insert(b"t:thought-1234", Hash::digest(b"changed"))
+            .unwrap();
+        let new = trie.commit().unwrap();
+
+        let cache = store.node_cache().unwrap();
+        let before = cache.stats();
+        let diff = diff_trees(&store, old, new).unwrap();
+        let after = cache.stats();
+        assert_eq!(diff.modified_count(), 1);
+        assert_eq!(diff.entries.len(), 1);
+
+        // Only the nodes along the changed path are read
+        let loads = (after.hits + after.misses) - (before.hits + before.misses);
+        assert!(loads < 40, "{} nodes loaded", loads);
+    }
 }
@@ -0,0 +1,571 @@
+//! Integrity verification: objects, trie structure and refs
+//!
+//! `fsck` runs in two passes:
+//! 1. every index entry is read, decompressed and re-hashed through
+//!    `Blob::hash`, which also covers the blob type byte;
+//! 2. everything reachable from the refs is walked: commits and their
+//!    parents, trie nodes and their children, the thought/edge blobs the
+//!    `t:`/`e:` keys point at, and the chunks of thoughts' attachments.
+//!
+//! Finally the tree at the tip of each ref is checked for edges whose
+//! endpoints are not in that tree. `Database::delete_thought` leaves a
+//! thought's edges in place, so these are reported as warnings rather than
+//! problems: the store is intact, the graph just has loose ends. Every
+//! finding is collected rather than stopping at the first one.
+
+use crate::model::{Commit, Edge, Hash, Thought, ThoughtId};
+use crate::store::{attachment_chunks, Blob, BlobType, Head, Storage, HEAD_REF};
+use crate::trie::{MerkleTrie, TrieNode};
+use crate::{Error, Result};
+use serde::Serialize;
+use std::collections::HashSet;
+
+/// A single integrity problem
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Problem {
+    /// An index entry could not be read or decompressed
+    Unreadable {
+        hash: String,
+        /// Where the damaged bytes start, if the store could tell
+        #[serde(skip_serializing_if = "Option::is_none")]
+        offset: Option<u64>,
+        error: String,
+    },
+    /// An object's content hashes to something other than its index key
+    HashMismatch { hash: String, actual: String },
+    /// An object could not be decoded as its blob type
+    Undecodable {
+        hash: String,
+        blob_type: String,
+        error: String,
+    },
+    /// A referenced object is not in the store
+    Missing {
+        hash: String,
+        referenced_by: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        key: Option<String>,
+    },
+    /// A referenced object has the wrong blob type
+    WrongType {
+        hash: String,
+        referenced_by: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        key: Option<String>,
+        expected: String,
+        found: String,
+    },
+    /// A trie key outside the `t:`/`e:` namespaces
+    UnexpectedKey { key: String, referenced_by: String },
+    /// An edge whose source or target is not in the same tree
+    DanglingEdge {
+        branch: String,
+        key: String,
+        missing: String,
+    },
+}
+
+/// Outcome of an integrity check
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct FsckReport {
+    /// Index entries verified
+    pub objects_checked: usize,
+    /// Commits reached from the refs
+    pub commits_checked: usize,
+    /// Trie nodes reached from those commits
+    pub trees_checked: usize,
+    /// Everything found to be wrong
+    pub problems: Vec<Problem>,
+    /// Graph inconsistencies the API can create on its own, such as
+    /// dangling edges; they do not count against `is_ok`
+    pub warnings: Vec<Problem>,
+    /// Bad index entries dropped by `repair`
+    pub repaired: usize,
+}
+
+impl FsckReport {
+    /// Whether no problems were found, warnings aside
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Verify the whole store
+///
+/// With `repair`, index entries that fail to decompress or re-hash are
+/// dropped and the store is synced. Objects referencing them are still
+/// reported as pointing at missing objects; nothing else is changed.
+pub fn fsck(store: &dyn Storage, repair: bool) -> Result<FsckReport> {
+    let mut checker = Checker {
+        store,
+        bad: HashSet::new(),
+        seen: HashSet::new(),
+        report: FsckReport::default(),
+    };
+
+    checker.check_objects();
+
+    let mut refs = store.list_refs();
+    refs.sort_by(|a, b| a.0.cmp(&b.0));
+    if let Head::Detached(hash) = store.head() {
+        refs.push((HEAD_REF.to_string(), hash));
+    }
+    let mut tips = Vec::new();
+    for (name, hash) in &refs {
+        if hash.is_zero() {
+            continue;
+        }
+        checker.check_history(*hash, name);
+        if let Ok(commit) = store.get_commit(hash) {
+            tips.push((name.clone(), commit.tree));
+        }
+    }
+
+    // Garbage objects could decode as plausible trie nodes, so only look
+    // for dangling edges in an otherwise readable store
+    if checker.bad.is_empty() {
+        let mut checked_trees = HashSet::new();
+        for (name, tree) in tips {
+            if !tree.is_zero() && checked_trees.insert(tree) {
+                checker.check_edges(&name, tree);
+            }
+        }
+    }
+
+    if repair && !checker.bad.is_empty() {
+        let bad: Vec<Hash> = checker.bad.iter().copied().collect();
+        checker.report.repaired = store.remove_objects(&bad)?;
+        store.sync()?;
+    }
+
+    Ok(checker.report)
+}
+
+struct Checker<'a> {
+    store: &'a dyn Storage,
+    /// Objects that failed to read or re-hash
+    bad: HashSet<Hash>,
+    /// Reachable objects already walked
+    seen: HashSet<Hash>,
+    report: FsckReport,
+}
+
+impl Checker<'_> {
+    /// Pass 1: every index entry decompresses and hashes to its key
+    fn check_objects(&mut self) {
+        let mut hashes = self.store.hashes();
+        hashes.sort_by_key(|h| *h.as_bytes());
+
+        for hash in hashes {
+            self.report.objects_checked += 1;
+            match self.store.get(&hash) {
+                Ok(blob) => {
+                    let actual = blob.hash();
+                    if actual != hash {
+                        self.bad.insert(hash);
+                        self.report.problems.push(Problem::HashMismatch {
+                            hash: hash.to_hex(),
+                            actual: actual.to_hex(),
+                        });
+                    }
+                }
+                Err(Error::CorruptObject { offset, reason, .. }) => {
+                    self.bad.insert(hash);
+                    self.report.problems.push(Problem::Unreadable {
+                        hash: hash.to_hex(),
+                        offset: Some(offset),
+                        error: reason,
+                    });
+                }
+                Err(e) => {
+                    self.bad.insert(hash);
+                    self.report.problems.push(Problem::Unreadable {
+                        hash: hash.to_hex(),
+                        offset: None,
+                        error: e.to_string(),
+                    });
+                }
+            }
+        }
+    }
+
+    /// Walk a ref's commit chain and every tree along it
+    fn check_history(&mut self, tip: Hash, ref_name: &str) {
+        let mut stack = vec![(tip, format!("ref:{}", ref_name))];
+        while let Some((hash, referenced_by)) = stack.pop() {
+            if !self.seen.insert(hash) {
+                continue;
+            }
+            let Some(commit) = self.load_commit(hash, &referenced_by) else {
+                continue;
+            };
+            self.report.commits_checked += 1;
+
+            if !commit.tree.is_zero() {
+                self.check_tree(commit.tree, hash.to_hex());
+            }
+            for parent in commit.parents {
+                stack.push((parent, hash.to_hex()));
+            }
+        }
+    }
+
+    /// Walk a trie from `root`, checking node and leaf types
+    fn check_tree(&mut self, root: Hash, referenced_by: String) {
+        let mut stack = vec![(root, referenced_by, Vec::new())];
+        while let Some((hash, referenced_by, key)) = stack.pop() {
+            if !self.seen.insert(hash) {
+                continue;
+            }
+            let Some(blob) = self.load(hash, &referenced_by, None, BlobType::Tree) else {
+                continue;
+            };
+            let Some(node) = self.decode::<TrieNode>(hash, &blob) else {
+                continue;
+            };
+            self.report.trees_checked += 1;
+
+            match node {
+                TrieNode::Empty => {}
+                TrieNode::Leaf { key_suffix, value } => {
+                    let mut full_key = key;
+                    full_key.extend(key_suffix);
+                    self.check_leaf(&full_key, value, hash);
+                }
+                TrieNode::Branch {
+                    prefix,
+                    children,
+                    value,
+                } => {
+                    let mut current = key;
+                    current.extend(prefix);
+                    if let Some(value) = value {
+                        self.check_leaf(&current, value, hash);
+                    }
+                    for (byte, child) in children {
+                        let mut child_key = current.clone();
+                        child_key.push(byte);
+                        stack.push((child, hash.to_hex(), child_key));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Check that a trie value has the blob type its key namespace implies
+    fn check_leaf(&mut self, key: &[u8], value: Hash, node: Hash) {
+        let key_str = String::from_utf8_lossy(key).to_string();
+        let expected = if key.starts_with(b"t:") {
+            BlobType::Thought
+        } else if key.starts_with(b"e:") {
+            BlobType::Edge
+        } else {
+            self.report.problems.push(Problem::UnexpectedKey {
+                key: key_str,
+                referenced_by: node.to_hex(),
+            });
+            return;
+        };
+
+        if !self.seen.insert(value) {
+            return;
+        }
+        let Some(blob) = self.load(value, &node.to_hex(), Some(key_str), expected) else {
+            return;
+        };
+        if expected == BlobType::Thought {
+            if let Some(thought) = self.decode::<Thought>(value, &blob) {
+                for attachment in thought.attachments {
+                    self.check_attachment(attachment.hash, value);
+                }
+            }
+        } else {
+            self.decode::<Edge>(value, &blob);
+        }
+    }
+
+    /// Check that an attachment and all of its chunks are present
+    fn check_attachment(&mut self, root: Hash, thought: Hash) {
+        let mut stack = vec![(root, thought.to_hex())];
+        while let Some((hash, referenced_by)) = stack.pop() {
+            if !self.seen.insert(hash) {
+                continue;
+            }
+            let Some(blob) = self.load(hash, &referenced_by, None, BlobType::Attachment) else {
+                continue;
+            };
+            match attachment_chunks(&blob) {
+                Ok(chunks) => stack.extend(
+                    chunks
+                        .unwrap_or_default()
+                        .into_iter()
+                        .map(|c| (c, hash.to_hex())),
+                ),
+                Err(e) => self.report.problems.push(Problem::Undecodable {
+                    hash: hash.to_hex(),
+                    blob_type: format!("{:?}", blob.blob_type),
+                    error: e.to_string(),
+                }),
+            }
+        }
+    }
+
+    /// Check that every edge in the tree has both endpoints in it
+    fn check_edges(&mut self, branch: &str, tree: Hash) {
+        let Ok(trie) = MerkleTrie::from_root(self.store, tree) else {
+            return;
+        };
+        let (Ok(thoughts), Ok(edges)) = (trie.list_prefix(b"t:"), trie.list_prefix(b"e:")) else {
+            return;
+        };
+
+        let ids: HashSet<Vec<u8>> = thoughts.into_iter().map(|(key, _)| key).collect();
+        for (key, hash) in edges {
+            let Ok(edge) = self.store.get_edge(&hash) else {
+                continue;
+            };
+            for endpoint in [&edge.source, &edge.target] {
+                if !ids.contains(&thought_key(endpoint)) {
+                    self.report.warnings.push(Problem::DanglingEdge {
+                        branch: branch.to_string(),
+                        key: String::from_utf8_lossy(&key).to_string(),
+                        missing: endpoint.0.clone(),
+                    });
+                }
+            }
+        }
+    }
+
+    fn load_commit(&mut self, hash: Hash, referenced_by: &str) -> Option<Commit> {
+        let blob = self.load(hash, referenced_by, None, BlobType::Commit)?;
+        self.decode(hash, &blob)
+    }
+
+    /// Fetch a referenced object, reporting it if missing or mistyped
+    ///
+    /// Objects that already failed pass 1 are skipped silently.
+    fn load(
+        &mut self,
+        hash: Hash,
+        referenced_by: &str,
+        key: Option<String>,
+        expected: BlobType,
+    ) -> Option<Blob> {
+        if self.bad.contains(&hash) {
+            return None;
+        }
+        if !self.store.contains(&hash) {
+            self.report.problems.push(Problem::Missing {
+                hash: hash.to_hex(),
+                referenced_by: referenced_by.to_string(),
+                key,
+            });
+            return None;
+        }
+
+        let blob = self.store.get(&hash).ok()?;
+        if blob.blob_type != expected {
+            self.report.problems.push(Problem::WrongType {
+                hash: hash.to_hex(),
+                referenced_by: referenced_by.to_string(),
+                key,
+                expected: format!("{:?}", expected),
+                found: format!("{:?}", blob.blob_type),
+            });
+            return None;
+        }
+        Some(blob)
+    }
+
+    fn decode<T: serde::de::DeserializeOwned>(&mut self, hash: Hash, blob: &Blob) -> Option<T> {
+        match bincode::deserialize(&blob.data) {
+            Ok(value) => Some(value),
+            Err(e) => {
+                self.report.problems.push(Problem::Undecodable {
+                    hash: hash.to_hex(),
+                    blob_type: format!("{:?}", blob.blob_type),
+                    error: e.to_string(),
+                });
+                None
+            }
+        }
+    }
+}
+
+/// Trie key of a thought
+fn thought_key(id: &ThoughtId) -> Vec<u8> {
+    format!("t:{}", id.0).into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::EdgeType;
+    use crate::ops::BranchManager;
+    use crate::store::ObjectStore;
+    use tempfile::tempdir;
+
+    fn setup() -> (tempfile::TempDir, ObjectStore) {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.indra");
+        let store = ObjectStore::create(&path).unwrap();
+        (dir, store)
+    }
+
+    /// Commit a tree holding the given keys on the current branch
+    fn commit_keys(store: &ObjectStore, entries: &[(&str, Hash)]) -> Hash {
+        let mut trie = MerkleTrie::new(store);
+        for (key, hash) in entries {
+            trie.insert(key.as_bytes(), *hash).unwrap();
+        }
+        let tree = trie.commit().unwrap();
+        BranchManager::new(store)
+            .commit(tree, "test", "test")
+            .unwrap()
+    }
+
+    #[test]
+    fn test_fsck_clean_store() {
+        let (_dir, store) = setup();
+        let a = store.put_thought(&Thought::with_id("a", "Alpha")).unwrap();
+        let b = store.put_thought(&Thought::with_id("b", "Beta")).unwrap();
+        let edge = store
+            .put_edge(&Edge::new("a", "b", EdgeType::RELATES_TO))
+            .unwrap();
+        commit_keys(&store, &[("t:a", a)]);
+        commit_keys(
+            &store,
+            &[("t:a", a), ("t:b", b), ("e:a:b:relates_to", edge)],
+        );
+
+        let report = fsck(&store, false).unwrap();
+        assert!(report.is_ok(), "{:?}", report.problems);
+        assert_eq!(report.objects_checked, store.object_count());
+        assert_eq!(report.commits_checked, 2);
+        assert!(report.trees_checked > 0);
+    }
+
+    #[test]
+    fn test_fsck_detects_and_repairs_corrupt_object() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.indra");
+        let thought = Thought::with_id("a", "Some content worth corrupting");
+        let hash = {
+            let store = ObjectStore::create(&path).unwrap();
+            let hash = store.put_thought(&thought).unwrap();
+            commit_keys(&store, &[("t:a", hash)]);
+            hash
+        };
+
+        // Flip a byte inside the stored thought
+        let stored = Blob::new(BlobType::Thought, bincode::serialize(&thought).unwrap())
+            .compress()
+            .unwrap();
+        let mut bytes = std::fs::read(&path).unwrap();
+        let pos = bytes
+            .windows(stored.len())
+            .position(|w| w == stored.as_slice())
+            .unwrap();
+        bytes[pos + stored.len() / 2] ^= 0xff;
+        std::fs::write(&path, &bytes).unwrap();
+
+        let store = ObjectStore::open(&path).unwrap();
+        let report = fsck(&store, true).unwrap();
+        assert!(report.problems.iter().any(|p| matches!(
+            p,
+            Problem::Unreadable { hash: h, offset: Some(at), .. }
+                if *h == hash.to_hex() && *at == pos as u64
+        )));
+        assert_eq!(report.repaired, 1);
+        assert!(!store.contains(&hash));
+        drop(store);
+
+        // After repair the entry is gone and the tree points at nothing
+        let store = ObjectStore::open(&path).unwrap();
+        let report = fsck(&store, false).unwrap();
+        assert!(matches!(
+            &report.problems[..],
+            [Problem::Missing { hash: h, key: Some(key), .. }]
+                if *h == hash.to_hex() && key == "t:a"
+        ));
+    }
+
+    #[test]
+    fn test_fsck_wrong_leaf_type() {
+        let (_dir, store) = setup();
+        let edge = store
+            .put_edge(&Edge::new("a", "b", EdgeType::RELATES_TO))
+            .unwrap();
+        commit_keys(&store, &[("t:a", edge)]);
+
+        let report = fsck(&store, false).unwrap();
+        assert!(matches!(
+            &report.problems[..],
+            [Problem::WrongType { key: Some(key), expected, found, .. }]
+                if key == "t:a" && expected == "Thought" && found == "Edge"
+        ));
+    }
+
+    #[test]
+    fn test_fsck_broken_ref_and_parent() {
+        let (_dir, store) = setup();
+        let ghost = Hash::digest(b"no such commit");
+        store.set_ref("ghost", ghost, "test").unwrap();
+
+        let orphan = Commit::child(Hash::ZERO, ghost, "orphan", "test");
+        let orphan_hash = store.put_commit(&orphan).unwrap();
+        store.set_ref("main", orphan_hash, "test").unwrap();
+
+        let report = fsck(&store, false).unwrap();
+        assert!(report.problems.contains(&Problem::Missing {
+            hash: ghost.to_hex(),
+            referenced_by: "ref:ghost".to_string(),
+            key: None,
+        }));
+        // The parent chain from main reaches the same missing commit, but
+        // each missing object is reported once
+        assert_eq!(report.problems.len(), 1);
+    }
+
+    #[test]
+    fn test_fsck_dangling_edge() {
+        let (_dir, store) = setup();
+        let a = store.put_thought(&Thought::with_id("a", "Alpha")).unwrap();
+        let edge = store
+            .put_edge(&Edge::new("a", "b", EdgeType::RELATES_TO))
+            .unwrap();
+        commit_keys(&store, &[("t:a", a), ("e:a:b:relates_to", edge)]);
+
+        let report = fsck(&store, false).unwrap();
+        assert!(report.is_ok());
+        assert_eq!(
+            report.warnings,
+            vec![Problem::DanglingEdge {
+                branch: "main".to_string(),
+                key: "e:a:b:relates_to".to_string(),
+                missing: "b".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_fsck_after_deleting_a_related_thought() {
+        let mut db = crate::Database::in_memory();
+        db.create_thought_with_id("a", "Alpha").unwrap();
+        db.create_thought_with_id("b", "Beta").unwrap();
+        db.relate("a", "b", EdgeType::RELATES_TO).unwrap();
+        db.commit("Related").unwrap();
+        db.delete_thought(&"b".into()).unwrap();
+        db.commit("Deleted").unwrap();
+
+        // The edge is left behind, which is a loose end, not corruption
+        let report = db.fsck(false).unwrap();
+        assert!(report.is_ok(), "{:?}", report.problems);
+        assert_eq!(report.warnings.len(), 1);
+        assert!(matches!(
+            &report.warnings[0],
+            Problem::DanglingEdge { missing, .. } if missing == "b"
+        ));
+    }
+}
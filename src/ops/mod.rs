@@ -1,7 +1,16 @@
-//! Git-like operations: branch, checkout, diff, merge
+//! Git-like operations: branch, checkout, diff, merge, fsck, stats, backup,
+//! bundles
 
+mod backup;
 mod branch;
+mod bundle;
 mod diff;
+mod fsck;
+mod stats;
 
+pub use backup::{backup, BackupReport};
 pub use branch::{checkout, BranchManager};
+pub use bundle::{create_bundle, unbundle, BundleReport};
 pub use diff::{diff_trees, Diff, DiffEntry};
+pub use fsck::{fsck, FsckReport, Problem};
+pub use stats::{stats, BranchStats, EmbeddingStats, StoreStats, TypeStats};
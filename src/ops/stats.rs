@@ -0,0 +1,293 @@
+//! Store statistics: what a database holds and where its bytes go
+//!
+//! `stats` reads every object once to size it by blob type, then walks what
+//! the refs reach: each branch's commit chain and tip tree, and the thoughts
+//! in those trees for embedding details. Objects nothing reaches are dead
+//! space that `gc` would reclaim, and so is everything else in the file
+//! besides the live objects and the commit records the current state is
+//! read from: superseded records and segments, a legacy index, the tail of
+//! a torn write.
+
+use crate::model::{Commit, Hash, Thought};
+use crate::store::{reachable_objects, Head, Storage, HEAD_REF};
+use crate::trie::{MerkleTrie, TrieNode};
+use crate::Result;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+
+/// Statistics about a whole store
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct StoreStats {
+    /// Objects by blob type name
+    pub objects: BTreeMap<String, TypeStats>,
+    /// Objects that could not be read
+    pub unreadable: usize,
+    /// Per ref, plus a detached HEAD
+    pub branches: Vec<BranchStats>,
+    /// Embeddings of the thoughts on any branch, by model and dimension
+    pub embeddings: Vec<EmbeddingStats>,
+    /// Objects not reachable from any ref
+    pub unreachable_objects: usize,
+    /// Stored bytes of those objects
+    pub unreachable_bytes: u64,
+    /// Size of the database file, for stores kept in one
+    pub file_bytes: Option<u64>,
+    /// Bytes of the file that `gc` would reclaim: the file size minus the
+    /// live objects, the header and the current commit records
+    pub reclaimable_bytes: Option<u64>,
+    /// Commits reachable from any ref
+    pub commits: usize,
+    /// Those commits by author
+    pub commits_by_author: BTreeMap<String, usize>,
+}
+
+impl StoreStats {
+    /// Objects of every type
+    pub fn object_count(&self) -> usize {
+        self.objects.values().map(|t| t.count).sum()
+    }
+
+    /// Stored bytes of every type
+    pub fn stored_bytes(&self) -> u64 {
+        self.objects.values().map(|t| t.stored_bytes).sum()
+    }
+
+    /// Uncompressed bytes of every type
+    pub fn uncompressed_bytes(&self) -> u64 {
+        self.objects.values().map(|t| t.uncompressed_bytes).sum()
+    }
+}
+
+/// Objects of one blob type
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct TypeStats {
+    pub count: usize,
+    /// Bytes the backend stores, after compression
+    pub stored_bytes: u64,
+    /// Decoded object sizes
+    pub uncompressed_bytes: u64,
+}
+
+/// The tree at the tip of one ref
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct BranchStats {
+    pub name: String,
+    /// Commit the ref points at
+    pub commit: String,
+    pub thoughts: usize,
+    pub edges: usize,
+    /// Nodes in the tip tree
+    pub trie_nodes: usize,
+    /// Nodes on the longest root-to-leaf path, 0 for an empty tree
+    pub trie_depth: usize,
+}
+
+/// Thoughts sharing an embedding model and dimension
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct EmbeddingStats {
+    /// The `embedder_model` attribute, if set
+    pub model: Option<String>,
+    pub dimension: usize,
+    pub thoughts: usize,
+}
+
+/// Gather statistics about the whole store
+pub fn stats(store: &dyn Storage) -> Result<StoreStats> {
+    let mut stats = StoreStats::default();
+
+    for hash in store.hashes() {
+        let Ok(blob) = store.get(&hash) else {
+            stats.unreadable += 1;
+            continue;
+        };
+        let entry = stats
+            .objects
+            .entry(format!("{:?}", blob.blob_type))
+            .or_default();
+        entry.count += 1;
+        entry.stored_bytes += store.stored_size(&hash).unwrap_or(0);
+        entry.uncompressed_bytes += blob.size() as u64;
+    }
+
+    let mut refs = store.list_refs();
+    refs.sort_by(|a, b| a.0.cmp(&b.0));
+    if let Head::Detached(hash) = store.head() {
+        refs.push((HEAD_REF.to_string(), hash));
+    }
+    refs.retain(|(_, hash)| !hash.is_zero());
+
+    let mut thoughts = HashSet::new();
+    for (name, hash) in &refs {
+        let tree = store.get_commit(hash)?.tree;
+        let trie = MerkleTrie::from_root(store, tree)?;
+        let branch_thoughts = trie.list_prefix(b"t:")?;
+        let (trie_nodes, trie_depth) = trie_shape(store, tree)?;
+        stats.branches.push(BranchStats {
+            name: name.clone(),
+            commit: hash.to_hex(),
+            thoughts: branch_thoughts.len(),
+            edges: trie.list_prefix(b"e:")?.len(),
+            trie_nodes,
+            trie_depth,
+        });
+        thoughts.extend(branch_thoughts.into_iter().map(|(_, hash)| hash));
+    }
+
+    let mut embeddings: BTreeMap<(Option<String>, usize), usize> = BTreeMap::new();
+    for hash in thoughts {
+        let thought: Thought = store.get_thought(&hash)?;
+        if let Some(embedding) = &thought.embedding {
+            let model = thought
+                .get_attr("embedder_model")
+                .and_then(|v| v.as_str())
+                .map(str::to_string);
+            *embeddings.entry((model, embedding.len())).or_default() += 1;
+        }
+    }
+    stats.embeddings = embeddings
+        .into_iter()
+        .map(|((model, dimension), thoughts)| EmbeddingStats {
+            model,
+            dimension,
+            thoughts,
+        })
+        .collect();
+
+    let tips: Vec<Hash> = refs.iter().map(|(_, hash)| *hash).collect();
+    let mut seen = HashSet::new();
+    let mut stack = tips.clone();
+    while let Some(hash) = stack.pop() {
+        if !seen.insert(hash) {
+            continue;
+        }
+        let commit: Commit = store.get_commit(&hash)?;
+        *stats.commits_by_author.entry(commit.author).or_default() += 1;
+        stack.extend(commit.parents.into_iter().filter(|p| !p.is_zero()));
+    }
+    stats.commits = seen.len();
+
+    let live = reachable_objects(store, tips)?;
+    let mut live_bytes = 0;
+    for hash in store.hashes() {
+        let size = store.stored_size(&hash).unwrap_or(0);
+        if live.contains(&hash) {
+            live_bytes += size;
+        } else {
+            stats.unreachable_objects += 1;
+            stats.unreachable_bytes += size;
+        }
+    }
+    if let Some(usage) = store.file_usage()? {
+        stats.file_bytes = Some(usage.file_bytes);
+        stats.reclaimable_bytes = Some(
+            usage
+                .file_bytes
+                .saturating_sub(usage.metadata_bytes + live_bytes),
+        );
+    }
+
+    Ok(stats)
+}
+
+/// Node count and depth of the trie at `root`
+fn trie_shape(store: &dyn Storage, root: Hash) -> Result<(usize, usize)> {
+    if root.is_zero() {
+        return Ok((0, 0));
+    }
+    let mut nodes = 0;
+    let mut depth = 0;
+    let mut stack = vec![(root, 1)];
+    while let Some((hash, level)) = stack.pop() {
+        let blob = store.get(&hash)?;
+        let node: TrieNode = bincode::deserialize(&blob.data)?;
+        nodes += 1;
+        depth = depth.max(level);
+        if let TrieNode::Branch { children, .. } = node {
+            stack.extend(children.into_values().map(|child| (child, level + 1)));
+        }
+    }
+    Ok((nodes, depth))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::embedding::MockEmbedder;
+    use crate::Database;
+    use crate::ObjectStore;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_stats() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.indra");
+        {
+            let mut db = Database::create(&path)
+                .unwrap()
+                .with_embedder(MockEmbedder::new(8));
+            db.create_thought_with_id("a", "Alpha").unwrap();
+            db.create_thought_with_id("b", "Beta").unwrap();
+            db.relate("a", "b", crate::EdgeType::RELATES_TO).unwrap();
+            db.commit_with_author("First", "ada").unwrap();
+
+            db.create_branch("scratch").unwrap();
+            db.checkout("scratch").unwrap();
+            db.create_thought_with_id("c", "Gamma").unwrap();
+            db.commit_with_author("Second", "grace").unwrap();
+            db.checkout("main").unwrap();
+            db.update_thought(&"a".into(), "Alpha, revised").unwrap();
+            db.commit_with_author("Third", "ada").unwrap();
+            db.sync().unwrap();
+        }
+
+        let store = ObjectStore::open(&path).unwrap();
+        let stats = stats(&store).unwrap();
+        assert_eq!(stats.object_count(), store.object_count());
+        assert_eq!(stats.objects["Commit"].count, 3);
+        assert_eq!(stats.objects["Thought"].count, 4);
+        assert_eq!(stats.objects["Edge"].count, 1);
+        assert!(stats.stored_bytes() > 0);
+        assert_eq!(stats.unreadable, 0);
+
+        let main = stats.branches.iter().find(|b| b.name == "main").unwrap();
+        assert_eq!((main.thoughts, main.edges), (2, 1));
+        assert!(main.trie_nodes > 0 && main.trie_depth > 0);
+        let scratch = stats.branches.iter().find(|b| b.name == "scratch").unwrap();
+        assert_eq!((scratch.thoughts, scratch.edges), (3, 1));
+
+        // a (twice), b and c, all embedded by the same model
+        assert_eq!(stats.embeddings.len(), 1);
+        assert_eq!(stats.embeddings[0].dimension, 8);
+        assert_eq!(stats.embeddings[0].thoughts, 4);
+
+        assert_eq!(stats.commits, 3);
+        assert_eq!(stats.commits_by_author["ada"], 2);
+        assert_eq!(stats.commits_by_author["grace"], 1);
+
+        // Everything is on some branch until scratch goes away
+        assert_eq!(stats.unreachable_objects, 0);
+        store.delete_branch("scratch").unwrap();
+        let stats = super::stats(&store).unwrap();
+        assert!(stats.unreachable_objects > 0);
+        assert!(stats.unreachable_bytes > 0);
+        assert_eq!(stats.commits, 2);
+
+        // Records superseded as the index is rewritten are dead space too,
+        // and gc leaves nothing reclaimable
+        for i in 0..20 {
+            store
+                .put_thought(&Thought::new(format!("Loose {}", i)))
+                .unwrap();
+            store.sync().unwrap();
+        }
+        let stats = super::stats(&store).unwrap();
+        assert!(stats.reclaimable_bytes.unwrap() > stats.unreachable_bytes);
+        let gc = store.gc(&[]).unwrap();
+        let stats = super::stats(&store).unwrap();
+        assert_eq!(stats.file_bytes, Some(gc.bytes_after));
+        assert_eq!(stats.reclaimable_bytes, Some(0));
+
+        let memory = super::stats(&crate::MemoryStore::new()).unwrap();
+        assert_eq!(memory.reclaimable_bytes, None);
+    }
+}
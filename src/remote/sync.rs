@@ -3,6 +3,7 @@
 //! Handles push/pull operations with the remote API.
 
 use crate::remote::{CredentialStore, Remote};
+use crate::store::{FileLock, StoreOptions};
 use crate::{Error, Result};
 use std::path::Path;
 
@@ -187,6 +188,7 @@ impl SyncState {
 pub struct SyncClient {
     config: SyncConfig,
     client: reqwest::blocking::Client,
+    store: StoreOptions,
 }
 
 #[cfg(feature = "sync")]
@@ -198,7 +200,18 @@ impl SyncClient {
             .build()
             .map_err(|e| Error::Http(e.to_string()))?;
 
-        Ok(SyncClient { config, client })
+        Ok(SyncClient {
+            config,
+            client,
+            store: StoreOptions::default(),
+        })
+    }
+
+    /// Open and lock local databases with these options, so that pushes and
+    /// pulls honour the caller's key and lock timeout
+    pub fn with_store_options(mut self, options: StoreOptions) -> Self {
+        self.store = options;
+        self
     }
 
     /// Create a sync client from environment
@@ -421,7 +434,8 @@ impl SyncClient {
         }
 
         // Open the database and get HEAD
-        let db = crate::Database::open(db_path)?;
+        let options = self.store.clone().with_read_only(true);
+        let db = crate::Database::open_with_options(db_path, options)?;
         let log = db.log(Some(1))?;
 
         Ok(log.first().map(|(h, _)| h.to_hex()))
@@ -461,8 +475,11 @@ impl SyncClient {
         // Get local head hash to send with request
         let local_head = self.get_local_head(db_path)?;
 
-        // Read the database file
-        let data = std::fs::read(db_path).map_err(Error::Io)?;
+        // Read the database file, keeping writers out while copying it
+        let data = {
+            let _lock = FileLock::acquire(db_path, false, self.store.lock_timeout)?;
+            std::fs::read(db_path).map_err(Error::Io)?
+        };
 
         // Ensure the base exists (or create it)
         let base_id = self.ensure_base(remote)?;
@@ -530,7 +547,8 @@ impl SyncClient {
 
         let size = bytes.len() as u64;
 
-        // Write to database path
+        // Write to database path, waiting for other processes to let go
+        let _lock = FileLock::acquire(db_path, true, self.store.lock_timeout)?;
         std::fs::write(db_path, &bytes).map_err(Error::Io)?;
 
         Ok(size)
@@ -611,4 +629,43 @@ impl SyncClient {
     pub fn from_env() -> Result<Self> {
         Self::new(SyncConfig::default())
     }
+
+    pub fn with_store_options(self, _options: StoreOptions) -> Self {
+        self
+    }
+}
+
+#[cfg(all(test, feature = "sync"))]
+mod tests {
+    use super::*;
+    use crate::embedding::MockEmbedder;
+    use crate::store::EncryptionKey;
+    use crate::Database;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_local_head_of_encrypted_database() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("secret.indra");
+        let options = StoreOptions::default()
+            .with_key(EncryptionKey::Passphrase("hunter2".into()))
+            .with_kdf_iterations(1000);
+
+        let head = {
+            let mut db = Database::open_or_create_with_options(&path, options.clone())
+                .unwrap()
+                .with_embedder(MockEmbedder::default());
+            db.create_thought("Top secret plans").unwrap();
+            db.commit("Initial").unwrap()
+        };
+
+        let client = SyncClient::new(SyncConfig::default()).unwrap();
+        assert!(matches!(
+            client.get_local_head(&path),
+            Err(Error::KeyRequired)
+        ));
+
+        let client = client.with_store_options(options);
+        assert_eq!(client.get_local_head(&path).unwrap(), Some(head.to_hex()));
+    }
 }
@@ -150,7 +150,7 @@ mod tests {
     use crate::embedding::Embedder;
     use crate::embedding::MockEmbedder;
     use crate::model::Thought;
-    use crate::store::ObjectStore;
+    use crate::store::{ObjectStore, Storage};
     use crate::trie::MerkleTrie;
     use tempfile::tempdir;
 
@@ -0,0 +1,238 @@
+//! Read snapshots pinned to one commit
+//!
+//! A `Snapshot` resolves HEAD once and answers every read from that
+//! commit's tree. Commits are immutable and the store is shared, so a
+//! snapshot keeps seeing the same state while a writer goes on committing,
+//! and any number of clones can be read from other threads.
+
+use crate::embedding::Embedder;
+use crate::graph::{GraphView, TraversalDirection};
+use crate::model::{Commit, Edge, Hash, Thought, ThoughtId};
+use crate::ops::{diff_trees, Diff};
+use crate::search::{SearchResult, VectorSearch};
+use crate::store::Storage;
+use crate::trie::{page_start, Page};
+use crate::Result;
+use std::sync::Arc;
+
+/// An immutable view of the database at one commit
+///
+/// Created by `Database::snapshot`. Uncommitted changes are not part of
+/// it. Cloning is cheap. `gc` may drop the objects of a snapshot whose
+/// commit no ref reaches anymore.
+#[derive(Clone)]
+pub struct Snapshot {
+    store: Arc<dyn Storage>,
+    embedder: Option<Arc<dyn Embedder>>,
+    /// The pinned commit, `None` before the first commit
+    commit: Option<Hash>,
+    /// Tree of that commit, zero when there is none
+    tree: Hash,
+}
+
+impl Snapshot {
+    /// Pin the commit HEAD points at now
+    pub(crate) fn at_head(
+        store: Arc<dyn Storage>,
+        embedder: Option<Arc<dyn Embedder>>,
+    ) -> Result<Self> {
+        let commit = store.head_commit();
+        let tree = match commit {
+            Some(hash) => store.get_commit(&hash)?.tree,
+            None => Hash::ZERO,
+        };
+        Ok(Snapshot {
+            store,
+            embedder,
+            commit,
+            tree,
+        })
+    }
+
+    /// The commit this snapshot is pinned to, if any
+    pub fn commit(&self) -> Option<Hash> {
+        self.commit
+    }
+
+    /// The tree of the pinned commit (zero for an empty database)
+    pub fn tree(&self) -> Hash {
+        self.tree
+    }
+
+    /// Get a thought by ID
+    pub fn get_thought(&self, id: &ThoughtId) -> Result<Option<Thought>> {
+        match self.view()? {
+            Some(view) => view.get_thought(id),
+            None => Ok(None),
+        }
+    }
+
+    /// List all thoughts
+    pub fn list_thoughts(&self) -> Result<Vec<Thought>> {
+        match self.view()? {
+            Some(view) => view.all_thoughts(),
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Up to `limit` thoughts in ID order, starting after `cursor` from the
+    /// previous page
+    pub fn list_thoughts_page(&self, cursor: Option<&str>, limit: usize) -> Result<Page<Thought>> {
+        match self.view()? {
+            Some(view) => view.thoughts_page(cursor, limit),
+            None => page_start(cursor, limit).map(|_| Page {
+                items: vec![],
+                next: None,
+            }),
+        }
+    }
+
+    /// Get neighbors of a thought
+    pub fn neighbors(
+        &self,
+        id: &ThoughtId,
+        direction: TraversalDirection,
+    ) -> Result<Vec<(Thought, Edge)>> {
+        match self.view()? {
+            Some(view) => view.neighbors(id, direction, None),
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Semantic search for thoughts similar to the query
+    pub fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchResult>> {
+        let query_embedding = self.embed(query)?;
+        match self.view()? {
+            Some(view) => VectorSearch::new(&view).search(&query_embedding, limit),
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Search with a minimum similarity threshold
+    pub fn search_with_threshold(
+        &self,
+        query: &str,
+        threshold: f32,
+        limit: usize,
+    ) -> Result<Vec<SearchResult>> {
+        let query_embedding = self.embed(query)?;
+        match self.view()? {
+            Some(view) => {
+                VectorSearch::new(&view).search_with_threshold(&query_embedding, threshold, limit)
+            }
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Commit history from the pinned commit, newest first
+    pub fn log(&self, limit: Option<usize>) -> Result<Vec<(Hash, Commit)>> {
+        let mut result = Vec::new();
+        let mut current = self.commit;
+        while let Some(hash) = current {
+            if result.len() >= limit.unwrap_or(usize::MAX) {
+                break;
+            }
+            let commit = self.store.get_commit(&hash)?;
+            current = commit.parents.first().copied();
+            result.push((hash, commit));
+        }
+        Ok(result)
+    }
+
+    /// Diff between two commits (zero for the empty state)
+    pub fn diff(&self, from: Hash, to: Hash) -> Result<Diff> {
+        diff_trees(
+            self.store.as_ref(),
+            self.commit_tree(from)?,
+            self.commit_tree(to)?,
+        )
+    }
+
+    /// What changed between `from` and the pinned commit
+    pub fn diff_from(&self, from: Hash) -> Result<Diff> {
+        diff_trees(self.store.as_ref(), self.commit_tree(from)?, self.tree)
+    }
+
+    pub(crate) fn view(&self) -> Result<Option<GraphView<'_>>> {
+        if self.tree.is_zero() {
+            return Ok(None);
+        }
+        Ok(Some(GraphView::new(self.store.as_ref(), self.tree)?))
+    }
+
+    fn commit_tree(&self, commit: Hash) -> Result<Hash> {
+        if commit.is_zero() {
+            Ok(Hash::ZERO)
+        } else {
+            Ok(self.store.get_commit(&commit)?.tree)
+        }
+    }
+
+    fn embed(&self, query: &str) -> Result<Vec<f32>> {
+        self.embedder
+            .as_ref()
+            .ok_or_else(|| crate::Error::Embedding("No embedder configured".into()))?
+            .embed(query)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::embedding::MockEmbedder;
+    use crate::{Database, Snapshot, ThoughtId, TraversalDirection};
+
+    #[test]
+    fn test_snapshot_is_send_sync() {
+        fn assert_send_sync<T: Send + Sync + Clone>() {}
+        assert_send_sync::<Snapshot>();
+    }
+
+    #[test]
+    fn test_snapshot_stays_pinned_while_writer_commits() {
+        let mut db = Database::in_memory().with_embedder(MockEmbedder::new(16));
+        db.create_thought_with_id("a", "Alpha").unwrap();
+        db.create_thought_with_id("b", "Beta").unwrap();
+        db.relate("a", "b", crate::EdgeType::RELATES_TO).unwrap();
+        let first = db.commit("First").unwrap();
+
+        let snapshot = db.snapshot().unwrap();
+        assert_eq!(snapshot.commit(), Some(first));
+
+        let reader = {
+            let snapshot = snapshot.clone();
+            std::thread::spawn(move || {
+                for _ in 0..50 {
+                    assert_eq!(snapshot.list_thoughts().unwrap().len(), 2);
+                    assert_eq!(snapshot.search("Alpha", 10).unwrap().len(), 2);
+                }
+            })
+        };
+        for i in 0..20 {
+            db.create_thought(format!("Later {}", i)).unwrap();
+            db.commit("Later").unwrap();
+        }
+        db.update_thought(&"a".into(), "Alpha, revised").unwrap();
+        let last = db.commit("Revise").unwrap();
+        reader.join().unwrap();
+
+        let a = ThoughtId::new("a");
+        assert_eq!(snapshot.get_thought(&a).unwrap().unwrap().content, "Alpha");
+        assert_eq!(
+            snapshot
+                .neighbors(&a, TraversalDirection::Outgoing)
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(snapshot.log(None).unwrap().len(), 1);
+        assert_eq!(snapshot.diff(first, last).unwrap().added_count(), 20);
+        assert!(snapshot.diff_from(first).unwrap().is_empty());
+
+        let now = db.snapshot().unwrap();
+        assert_eq!(now.list_thoughts().unwrap().len(), 22);
+        assert_eq!(
+            now.get_thought(&a).unwrap().unwrap().content,
+            "Alpha, revised"
+        );
+    }
+}
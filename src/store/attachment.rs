@@ -0,0 +1,197 @@
+//! Chunked attachment content
+//!
+//! Attachment content is split into `ATTACHMENT_CHUNK_SIZE` pieces, each
+//! stored as its own `Attachment` blob, so identical files and identical
+//! pieces of files are stored once. Content that fits in one chunk is a
+//! single blob; anything larger gets a root blob listing its chunks.
+//!
+//! Attachment blob layout:
+//! ```text
+//!   - kind: 1 byte (0 = data, 1 = chunk list)
+//!   - data: content bytes | chunk list: 32-byte chunk hashes, in order
+//! ```
+
+use crate::model::Hash;
+use crate::store::{Blob, BlobType, Storage};
+use crate::{Error, Result};
+use std::collections::VecDeque;
+use std::io::Read;
+
+/// Largest piece of content stored in one blob
+pub const ATTACHMENT_CHUNK_SIZE: usize = 1 << 20;
+
+const KIND_DATA: u8 = 0;
+const KIND_CHUNKS: u8 = 1;
+
+/// Store everything `reader` yields as an attachment
+///
+/// Reads one chunk at a time, so content of any size streams through.
+/// Returns the hash of the root object and the content size.
+pub fn put_attachment(store: &dyn Storage, mut reader: impl Read) -> Result<(Hash, u64)> {
+    let mut chunks = Vec::new();
+    let mut size = 0;
+    loop {
+        let mut data = vec![KIND_DATA];
+        let read = (&mut reader)
+            .take(ATTACHMENT_CHUNK_SIZE as u64)
+            .read_to_end(&mut data)?;
+        size += read as u64;
+        // Empty content is one empty chunk
+        if read > 0 || chunks.is_empty() {
+            chunks.push(store.put(&Blob::new(BlobType::Attachment, data))?);
+        }
+        if read < ATTACHMENT_CHUNK_SIZE {
+            break;
+        }
+    }
+
+    if let [single] = chunks[..] {
+        return Ok((single, size));
+    }
+    let mut list = Vec::with_capacity(1 + chunks.len() * 32);
+    list.push(KIND_CHUNKS);
+    for chunk in &chunks {
+        list.extend_from_slice(chunk.as_bytes());
+    }
+    Ok((store.put(&Blob::new(BlobType::Attachment, list))?, size))
+}
+
+/// The chunks an attachment object lists, or `None` for a data chunk
+pub(crate) fn attachment_chunks(blob: &Blob) -> Result<Option<Vec<Hash>>> {
+    match blob.data.split_first() {
+        Some((&KIND_DATA, _)) => Ok(None),
+        Some((&KIND_CHUNKS, hashes)) if hashes.len() % 32 == 0 => Ok(Some(
+            hashes
+                .chunks_exact(32)
+                .map(|h| Hash::from_bytes(h.try_into().unwrap()))
+                .collect(),
+        )),
+        _ => Err(Error::Corruption("Invalid attachment object".into())),
+    }
+}
+
+/// Streams an attachment's content, loading one chunk at a time
+pub struct AttachmentReader<'a> {
+    store: &'a dyn Storage,
+    /// Chunks not loaded yet
+    chunks: VecDeque<Hash>,
+    /// Content of the current chunk, without the kind byte
+    current: Vec<u8>,
+    /// Read position in `current`
+    pos: usize,
+}
+
+impl<'a> AttachmentReader<'a> {
+    /// Open the attachment whose root object is `hash`
+    pub fn open(store: &'a dyn Storage, hash: &Hash) -> Result<Self> {
+        let root = get_attachment_blob(store, hash)?;
+        let (chunks, current, pos) = match attachment_chunks(&root)? {
+            Some(chunks) => (chunks.into(), Vec::new(), 0),
+            // A single chunk: past its kind byte
+            None => (VecDeque::new(), root.data, 1),
+        };
+        Ok(AttachmentReader {
+            store,
+            chunks,
+            current,
+            pos,
+        })
+    }
+}
+
+impl Read for AttachmentReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.pos >= self.current.len() {
+            let Some(hash) = self.chunks.pop_front() else {
+                return Ok(0);
+            };
+            let chunk = get_attachment_blob(self.store, &hash).map_err(std::io::Error::other)?;
+            if attachment_chunks(&chunk)
+                .map_err(std::io::Error::other)?
+                .is_some()
+            {
+                return Err(std::io::Error::other(Error::Corruption(format!(
+                    "Attachment chunk {} is a chunk list",
+                    hash.to_hex()
+                ))));
+            }
+            self.current = chunk.data;
+            self.pos = 1;
+        }
+
+        let len = buf.len().min(self.current.len() - self.pos);
+        buf[..len].copy_from_slice(&self.current[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+/// Fetch an object and check that it is an attachment
+fn get_attachment_blob(store: &dyn Storage, hash: &Hash) -> Result<Blob> {
+    let blob = store.get(hash)?;
+    if blob.blob_type != BlobType::Attachment {
+        return Err(Error::Corruption(format!(
+            "Expected Attachment, got {:?}",
+            blob.blob_type
+        )));
+    }
+    Ok(blob)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+
+    fn roundtrip(store: &MemoryStore, content: &[u8]) -> Hash {
+        let (hash, size) = put_attachment(store, content).unwrap();
+        assert_eq!(size, content.len() as u64);
+        let mut read = Vec::new();
+        AttachmentReader::open(store, &hash)
+            .unwrap()
+            .read_to_end(&mut read)
+            .unwrap();
+        assert_eq!(read, content);
+        hash
+    }
+
+    #[test]
+    fn test_attachment_roundtrip() {
+        let store = MemoryStore::new();
+        roundtrip(&store, b"");
+        roundtrip(&store, b"small file");
+        assert_eq!(store.object_count(), 2);
+
+        // Exactly one chunk, then one byte over
+        let exact = vec![7u8; ATTACHMENT_CHUNK_SIZE];
+        roundtrip(&store, &exact);
+        let mut over = exact.clone();
+        over.push(8);
+        let hash = roundtrip(&store, &over);
+        let root = store.get(&hash).unwrap();
+        assert_eq!(attachment_chunks(&root).unwrap().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_attachment_chunks_are_shared() {
+        let store = MemoryStore::new();
+        let mut first: Vec<u8> = (0..ATTACHMENT_CHUNK_SIZE * 2)
+            .map(|i| (i * 31 % 251) as u8)
+            .collect();
+        roundtrip(&store, &first);
+        let before = store.object_count();
+
+        // Same first chunk, different second one: one new chunk, one root
+        first[ATTACHMENT_CHUNK_SIZE + 5] ^= 1;
+        roundtrip(&store, &first);
+        assert_eq!(store.object_count(), before + 2);
+
+        let thought = store
+            .put_thought(&crate::model::Thought::new("Not an attachment"))
+            .unwrap();
+        assert!(matches!(
+            AttachmentReader::open(&store, &thought),
+            Err(Error::Corruption(_))
+        ));
+    }
+}
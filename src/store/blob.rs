@@ -4,7 +4,7 @@ use crate::model::Hash;
 use serde::{Deserialize, Serialize};
 
 /// Type tag for blobs
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum BlobType {
     /// A thought (node)
     Thought,
@@ -14,6 +14,9 @@ pub enum BlobType {
     Commit,
     /// A tree (merkle trie node)
     Tree,
+    /// A piece of attachment content, or the list of an attachment's
+    /// pieces
+    Attachment,
 }
 
 impl BlobType {
@@ -23,6 +26,7 @@ impl BlobType {
             BlobType::Edge => 1,
             BlobType::Commit => 2,
             BlobType::Tree => 3,
+            BlobType::Attachment => 4,
         }
     }
 
@@ -32,6 +36,7 @@ impl BlobType {
             1 => Some(BlobType::Edge),
             2 => Some(BlobType::Commit),
             3 => Some(BlobType::Tree),
+            4 => Some(BlobType::Attachment),
             _ => None,
         }
     }
@@ -0,0 +1,250 @@
+//! Trained zstd dictionaries for small objects
+//!
+//! Trie nodes, edges and short thoughts are a few hundred bytes each, so
+//! compressed on their own most of a zstd frame is overhead. `repack`
+//! trains one dictionary per `BlobType` from the objects already in the
+//! store; small objects are then compressed against it.
+//!
+//! Stored layout of a dictionary-compressed object:
+//! ```text
+//!   - tag: 1 byte (DICT_TAG)
+//!   - blob_type: 1 byte
+//!   - data: zstd frame, compressed with the dictionary for blob_type
+//! ```
+//!
+//! Objects written before a dictionary existed keep the plain layout and
+//! stay readable.
+
+use crate::store::{Blob, BlobType};
+use crate::{Error, Result};
+use std::collections::HashMap;
+use std::io::Read;
+use zstd::dict::{DecoderDictionary, EncoderDictionary};
+
+/// First byte of a dictionary-compressed object
+pub const DICT_TAG: u8 = 0x81;
+
+/// Largest dictionary trained per blob type
+pub const DICT_SIZE: usize = 8 * 1024;
+
+/// Objects up to this size are compressed with a dictionary and used as
+/// training samples; larger ones carry enough context of their own
+pub const SMALL_BLOB_SIZE: usize = 4 * 1024;
+
+/// zstd level, same as `Blob::compress`
+const LEVEL: i32 = 3;
+
+/// Types with fewer small objects than this get no dictionary
+const MIN_SAMPLES: usize = 64;
+
+/// Sample bytes collected per type at most
+const SAMPLE_BUDGET: usize = 100 * DICT_SIZE;
+
+struct Dictionary {
+    raw: Vec<u8>,
+    encoder: EncoderDictionary<'static>,
+    decoder: DecoderDictionary<'static>,
+}
+
+impl Dictionary {
+    fn new(raw: Vec<u8>) -> Self {
+        Dictionary {
+            encoder: EncoderDictionary::copy(&raw, LEVEL),
+            decoder: DecoderDictionary::copy(&raw),
+            raw,
+        }
+    }
+
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let mut compressor = zstd::bulk::Compressor::with_prepared_dictionary(&self.encoder)?;
+        Ok(compressor.compress(data)?)
+    }
+}
+
+/// The dictionaries a store compresses small objects with, by blob type
+#[derive(Default)]
+pub(crate) struct Dictionaries {
+    by_type: HashMap<BlobType, Dictionary>,
+}
+
+impl Dictionaries {
+    /// Load dictionaries as persisted in a commit record
+    pub fn from_raw(raw: Vec<(BlobType, Vec<u8>)>) -> Self {
+        Dictionaries {
+            by_type: raw
+                .into_iter()
+                .map(|(blob_type, dict)| (blob_type, Dictionary::new(dict)))
+                .collect(),
+        }
+    }
+
+    /// Dictionaries in the form persisted in a commit record, sorted by type
+    pub fn to_raw(&self) -> Vec<(BlobType, Vec<u8>)> {
+        let mut raw: Vec<_> = self
+            .by_type
+            .iter()
+            .map(|(blob_type, dict)| (*blob_type, dict.raw.clone()))
+            .collect();
+        raw.sort_by_key(|(blob_type, _)| blob_type.as_byte());
+        raw
+    }
+
+    /// Number of blob types with a dictionary
+    pub fn len(&self) -> usize {
+        self.by_type.len()
+    }
+
+    /// Compress a blob for storage, with its type's dictionary if it is
+    /// small and there is one
+    pub fn compress(&self, blob: &Blob) -> Result<Vec<u8>> {
+        match self.by_type.get(&blob.blob_type) {
+            Some(dict) if blob.size() <= SMALL_BLOB_SIZE => {
+                let frame = dict.compress(&blob.data)?;
+                let mut output = Vec::with_capacity(2 + frame.len());
+                output.push(DICT_TAG);
+                output.push(blob.blob_type.as_byte());
+                output.extend(frame);
+                Ok(output)
+            }
+            _ => blob.compress(),
+        }
+    }
+
+    /// Decompress a stored object that is not a delta
+    pub fn decompress(&self, raw: &[u8]) -> Result<Blob> {
+        if raw.first() != Some(&DICT_TAG) {
+            return Blob::decompress(raw);
+        }
+
+        let blob_type = raw
+            .get(1)
+            .and_then(|b| BlobType::from_byte(*b))
+            .ok_or_else(|| Error::Corruption("Invalid dictionary object header".into()))?;
+        let dict = self.by_type.get(&blob_type).ok_or_else(|| {
+            Error::Corruption(format!("No dictionary for {:?} objects", blob_type))
+        })?;
+
+        let mut decoder =
+            zstd::stream::read::Decoder::with_prepared_dictionary(&raw[2..], &dict.decoder)?;
+        let mut data = Vec::new();
+        decoder.read_to_end(&mut data)?;
+        Ok(Blob::new(blob_type, data))
+    }
+}
+
+/// Collects small objects and trains a dictionary per type from them
+#[derive(Default)]
+pub(crate) struct Trainer {
+    samples: HashMap<BlobType, (Vec<Vec<u8>>, usize)>,
+}
+
+impl Trainer {
+    /// Offer an object as a training sample
+    pub fn add(&mut self, blob: &Blob) {
+        if blob.size() > SMALL_BLOB_SIZE {
+            return;
+        }
+        let (samples, bytes) = self.samples.entry(blob.blob_type).or_default();
+        if *bytes + blob.size() <= SAMPLE_BUDGET {
+            *bytes += blob.size();
+            samples.push(blob.data.clone());
+        }
+    }
+
+    /// Train a dictionary for every type with enough samples
+    ///
+    /// A dictionary is only kept if compressing the samples with it saves
+    /// more than the dictionary itself costs to store.
+    pub fn train(self) -> Result<Dictionaries> {
+        let mut by_type = HashMap::new();
+        for (blob_type, (samples, _)) in self.samples {
+            if samples.len() < MIN_SAMPLES {
+                continue;
+            }
+            // zstd refuses sample sets it cannot learn from
+            let Ok(raw) = zstd::dict::from_samples(&samples, DICT_SIZE) else {
+                continue;
+            };
+            let dict = Dictionary::new(raw);
+
+            let mut plain = 0;
+            let mut with_dict = 0;
+            for sample in &samples {
+                plain += zstd::bulk::compress(sample, LEVEL)?.len();
+                with_dict += dict.compress(sample)?.len();
+            }
+            if plain > with_dict + dict.raw.len() {
+                by_type.insert(blob_type, dict);
+            }
+        }
+        Ok(Dictionaries { by_type })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{Edge, EdgeType, ThoughtId};
+
+    /// Sample edges with a fixed timestamp, so the blobs (and how well
+    /// they compress) are the same on every run
+    fn edges(count: usize) -> Vec<Blob> {
+        (0..count)
+            .map(|i| {
+                let mut edge = Edge::new(
+                    ThoughtId::new(format!("thought-{}", i)),
+                    ThoughtId::new(format!("thought-{}", i + 1)),
+                    EdgeType::new("relates_to"),
+                );
+                edge.created_at = 1_700_000_000_000 + i as u64;
+                Blob::new(BlobType::Edge, bincode::serialize(&edge).unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_trained_dictionary_roundtrip() {
+        let blobs = edges(500);
+        let mut trainer = Trainer::default();
+        for blob in &blobs {
+            trainer.add(blob);
+        }
+        let dicts = trainer.train().unwrap();
+        assert_eq!(dicts.len(), 1);
+
+        let mut plain = 0;
+        let mut with_dict = 0;
+        for blob in &blobs {
+            let stored = dicts.compress(blob).unwrap();
+            assert_eq!(stored[0], DICT_TAG);
+            assert_eq!(dicts.decompress(&stored).unwrap().hash(), blob.hash());
+            plain += blob.compress().unwrap().len();
+            with_dict += stored.len();
+        }
+        assert!(with_dict * 3 < plain * 2, "{} vs {}", with_dict, plain);
+
+        // Plain objects still read, and survive a persist/load cycle
+        let reloaded = Dictionaries::from_raw(dicts.to_raw());
+        let plain_blob = blobs[0].compress().unwrap();
+        assert_eq!(
+            reloaded.decompress(&plain_blob).unwrap().hash(),
+            blobs[0].hash()
+        );
+        let stored = reloaded.compress(&blobs[1]).unwrap();
+        assert_eq!(dicts.decompress(&stored).unwrap().hash(), blobs[1].hash());
+    }
+
+    #[test]
+    fn test_too_few_samples_trains_nothing() {
+        let mut trainer = Trainer::default();
+        for blob in &edges(10) {
+            trainer.add(blob);
+        }
+        let dicts = trainer.train().unwrap();
+        assert_eq!(dicts.len(), 0);
+        assert!(matches!(
+            dicts.decompress(&[DICT_TAG, BlobType::Edge.as_byte(), 0]),
+            Err(Error::Corruption(_))
+        ));
+    }
+}
@@ -5,32 +5,91 @@
 //! [HEADER: 64 bytes]
 //!   - magic: 8 bytes ("INDRA_DB")
 //!   - version: 4 bytes (u32 LE)
-//!   - flags: 4 bytes
+//!   - flags: 4 bytes (u32 LE)
 //!   - object_count: 8 bytes (u64 LE)
 //!   - index_offset: 8 bytes (u64 LE)
-//!   - reserved: 32 bytes
+//!   - refs_offset: 8 bytes (u64 LE)
+//!   - refs_count: 8 bytes (u64 LE)
+//!   - head_len: 2 bytes (u16 LE, version 1 only)
+//!   - head: 14 bytes (truncated HEAD ref name, version 1 only)
+//!   - kdf: 16 bytes (key derivation parameters, with FLAG_ENCRYPTED)
 //!
 //! [OBJECTS: variable]
-//!   - blob data, concatenated
+//!   - blob data, appended
 //!
-//! [INDEX: variable]
-//!   - sorted array of (hash, offset, size) entries
-//!
-//! [REFS: variable]
-//!   - branch names → commit hashes
+//! [COMMIT RECORDS: interleaved with objects]
+//!   - checksummed index + refs + HEAD, one per sync (see `journal`)
 //! ```
+//!
+//! A sync only writes the index entries added since the previous record,
+//! as a segment extending it. Once `MAX_SEGMENTS` segments have piled up
+//! they are merged into one, and once the segments would cover a quarter
+//! of the index the full index is written again, so both the cost of a
+//! sync and the number of records `open` reads stay bounded.
+//!
+//! The reflog is chained through the records the same way: a sync writes
+//! only the ref movements since the previous record, and the whole log is
+//! rewritten once the segments would hold a quarter of it. Compression
+//! dictionaries are written by the first record after `repack` trains them
+//! and referred to by offset after that.
+//!
+//! The header points at the latest commit record, which also holds HEAD and
+//! the newest reflog entries (see `refs`). Version 1 files written before commit records
+//! existed (no `FLAG_JOURNAL`) have a bare index and refs section at
+//! `index_offset`/`refs_offset` and are still readable.
+//!
+//! Stores created with a key (see `StoreOptions::with_key`) set
+//! `FLAG_ENCRYPTED` and seal every object and commit record body (see
+//! `crypto`). Only the header and record preludes stay readable without
+//! the key.
+//!
+//! Processes coordinate through an advisory lock on `<db>.lock` (see
+//! `lock`): writable stores hold it exclusively, read-only stores share it.
+//!
+//! Reads go through a shared memory map of the file rather than the file
+//! handle, so concurrent readers never contend on a lock. Objects are never
+//! overwritten in place, which means a map stays valid for everything it
+//! covers; it is only remapped when a read lands past its end after an
+//! append, and dropped when `gc` swaps in a new file.
 
 use crate::model::Hash;
-use crate::store::blob::{Blob, BlobType};
+use crate::store::blob::Blob;
+use crate::store::crypto::{Crypto, KdfParams};
+use crate::store::dict::Dictionaries;
+use crate::store::journal::{self, Chain, LogChain, Record, INDEX_ENTRY_SIZE};
+use crate::store::lock::FileLock;
+use crate::store::pack::{self, RepackStats, MAX_DELTA_DEPTH};
+use crate::store::refs::{Head, RefLogEntry, RefTable};
+use crate::store::{reachable_objects, EncryptionKey, Storage, StoreOptions};
+use crate::trie::NodeCache;
 use crate::{Error, Result, MAGIC, VERSION};
+use memmap2::Mmap;
 use parking_lot::RwLock;
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::fs::{File, OpenOptions};
 use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
 
 const HEADER_SIZE: u64 = 64;
 
+/// Header flag: the header points into a checksummed commit record
+const FLAG_JOURNAL: u32 = 1;
+
+/// Header flag: objects and records are sealed, KDF parameters follow
+const FLAG_ENCRYPTED: u32 = 2;
+
+/// Where the KDF parameters live in the header
+const KDF_OFFSET: usize = 48;
+
+/// Segments written on top of a full index before they are merged
+const MAX_SEGMENTS: usize = 32;
+
+/// The full index is rewritten once segments hold 1/N of its entries
+const CONSOLIDATE_DIVISOR: usize = 4;
+
 /// Index entry for an object
 #[derive(Clone, Debug)]
 struct IndexEntry {
@@ -41,36 +100,224 @@ struct IndexEntry {
 /// In-memory index for fast lookups
 struct Index {
     entries: HashMap<Hash, IndexEntry>,
+    /// Hashes added since the newest commit record
+    pending: Vec<Hash>,
+    /// Records the next sync can extend; `None` makes it write the full
+    /// index
+    chain: Option<Chain>,
 }
 
 impl Index {
     fn new() -> Self {
         Index {
             entries: HashMap::new(),
+            pending: Vec::new(),
+            chain: None,
+        }
+    }
+
+    /// Index entries for the next commit record and the record it extends
+    ///
+    /// Normally a segment of the entries added since the newest record;
+    /// once `MAX_SEGMENTS` have piled up, one segment of everything added
+    /// since the full index. The full index itself, sorted by hash, when
+    /// there is nothing to extend or the segments have grown too large.
+    fn next_record(&self) -> (Vec<(Hash, u64, u32)>, Option<u64>) {
+        let entry = |hash: &Hash| {
+            let entry = &self.entries[hash];
+            (*hash, entry.offset, entry.size)
+        };
+        match &self.chain {
+            Some(chain)
+                if (chain.since_base.len() + self.pending.len()) * CONSOLIDATE_DIVISOR
+                    <= self.entries.len() =>
+            {
+                if chain.segments < MAX_SEGMENTS {
+                    (self.pending.iter().map(entry).collect(), Some(chain.tip))
+                } else {
+                    let hashes = chain.since_base.iter().chain(&self.pending);
+                    (hashes.map(entry).collect(), Some(chain.base))
+                }
+            }
+            _ => {
+                let mut entries: Vec<_> = self.entries.keys().map(entry).collect();
+                entries.sort_by_key(|(h, _, _)| *h.as_bytes());
+                (entries, None)
+            }
+        }
+    }
+
+    /// Note that the record from `next_record` was written at `offset`
+    fn record_written(&mut self, offset: u64, prev: Option<u64>) {
+        let pending = std::mem::take(&mut self.pending);
+        match (prev, self.chain.as_mut()) {
+            (Some(prev), Some(chain)) => {
+                // A segment on the base covers everything since it
+                chain.segments = if prev == chain.base {
+                    1
+                } else {
+                    chain.segments + 1
+                };
+                chain.tip = offset;
+                chain.since_base.extend(pending);
+            }
+            _ => self.chain = Some(Chain::full(offset)),
+        }
+    }
+}
+
+/// What earlier commit records already hold, so the next one can refer to
+/// it instead of repeating it
+#[derive(Default)]
+pub(super) struct Carried {
+    /// Entries of the reflog already in a record
+    log_synced: usize,
+    /// Records the next sync can extend with a log segment; `None` makes it
+    /// write the whole reflog
+    log_chain: Option<LogChain>,
+    /// Record holding the current dictionaries; `None` makes the next sync
+    /// write them
+    dicts_at: Option<u64>,
+}
+
+impl Carried {
+    /// Reflog entries for the next commit record and the record it extends
+    ///
+    /// Like `Index::next_record`: the entries since the newest record as a
+    /// segment, everything since the whole log once `MAX_SEGMENTS` have
+    /// piled up, the whole log once segments would hold a quarter of it. A
+    /// record without new entries points at the newest record with some.
+    fn next_log<'l>(&self, log: &'l [RefLogEntry]) -> (&'l [RefLogEntry], Option<u64>) {
+        let pending = log.len() - self.log_synced;
+        match &self.log_chain {
+            Some(chain) if pending == 0 => (&[], Some(chain.tip)),
+            Some(chain) if (chain.since_base + pending) * CONSOLIDATE_DIVISOR <= log.len() => {
+                if chain.segments < MAX_SEGMENTS {
+                    (&log[self.log_synced..], Some(chain.tip))
+                } else {
+                    (&log[self.log_synced - chain.since_base..], Some(chain.base))
+                }
+            }
+            _ => (log, None),
+        }
+    }
+
+    /// Note that the record holding `next_log` was written at `offset`,
+    /// with the reflog `log_len` entries long
+    fn log_written(&mut self, offset: u64, prev: Option<u64>, log_len: usize) {
+        let pending = log_len - std::mem::replace(&mut self.log_synced, log_len);
+        match (prev, self.log_chain.as_mut()) {
+            (Some(_), Some(_)) if pending == 0 => {}
+            (Some(prev), Some(chain)) => {
+                chain.segments = if prev == chain.base {
+                    1
+                } else {
+                    chain.segments + 1
+                };
+                chain.tip = offset;
+                chain.since_base += pending;
+            }
+            _ => self.log_chain = Some(LogChain::full(offset)),
         }
     }
 }
 
+/// Outcome of a garbage collection pass
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GcStats {
+    /// Objects in the index before collection
+    pub objects_before: usize,
+    /// Objects kept because they are reachable from a ref
+    pub objects_after: usize,
+    /// File size before collection
+    pub bytes_before: u64,
+    /// File size after compaction
+    pub bytes_after: u64,
+}
+
+impl GcStats {
+    /// Number of unreachable objects dropped
+    pub fn objects_removed(&self) -> usize {
+        self.objects_before.saturating_sub(self.objects_after)
+    }
+
+    /// Number of bytes the file shrank by
+    pub fn bytes_reclaimed(&self) -> u64 {
+        self.bytes_before.saturating_sub(self.bytes_after)
+    }
+}
+
+/// Where the bytes of a database file go
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FileUsage {
+    /// Size of the file
+    pub file_bytes: u64,
+    /// The header and the commit records the current state is read from
+    pub metadata_bytes: u64,
+}
+
 /// A content-addressed object store backed by a single file
 pub struct ObjectStore {
     /// Path to the database file
     path: std::path::PathBuf,
     /// The file handle
     file: RwLock<File>,
+    /// Read-only map of the file, covering at least every indexed object
+    /// once a read has needed it
+    map: RwLock<Option<Arc<Mmap>>>,
     /// In-memory index
     index: RwLock<Index>,
-    /// Refs (branch name → commit hash)
-    refs: RwLock<HashMap<String, Hash>>,
-    /// Current HEAD ref name
-    head: RwLock<String>,
+    /// Compression dictionaries, replaced wholesale by `repack`
+    dicts: RwLock<Arc<Dictionaries>>,
+    /// Cipher for encrypted stores
+    crypto: Option<Crypto>,
+    /// Refs, HEAD and reflog
+    refs: RwLock<RefTable>,
+    /// What the commit records on disk already hold
+    carried: RwLock<Carried>,
     /// Current append position
     write_offset: RwLock<u64>,
+    /// Whether there is state not yet covered by a commit record
+    dirty: AtomicBool,
+    /// Whether writes are refused
+    read_only: bool,
+    /// Whether reads re-hash what they decode
+    verify_reads: bool,
+    /// Reads that found an object's stored bytes damaged
+    verification_failures: AtomicU64,
+    /// Decoded trie nodes shared by every trie on this store
+    node_cache: NodeCache,
+    /// Inter-process lock, held for the lifetime of the store
+    _lock: FileLock,
 }
 
 impl ObjectStore {
     /// Create a new database file
     pub fn create(path: impl AsRef<Path>) -> Result<Self> {
-        let path = path.as_ref().to_path_buf();
+        Self::create_with_options(path, StoreOptions::default())
+    }
+
+    /// Create a new database file, waiting up to `options.lock_timeout` for
+    /// the exclusive lock
+    pub fn create_with_options(path: impl AsRef<Path>, options: StoreOptions) -> Result<Self> {
+        if options.read_only {
+            return Err(Error::ReadOnly);
+        }
+        let path = path.as_ref();
+        let lock = FileLock::acquire(path, true, options.lock_timeout)?;
+        Self::create_locked(path, &options, lock)
+    }
+
+    /// Create the file once the exclusive lock is held, so a concurrent
+    /// process can never see it truncated
+    ///
+    /// With `options.key` the store is encrypted.
+    fn create_locked(path: &Path, options: &StoreOptions, lock: FileLock) -> Result<Self> {
+        let path = path.to_path_buf();
+        let crypto = match &options.key {
+            Some(key) => Some(Crypto::create(key, options.kdf_iterations)?),
+            None => None,
+        };
 
         let mut file = OpenOptions::new()
             .read(true)
@@ -79,137 +326,317 @@ impl ObjectStore {
             .truncate(true)
             .open(&path)?;
 
-        // Write header
-        let mut header = [0u8; HEADER_SIZE as usize];
-        header[0..8].copy_from_slice(MAGIC);
-        header[8..12].copy_from_slice(&VERSION.to_le_bytes());
-        // flags: 0
-        // object_count: 0
-        // index_offset: 0 (will be updated)
-        file.write_all(&header)?;
-        file.sync_all()?;
-
-        let mut refs = HashMap::new();
-        refs.insert("main".to_string(), Hash::ZERO);
+        write_empty_header(&mut file, crypto.as_ref())?;
 
-        Ok(ObjectStore {
+        let store = ObjectStore {
             path,
             file: RwLock::new(file),
+            map: RwLock::new(None),
             index: RwLock::new(Index::new()),
-            refs: RwLock::new(refs),
-            head: RwLock::new("main".to_string()),
+            dicts: RwLock::new(Arc::new(Dictionaries::default())),
+            crypto,
+            refs: RwLock::new(RefTable::new()),
+            carried: RwLock::new(Carried::default()),
             write_offset: RwLock::new(HEADER_SIZE),
-        })
+            dirty: AtomicBool::new(true),
+            read_only: false,
+            verify_reads: options.verify_reads,
+            verification_failures: AtomicU64::new(0),
+            node_cache: NodeCache::new(options.node_cache_size),
+            _lock: lock,
+        };
+        if store.crypto.is_some() {
+            // A sealed record from the start, so a wrong key never opens
+            // the store as empty
+            store.sync()?;
+        }
+        Ok(store)
     }
 
     /// Open an existing database file
+    ///
+    /// If the latest commit record is torn (the process died mid-sync), the
+    /// store recovers to the last intact record. Objects written after that
+    /// record are discarded.
     pub fn open(path: impl AsRef<Path>) -> Result<Self> {
-        let path = path.as_ref().to_path_buf();
+        Self::open_with_options(path, StoreOptions::default())
+    }
 
-        let mut file = OpenOptions::new().read(true).write(true).open(&path)?;
+    /// Open an existing database file with the given options
+    ///
+    /// Read-only stores take a shared lock and coexist with each other;
+    /// writable stores take an exclusive lock. Returns `Error::Locked` if
+    /// the lock cannot be taken within `options.lock_timeout`.
+    ///
+    /// Files written by an older format version can only be opened
+    /// read-only; writable opens fail with `Error::MigrationRequired` until
+    /// the file has been upgraded with `migrate`.
+    ///
+    /// Encrypted stores need `options.key`: without one the open fails with
+    /// `Error::KeyRequired`, with the wrong one with `Error::WrongKey`.
+    pub fn open_with_options(path: impl AsRef<Path>, options: StoreOptions) -> Result<Self> {
+        let path = path.as_ref();
+        if !path.exists() {
+            // Fail before leaving a lock file behind
+            return Err(std::io::Error::from(std::io::ErrorKind::NotFound).into());
+        }
+        let lock = FileLock::acquire(path, !options.read_only, options.lock_timeout)?;
+        Self::open_locked(path, &options, lock)
+    }
 
-        // Read and validate header
-        let mut header = [0u8; HEADER_SIZE as usize];
-        file.read_exact(&mut header)?;
+    fn open_locked(path: &Path, options: &StoreOptions, lock: FileLock) -> Result<Self> {
+        let path = path.to_path_buf();
+        let read_only = options.read_only;
 
-        if &header[0..8] != MAGIC {
-            return Err(Error::InvalidFile("Invalid magic bytes".into()));
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(!read_only)
+            .open(&path)?;
+
+        let version = read_version(&mut file)?;
+        check_version(version, read_only)?;
+        let crypto = open_crypto(&mut file, options.key.as_ref())?;
+        let (record, write_offset, chain, carried) =
+            load_state(&mut file, version, crypto.as_ref())?;
+
+        let mut index = Index::new();
+        for (hash, offset, size) in record.entries {
+            index.entries.insert(hash, IndexEntry { offset, size });
         }
+        index.chain = chain;
 
-        let version = u32::from_le_bytes(header[8..12].try_into().unwrap());
-        if version != VERSION {
-            return Err(Error::VersionMismatch {
-                expected: VERSION,
-                found: version,
-            });
+        // Files synced before any ref existed still get a `main` branch
+        let mut refs = RefTable::new();
+        if !record.refs.is_empty() {
+            refs.refs = record.refs.into_iter().collect();
+            refs.head = record.head;
         }
+        refs.log = record.reflog;
 
-        let object_count = u64::from_le_bytes(header[16..24].try_into().unwrap());
-        let index_offset = u64::from_le_bytes(header[24..32].try_into().unwrap());
-        let refs_offset = u64::from_le_bytes(header[32..40].try_into().unwrap());
-        let refs_count = u64::from_le_bytes(header[40..48].try_into().unwrap());
-        let head_len = u16::from_le_bytes(header[48..50].try_into().unwrap()) as usize;
-        let head_name = if head_len > 0 && head_len <= 14 {
-            String::from_utf8_lossy(&header[50..50 + head_len]).to_string()
+        Ok(ObjectStore {
+            path,
+            file: RwLock::new(file),
+            map: RwLock::new(None),
+            index: RwLock::new(index),
+            dicts: RwLock::new(Arc::new(Dictionaries::from_raw(record.dictionaries))),
+            crypto,
+            refs: RwLock::new(refs),
+            carried: RwLock::new(carried),
+            write_offset: RwLock::new(write_offset),
+            dirty: AtomicBool::new(false),
+            read_only,
+            verify_reads: options.verify_reads,
+            verification_failures: AtomicU64::new(0),
+            node_cache: NodeCache::new(options.node_cache_size),
+            _lock: lock,
+        })
+    }
+
+    /// Open or create a database file
+    pub fn open_or_create(path: impl AsRef<Path>) -> Result<Self> {
+        Self::open_or_create_with_options(path, StoreOptions::default())
+    }
+
+    /// Open or create a database file with the given options
+    ///
+    /// A read-only store can only open an existing file.
+    pub fn open_or_create_with_options(
+        path: impl AsRef<Path>,
+        options: StoreOptions,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        if options.read_only {
+            return Self::open_with_options(path, options);
+        }
+
+        // Decide under the lock so two processes never both create the file
+        let lock = FileLock::acquire(path, true, options.lock_timeout)?;
+        if path.exists() {
+            Self::open_locked(path, &options, lock)
         } else {
-            "main".to_string()
-        };
+            Self::create_locked(path, &options, lock)
+        }
+    }
 
-        // Load index if it exists
-        let mut index = Index::new();
-        if index_offset > 0 && object_count > 0 {
-            file.seek(SeekFrom::Start(index_offset))?;
-            // Each index entry: 32 (hash) + 8 (offset) + 4 (size) = 44 bytes
-            for _ in 0..object_count {
-                let mut entry_buf = [0u8; 44];
-                file.read_exact(&mut entry_buf)?;
+    /// Whether the store was opened read-only
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
 
-                let mut hash_bytes = [0u8; 32];
-                hash_bytes.copy_from_slice(&entry_buf[0..32]);
-                let hash = Hash::from_bytes(hash_bytes);
+    /// Whether objects and records are encrypted
+    pub fn is_encrypted(&self) -> bool {
+        self.crypto.is_some()
+    }
 
-                let offset = u64::from_le_bytes(entry_buf[32..40].try_into().unwrap());
-                let size = u32::from_le_bytes(entry_buf[40..44].try_into().unwrap());
+    /// Number of reads so far that found an object's stored bytes damaged
+    /// (undecodable, or hashing to something other than requested)
+    pub fn verification_failures(&self) -> u64 {
+        self.verification_failures.load(Ordering::Relaxed)
+    }
 
-                index.entries.insert(hash, IndexEntry { offset, size });
-            }
+    /// Count a damaged object and describe it
+    fn verification_failed(
+        &self,
+        hash: &Hash,
+        offset: u64,
+        reason: impl std::fmt::Display,
+    ) -> Error {
+        self.verification_failures.fetch_add(1, Ordering::Relaxed);
+        Error::CorruptObject {
+            hash: *hash,
+            offset,
+            reason: reason.to_string(),
         }
+    }
 
-        // Load refs
-        let mut refs = HashMap::new();
-        if refs_offset > 0 && refs_count > 0 {
-            file.seek(SeekFrom::Start(refs_offset))?;
-            for _ in 0..refs_count {
-                let mut len_buf = [0u8; 2];
-                file.read_exact(&mut len_buf)?;
-                let name_len = u16::from_le_bytes(len_buf) as usize;
+    fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+        Ok(())
+    }
 
-                let mut name_buf = vec![0u8; name_len];
-                file.read_exact(&mut name_buf)?;
-                let name = String::from_utf8_lossy(&name_buf).to_string();
+    /// Write a commit record for `index`, the refs and `dicts` to `file` at
+    /// `offset` and point its header at it
+    ///
+    /// Marks what the record covers as written in `index` and `carried` and
+    /// returns its length. Shared by `sync`, which appends to the open file,
+    /// and `gc` and `repack`, which finish the new file with it before it
+    /// replaces the old one.
+    fn write_commit_record(
+        &self,
+        file: &mut File,
+        offset: u64,
+        index: &mut Index,
+        carried: &mut Carried,
+        dicts: &Dictionaries,
+    ) -> Result<u64> {
+        let refs = self.refs.read();
+        let (entries, prev) = index.next_record();
+        let (reflog, log_prev) = carried.next_log(&refs.log);
+        let mut ref_list: Vec<_> = refs.refs.iter().map(|(n, h)| (n.clone(), *h)).collect();
+        ref_list.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let record = Record {
+            entries,
+            prev,
+            object_count: index.entries.len() as u64,
+            refs: ref_list,
+            head: refs.head.clone(),
+            reflog: reflog.to_vec(),
+            log_prev,
+            log_len: refs.log.len() as u64,
+            dictionaries: match carried.dicts_at {
+                Some(_) => Vec::new(),
+                None => dicts.to_raw(),
+            },
+            dicts_at: carried.dicts_at,
+        };
+        let len = write_record(file, &record, offset, VERSION, self.crypto.as_ref())?;
+        index.record_written(offset, prev);
+        carried.log_written(offset, log_prev, refs.log.len());
+        carried.dicts_at.get_or_insert(offset);
+        Ok(len)
+    }
 
-                let mut hash_buf = [0u8; 32];
-                file.read_exact(&mut hash_buf)?;
-                let hash = Hash::from_bytes(hash_buf);
+    /// Get the file path
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
 
-                refs.insert(name, hash);
+    /// Get a map of the file that covers `end`, remapping if the current
+    /// one predates the append that wrote it
+    ///
+    /// If the file itself is shorter than `end`, the returned map is too;
+    /// callers must bounds-check.
+    fn map_covering(&self, end: u64) -> Result<Arc<Mmap>> {
+        if let Some(map) = self.map.read().as_ref() {
+            if map.len() as u64 >= end {
+                return Ok(Arc::clone(map));
             }
         }
 
-        // Ensure main branch exists
-        if refs.is_empty() {
-            refs.insert("main".to_string(), Hash::ZERO);
+        // Same lock order as `gc`: file before map
+        let file = self.file.read();
+        let mut slot = self.map.write();
+        if let Some(map) = slot.as_ref() {
+            // Another reader may have remapped while we waited
+            if map.len() as u64 >= end {
+                return Ok(Arc::clone(map));
+            }
         }
 
-        // Calculate write offset (end of objects, before index)
-        let write_offset = if index_offset > 0 {
-            index_offset
-        } else {
-            file.seek(SeekFrom::End(0))?
+        // Safety: objects and commit records are only ever appended, and
+        // other processes cannot write while we hold the store lock, so the
+        // mapped bytes never change underneath us. `gc` drops the map before
+        // replacing the file.
+        let map = Arc::new(unsafe { Mmap::map(&*file)? });
+        *slot = Some(Arc::clone(&map));
+        Ok(map)
+    }
+
+    /// Read an object that is `depth` deltas away from the one requested
+    fn get_at_depth(&self, hash: &Hash, depth: usize) -> Result<Blob> {
+        if depth > MAX_DELTA_DEPTH {
+            return Err(Error::Corruption(format!(
+                "Delta chain through {} is too deep",
+                hash.to_hex()
+            )));
+        }
+
+        let entry = {
+            let index = self.index.read();
+            index.entries.get(hash).cloned()
         };
 
-        Ok(ObjectStore {
-            path,
-            file: RwLock::new(file),
-            index: RwLock::new(index),
-            refs: RwLock::new(refs),
-            head: RwLock::new(head_name),
-            write_offset: RwLock::new(write_offset),
-        })
-    }
+        let entry = entry.ok_or_else(|| Error::NotFound(hash.to_hex()))?;
 
-    /// Open or create a database file
-    pub fn open_or_create(path: impl AsRef<Path>) -> Result<Self> {
-        let path = path.as_ref();
-        if path.exists() {
-            Self::open(path)
-        } else {
-            Self::create(path)
+        let end = entry.offset + entry.size as u64;
+        let map = self.map_covering(end)?;
+        let data = map
+            .get(entry.offset as usize..end as usize)
+            .ok_or_else(|| {
+                self.verification_failed(hash, entry.offset, "extends past the end of the file")
+            })?;
+        let data = unseal(self.crypto.as_ref(), hash, data)
+            .map_err(|_| self.verification_failed(hash, entry.offset, "fails authentication"))?;
+
+        // A broken base reports itself; only our own bytes are blamed here
+        let dicts = Arc::clone(&self.dicts.read());
+        let mut base_failed = false;
+        let blob = pack::decode(&data, &dicts, |base| {
+            let blob = self.get_at_depth(base, depth + 1);
+            base_failed = blob.is_err();
+            blob
+        });
+        let blob = match blob {
+            Err(e) if !base_failed => {
+                return Err(self.verification_failed(
+                    hash,
+                    entry.offset,
+                    format_args!("does not decode: {}", e),
+                ))
+            }
+            blob => blob?,
+        };
+
+        if self.verify_reads {
+            let actual = blob.hash();
+            if actual != *hash {
+                return Err(self.verification_failed(
+                    hash,
+                    entry.offset,
+                    format_args!("hashes to {}", actual.to_hex()),
+                ));
+            }
         }
+        Ok(blob)
     }
+}
 
+impl Storage for ObjectStore {
     /// Store a blob, returns its hash
-    pub fn put(&self, blob: &Blob) -> Result<Hash> {
+    fn put(&self, blob: &Blob) -> Result<Hash> {
         let hash = blob.hash();
 
         // Check if already exists
@@ -220,8 +647,11 @@ impl ObjectStore {
             }
         }
 
-        // Compress and write
-        let compressed = blob.compress()?;
+        self.check_writable()?;
+
+        // Compress, seal and write
+        let compressed = self.dicts.read().compress(blob)?;
+        let compressed = seal(self.crypto.as_ref(), &hash, compressed)?;
         let size = compressed.len() as u32;
 
         let offset = {
@@ -240,232 +670,862 @@ impl ObjectStore {
         {
             let mut index = self.index.write();
             index.entries.insert(hash, IndexEntry { offset, size });
+            index.pending.push(hash);
         }
+        self.dirty.store(true, Ordering::Release);
 
         Ok(hash)
     }
 
     /// Retrieve a blob by hash
-    pub fn get(&self, hash: &Hash) -> Result<Blob> {
-        let entry = {
-            let index = self.index.read();
-            index.entries.get(hash).cloned()
-        };
+    ///
+    /// Decompresses straight out of the memory map without taking the file
+    /// lock, so any number of threads can read at once. Delta-encoded
+    /// objects are rebuilt from their base. Unless the store was opened
+    /// without `verify_reads`, the result is re-hashed; damaged objects
+    /// fail with `Error::CorruptObject` naming the hash and offset.
+    fn get(&self, hash: &Hash) -> Result<Blob> {
+        self.get_at_depth(hash, 0)
+    }
 
-        let entry = entry.ok_or_else(|| Error::NotFound(hash.to_hex()))?;
+    /// Check if a hash exists
+    fn contains(&self, hash: &Hash) -> bool {
+        let index = self.index.read();
+        index.entries.contains_key(hash)
+    }
 
-        let mut file = self.file.write();
-        file.seek(SeekFrom::Start(entry.offset))?;
+    // === Ref Management ===
 
-        let mut data = vec![0u8; entry.size as usize];
-        file.read_exact(&mut data)?;
+    fn head(&self) -> Head {
+        self.refs.read().head.clone()
+    }
 
-        Blob::decompress(&data)
+    fn set_head(&self, head: Head, reason: &str) -> Result<()> {
+        self.check_writable()?;
+        self.refs.write().set_head(head, reason)?;
+        self.dirty.store(true, Ordering::Release);
+        Ok(())
     }
 
-    /// Check if a hash exists
-    pub fn contains(&self, hash: &Hash) -> bool {
-        let index = self.index.read();
-        index.entries.contains_key(hash)
+    fn get_ref(&self, ref_name: &str) -> Option<Hash> {
+        self.refs.read().refs.get(ref_name).copied()
     }
 
-    /// Store a thought and return its hash
-    pub fn put_thought(&self, thought: &crate::model::Thought) -> Result<Hash> {
-        let data = bincode::serialize(thought)?;
-        let blob = Blob::new(BlobType::Thought, data);
-        self.put(&blob)
+    /// Set a ref to point to a commit
+    ///
+    /// On a read-only store the change is kept in memory only; `sync`
+    /// refuses to persist it.
+    fn set_ref(&self, ref_name: &str, commit_hash: Hash, reason: &str) -> Result<()> {
+        self.refs.write().set_ref(ref_name, commit_hash, reason)?;
+        self.dirty.store(true, Ordering::Release);
+        Ok(())
     }
 
-    /// Retrieve a thought by hash
-    pub fn get_thought(&self, hash: &Hash) -> Result<crate::model::Thought> {
-        let blob = self.get(hash)?;
-        if blob.blob_type != BlobType::Thought {
-            return Err(Error::Corruption(format!(
-                "Expected Thought, got {:?}",
-                blob.blob_type
-            )));
-        }
-        Ok(bincode::deserialize(&blob.data)?)
+    fn list_refs(&self) -> Vec<(String, Hash)> {
+        let refs = self.refs.read();
+        refs.refs.iter().map(|(k, v)| (k.clone(), *v)).collect()
     }
 
-    /// Store an edge and return its hash
-    pub fn put_edge(&self, edge: &crate::model::Edge) -> Result<Hash> {
-        let data = bincode::serialize(edge)?;
-        let blob = Blob::new(BlobType::Edge, data);
-        self.put(&blob)
+    fn create_branch(&self, name: &str, commit_hash: Hash) -> Result<()> {
+        self.check_writable()?;
+        self.refs.write().create_branch(name, commit_hash)?;
+        self.dirty.store(true, Ordering::Release);
+        Ok(())
     }
 
-    /// Retrieve an edge by hash
-    pub fn get_edge(&self, hash: &Hash) -> Result<crate::model::Edge> {
-        let blob = self.get(hash)?;
-        if blob.blob_type != BlobType::Edge {
-            return Err(Error::Corruption(format!(
-                "Expected Edge, got {:?}",
-                blob.blob_type
-            )));
-        }
-        Ok(bincode::deserialize(&blob.data)?)
+    fn delete_branch(&self, name: &str) -> Result<()> {
+        self.check_writable()?;
+        self.refs.write().delete_branch(name)?;
+        self.dirty.store(true, Ordering::Release);
+        Ok(())
     }
 
-    /// Store a commit and return its hash
-    pub fn put_commit(&self, commit: &crate::model::Commit) -> Result<Hash> {
-        let data = bincode::serialize(commit)?;
-        let blob = Blob::new(BlobType::Commit, data);
-        self.put(&blob)
+    fn reflog(&self) -> Vec<RefLogEntry> {
+        self.refs.read().log.clone()
     }
 
-    /// Retrieve a commit by hash
-    pub fn get_commit(&self, hash: &Hash) -> Result<crate::model::Commit> {
-        let blob = self.get(hash)?;
-        if blob.blob_type != BlobType::Commit {
-            return Err(Error::Corruption(format!(
-                "Expected Commit, got {:?}",
-                blob.blob_type
-            )));
+    /// List the hash of every stored object
+    fn hashes(&self) -> Vec<Hash> {
+        self.index.read().entries.keys().copied().collect()
+    }
+
+    /// Drop objects from the index, returning how many were present
+    ///
+    /// The bytes stay in the file until the next `gc`.
+    fn remove_objects(&self, hashes: &[Hash]) -> Result<usize> {
+        self.check_writable()?;
+        let mut index = self.index.write();
+        let removed = hashes
+            .iter()
+            .filter(|hash| index.entries.remove(hash).is_some())
+            .count();
+        if removed > 0 {
+            self.node_cache.clear();
+            // Segments cannot drop entries
+            index.pending.clear();
+            index.chain = None;
+            self.dirty.store(true, Ordering::Release);
         }
-        Ok(bincode::deserialize(&blob.data)?)
+        Ok(removed)
     }
 
-    // === Ref Management ===
+    /// Get the number of objects in the store
+    fn object_count(&self) -> usize {
+        let index = self.index.read();
+        index.entries.len()
+    }
 
-    /// Get the current HEAD ref name
-    pub fn head(&self) -> String {
-        self.head.read().clone()
+    /// Size of the stored encoding: compressed, possibly a delta, sealed
+    /// in encrypted stores
+    fn stored_size(&self, hash: &Hash) -> Option<u64> {
+        let index = self.index.read();
+        index.entries.get(hash).map(|entry| entry.size as u64)
     }
 
-    /// Set HEAD to point to a ref
-    pub fn set_head(&self, ref_name: &str) -> Result<()> {
-        let refs = self.refs.read();
-        if !refs.contains_key(ref_name) {
-            return Err(Error::RefNotFound(ref_name.to_string()));
+    fn node_cache(&self) -> Option<&NodeCache> {
+        Some(&self.node_cache)
+    }
+
+    /// Reads the records on disk: the newest, its index and reflog
+    /// segments back to the full ones, and the record holding the
+    /// dictionaries. State not yet synced is not counted.
+    fn file_usage(&self) -> Result<Option<FileUsage>> {
+        let mut file = self.file.write();
+        let file_len = file.metadata()?.len();
+        let version = read_version(&mut file)?;
+        let crypto = self.crypto.as_ref();
+
+        let mut header = [0u8; HEADER_SIZE as usize];
+        file.seek(SeekFrom::Start(0))?;
+        file.read_exact(&mut header)?;
+        let flags = u32::from_le_bytes(header[12..16].try_into().unwrap());
+        let mut records = HashMap::new();
+        if flags & FLAG_JOURNAL != 0 {
+            let index_offset = u64::from_le_bytes(header[24..32].try_into().unwrap());
+            let (newest, offset, end) =
+                load_journal(&mut file, index_offset, file_len, version, crypto)?;
+            records.insert(offset, end - offset);
+            let mut follow = |link: fn(&Record) -> Option<u64>| -> Result<()> {
+                let mut later = offset;
+                let mut prev = link(&newest);
+                while let Some(at) = prev {
+                    if at >= later || at < HEADER_SIZE {
+                        return Err(Error::Corruption(format!(
+                            "Commit record at {} extends a later record",
+                            later
+                        )));
+                    }
+                    let (record, len) = Record::read_at(&mut file, at, file_len, version, crypto)?
+                        .ok_or_else(|| {
+                            Error::Corruption(format!("Commit record at {} is missing", at))
+                        })?;
+                    records.insert(at, len);
+                    later = at;
+                    prev = link(&record);
+                }
+                Ok(())
+            };
+            follow(|record| record.prev)?;
+            follow(|record| record.log_prev)?;
+            follow(|record| record.dicts_at)?;
         }
-        drop(refs);
 
-        *self.head.write() = ref_name.to_string();
+        Ok(Some(FileUsage {
+            file_bytes: file_len,
+            metadata_bytes: HEADER_SIZE + records.values().sum::<u64>(),
+        }))
+    }
+
+    /// Flush changes and write index to disk
+    ///
+    /// Appends a commit record after the objects, flushes it, then points
+    /// the header at it and flushes again. The record usually holds only the
+    /// index and reflog entries added since the previous one, and refers to
+    /// the record holding the dictionaries instead of repeating them. A
+    /// crash before the header write leaves the previous record in effect.
+    /// Superseded records become dead space that `gc` reclaims. Does nothing
+    /// if nothing changed since the last sync.
+    fn sync(&self) -> Result<()> {
+        if !self.dirty.load(Ordering::Acquire) {
+            return Ok(());
+        }
+        self.check_writable()?;
+
+        // Same lock order as `put` and `gc`
+        let mut write_offset = self.write_offset.write();
+        let mut file = self.file.write();
+        let mut index = self.index.write();
+        let mut carried = self.carried.write();
+        let dicts = self.dicts.read().clone();
+
+        let len =
+            self.write_commit_record(&mut file, *write_offset, &mut index, &mut carried, &dicts)?;
+        *write_offset += len;
+        self.dirty.store(false, Ordering::Release);
         Ok(())
     }
 
-    /// Get the commit hash for a ref
-    pub fn get_ref(&self, ref_name: &str) -> Option<Hash> {
-        let refs = self.refs.read();
-        refs.get(ref_name).copied()
+    /// Remove unreachable objects and compact the file
+    ///
+    /// Marks everything reachable from any ref (commits, their parents,
+    /// trie nodes, thoughts and edges), copies only those objects into a
+    /// fresh file next to the database, writes the index and refs there and
+    /// atomically renames it over the original. Superseded thought versions,
+    /// intermediate trie nodes and the history of deleted branches are
+    /// dropped.
+    ///
+    /// Must not run concurrently with other writers to the same store.
+    /// Reflog entries are not roots and may name commits that were dropped.
+    /// Deltas are copied as they are unless their base is dropped, in which
+    /// case they are stored whole. Sealed objects are copied without being
+    /// re-encrypted.
+    fn gc(&self, keep: &[Hash]) -> Result<GcStats> {
+        self.check_writable()?;
+        let mut roots = self.refs.read().roots();
+        roots.extend_from_slice(keep);
+        let live = reachable_objects(self, roots)?;
+
+        let bytes_before = self.file.read().metadata()?.len();
+        let tmp_path = sidecar_path(&self.path, "gc");
+
+        let mut new_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&tmp_path)?;
+        write_empty_header(&mut new_file, self.crypto.as_ref())?;
+
+        let objects_before;
+        {
+            // Same lock order as `put`
+            let mut write_offset = self.write_offset.write();
+            let mut file = self.file.write();
+            let mut index = self.index.write();
+            objects_before = index.entries.len();
+
+            // Copy in file order to keep related objects close together
+            let mut entries: Vec<_> = index
+                .entries
+                .iter()
+                .filter(|(hash, _)| live.contains(hash))
+                .collect();
+            entries.sort_by_key(|(_, entry)| entry.offset);
+
+            let mut new_index = Index::new();
+            let mut offset = HEADER_SIZE;
+            for (hash, entry) in entries {
+                let crypto = self.crypto.as_ref();
+                let mut data = read_raw(&mut file, entry)?;
+                // A delta whose base is being dropped is stored whole
+                let base = pack::delta_base(&unseal(crypto, hash, &data)?);
+                if base.is_some_and(|base| !live.contains(&base)) {
+                    let dicts = self.dicts.read();
+                    let blob = read_blob(&mut file, &index, &dicts, crypto, hash, 0)?;
+                    data = seal(crypto, hash, dicts.compress(&blob)?)?;
+                }
+                new_file.write_all(&data)?;
+                new_index.entries.insert(
+                    *hash,
+                    IndexEntry {
+                        offset,
+                        size: data.len() as u32,
+                    },
+                );
+                offset += data.len() as u64;
+            }
+
+            // Index and refs go into the new file before it replaces the
+            // old one, and only then does the store switch over to it
+            let mut new_carried = Carried::default();
+            let dicts = self.dicts.read().clone();
+            let finished = self
+                .write_commit_record(
+                    &mut new_file,
+                    offset,
+                    &mut new_index,
+                    &mut new_carried,
+                    &dicts,
+                )
+                .and_then(|len| {
+                    std::fs::rename(&tmp_path, &self.path)?;
+                    Ok(len)
+                });
+            let len = match finished {
+                Ok(len) => len,
+                Err(e) => {
+                    let _ = std::fs::remove_file(&tmp_path);
+                    return Err(e);
+                }
+            };
+
+            // Offsets in the old map are meaningless for the new file
+            *self.map.write() = None;
+            *file = new_file;
+            *index = new_index;
+            *self.carried.write() = new_carried;
+            *write_offset = offset + len;
+        }
+        self.dirty.store(false, Ordering::Release);
+        self.node_cache.clear();
+
+        Ok(GcStats {
+            objects_before,
+            objects_after: self.object_count(),
+            bytes_before,
+            bytes_after: self.file.read().metadata()?.len(),
+        })
     }
 
-    /// Set a ref to point to a commit
-    pub fn set_ref(&self, ref_name: &str, commit_hash: Hash) {
-        let mut refs = self.refs.write();
-        refs.insert(ref_name.to_string(), commit_hash);
+    /// Rewrite the file with similar objects stored as deltas and small
+    /// objects compressed with trained dictionaries
+    ///
+    /// Every indexed object is kept, reachable or not. A compression
+    /// dictionary is trained per blob type from the small objects already
+    /// stored (see `dict`); it replaces any previous one and is used by
+    /// later `put`s too. Objects are then ordered so that versions of the
+    /// same thought sit together (newest first) and other objects of a type
+    /// are grouped by size, and each is stored as a delta against one of
+    /// the objects just before it when that is smaller than compressing it
+    /// alone (see `pack`). Reads rebuild deltas transparently. Like `gc`,
+    /// the new file is written next to the database and renamed over it.
+    ///
+    /// Must not run concurrently with other writers to the same store.
+    fn repack(&self) -> Result<RepackStats> {
+        self.check_writable()?;
+        let tmp_path = sidecar_path(&self.path, "repack");
+
+        let mut new_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&tmp_path)?;
+        write_empty_header(&mut new_file, self.crypto.as_ref())?;
+
+        let mut stats = RepackStats::default();
+        {
+            // Holding the append position keeps `put` and `sync` out while
+            // reads go through the map as usual
+            let mut write_offset = self.write_offset.write();
+            stats.bytes_before = self.file.read().metadata()?.len();
+            stats.stored_bytes_before = self
+                .index
+                .read()
+                .entries
+                .values()
+                .map(|entry| entry.size as u64)
+                .sum();
+
+            let mut new_index = Index::new();
+            let mut offset = HEADER_SIZE;
+            let packed = pack::pack_objects(
+                self.hashes(),
+                |hash| self.get(hash),
+                |hash, data| {
+                    let data = seal(self.crypto.as_ref(), &hash, data.to_vec())?;
+                    new_file.write_all(&data)?;
+                    new_index.entries.insert(
+                        hash,
+                        IndexEntry {
+                            offset,
+                            size: data.len() as u32,
+                        },
+                    );
+                    offset += data.len() as u64;
+                    Ok(())
+                },
+            )?;
+
+            stats.objects = new_index.entries.len();
+            stats.deltas = packed.deltas;
+            stats.dictionaries = packed.dictionaries.len();
+            stats.uncompressed_bytes = packed.uncompressed_bytes;
+            stats.stored_bytes_after = offset - HEADER_SIZE;
+
+            // Like `gc`, finish and rename the new file before switching
+            let mut file = self.file.write();
+            let mut index = self.index.write();
+            let mut new_carried = Carried::default();
+            let finished = self
+                .write_commit_record(
+                    &mut new_file,
+                    offset,
+                    &mut new_index,
+                    &mut new_carried,
+                    &packed.dictionaries,
+                )
+                .and_then(|len| {
+                    std::fs::rename(&tmp_path, &self.path)?;
+                    Ok(len)
+                });
+            let len = match finished {
+                Ok(len) => len,
+                Err(e) => {
+                    let _ = std::fs::remove_file(&tmp_path);
+                    return Err(e);
+                }
+            };
+
+            *self.dicts.write() = Arc::new(packed.dictionaries);
+            *self.map.write() = None;
+            *file = new_file;
+            *index = new_index;
+            *self.carried.write() = new_carried;
+            *write_offset = offset + len;
+        }
+        self.dirty.store(false, Ordering::Release);
+
+        stats.bytes_after = self.file.read().metadata()?.len();
+        Ok(stats)
     }
+}
 
-    /// Get the current HEAD commit hash
-    pub fn head_commit(&self) -> Option<Hash> {
-        let head = self.head.read();
-        let refs = self.refs.read();
-        refs.get(head.as_str()).copied().filter(|h| !h.is_zero())
+/// Check that a file at `version` can be opened by this build
+///
+/// Older versions are readable but must be migrated before writing.
+fn check_version(version: u32, read_only: bool) -> Result<()> {
+    if version == 0 || version > VERSION {
+        return Err(Error::VersionMismatch {
+            expected: VERSION,
+            found: version,
+        });
     }
+    if version < VERSION && !read_only {
+        return Err(Error::MigrationRequired {
+            found: version,
+            current: VERSION,
+        });
+    }
+    Ok(())
+}
 
-    /// List all refs
-    pub fn list_refs(&self) -> Vec<(String, Hash)> {
-        let refs = self.refs.read();
-        refs.iter().map(|(k, v)| (k.clone(), *v)).collect()
+/// Read the format version from a file's header, checking the magic bytes
+pub(super) fn read_version(file: &mut File) -> Result<u32> {
+    let mut prefix = [0u8; 12];
+    file.seek(SeekFrom::Start(0))?;
+    file.read_exact(&mut prefix)?;
+    if &prefix[0..8] != MAGIC {
+        return Err(Error::InvalidFile("Invalid magic bytes".into()));
+    }
+    Ok(u32::from_le_bytes(prefix[8..12].try_into().unwrap()))
+}
+
+/// Overwrite the format version in a file's header and flush it
+pub(super) fn write_version(file: &mut File, version: u32) -> Result<()> {
+    file.seek(SeekFrom::Start(8))?;
+    file.write_all(&version.to_le_bytes())?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Derive the cipher of an encrypted file from `key`, or `None` if the file
+/// is not encrypted
+///
+/// Fails with `Error::KeyRequired` if it is and there is no key.
+pub(super) fn open_crypto(file: &mut File, key: Option<&EncryptionKey>) -> Result<Option<Crypto>> {
+    match read_kdf_params(file)? {
+        Some(params) => Ok(Some(Crypto::open(key.ok_or(Error::KeyRequired)?, params)?)),
+        None => Ok(None),
+    }
+}
+
+/// Read the key derivation parameters of an encrypted file, or `None` if
+/// the file is not encrypted
+fn read_kdf_params(file: &mut File) -> Result<Option<KdfParams>> {
+    let mut header = [0u8; HEADER_SIZE as usize];
+    file.seek(SeekFrom::Start(0))?;
+    file.read_exact(&mut header)?;
+
+    let flags = u32::from_le_bytes(header[12..16].try_into().unwrap());
+    if flags & FLAG_ENCRYPTED == 0 {
+        return Ok(None);
+    }
+    let params = &header[KDF_OFFSET..KDF_OFFSET + KdfParams::ENCODED_SIZE];
+    KdfParams::decode(params.try_into().unwrap()).map(Some)
+}
+
+/// Load the durable state of a file at format `version`, unsealing records
+/// with `crypto` if it is encrypted
+///
+/// Returns the state with the full index and reflog merged from their
+/// segments, the offset new objects should be appended at, the records the
+/// next sync can extend (`None` for files without commit records) and what
+/// those records hold.
+pub(super) fn load_state(
+    file: &mut File,
+    version: u32,
+    crypto: Option<&Crypto>,
+) -> Result<(Record, u64, Option<Chain>, Carried)> {
+    let file_len = file.metadata()?.len();
+    let mut header = [0u8; HEADER_SIZE as usize];
+    file.seek(SeekFrom::Start(0))?;
+    file.read_exact(&mut header)?;
+
+    let flags = u32::from_le_bytes(header[12..16].try_into().unwrap());
+    if flags & FLAG_JOURNAL != 0 {
+        let index_offset = u64::from_le_bytes(header[24..32].try_into().unwrap());
+        let (record, offset, end) = load_journal(file, index_offset, file_len, version, crypto)?;
+        let (record, chain) = merge_segments(file, record, offset, file_len, version, crypto)?;
+        let (record, mut carried) = merge_log(file, record, offset, file_len, version, crypto)?;
+        carried.dicts_at = Some(record.dicts_at.unwrap_or(offset));
+        let record = load_dictionaries(file, record, offset, file_len, version, crypto)?;
+        Ok((record, end, Some(chain), carried))
+    } else {
+        // Never overwrite a legacy index: append after it
+        let record = load_legacy(file, &header)?;
+        Ok((record, file_len, None, Carried::default()))
+    }
+}
+
+/// Append a commit record at `offset` in the layout of format `version`,
+/// then point the header at it
+///
+/// The record is flushed before the header is touched, so a crash leaves
+/// the previous record in effect. Returns the encoded length.
+pub(super) fn write_record(
+    file: &mut File,
+    record: &Record,
+    offset: u64,
+    version: u32,
+    crypto: Option<&Crypto>,
+) -> Result<u64> {
+    let encoded = record.encode(version, crypto)?;
+    file.seek(SeekFrom::Start(offset))?;
+    file.write_all(&encoded)?;
+    file.sync_all()?;
+
+    // Everything after the magic in one write; HEAD lives in the record
+    let index_offset = offset + journal::PRELUDE_SIZE;
+    let mut fields = Vec::with_capacity(HEADER_SIZE as usize - 8);
+    fields.extend_from_slice(&version.to_le_bytes());
+    fields.extend_from_slice(&(FLAG_JOURNAL | encryption_flag(crypto)).to_le_bytes());
+    fields.extend_from_slice(&record.object_count.to_le_bytes());
+    fields.extend_from_slice(&index_offset.to_le_bytes());
+    fields.extend_from_slice(&(offset + record.refs_offset()).to_le_bytes());
+    fields.extend_from_slice(&(record.refs.len() as u64).to_le_bytes());
+    if let Some(crypto) = crypto {
+        fields.extend_from_slice(&crypto.params().encode());
     }
+    fields.resize(HEADER_SIZE as usize - 8, 0);
+
+    file.seek(SeekFrom::Start(8))?;
+    file.write_all(&fields)?;
+    file.sync_all()?;
+    Ok(encoded.len() as u64)
+}
 
-    /// Create a new branch at the given commit
-    pub fn create_branch(&self, name: &str, commit_hash: Hash) -> Result<()> {
-        let mut refs = self.refs.write();
-        if refs.contains_key(name) {
-            return Err(Error::BranchNotFound(format!(
-                "Branch '{}' already exists",
-                name
+/// Load the commit record the header points at, recovering if it is torn
+///
+/// Returns the record, its offset and the offset right after it.
+fn load_journal(
+    file: &mut File,
+    index_offset: u64,
+    file_len: u64,
+    version: u32,
+    crypto: Option<&Crypto>,
+) -> Result<(Record, u64, u64)> {
+    if let Some(record_offset) = index_offset.checked_sub(journal::PRELUDE_SIZE) {
+        if record_offset >= HEADER_SIZE {
+            if let Some((record, len)) =
+                Record::read_at(file, record_offset, file_len, version, crypto)?
+            {
+                return Ok((record, record_offset, record_offset + len));
+            }
+        }
+    }
+
+    match Record::scan_last(file, HEADER_SIZE, file_len, version, crypto)? {
+        Some((record, offset, len)) => Ok((record, offset, offset + len)),
+        None => Err(Error::Corruption("No valid commit record found".into())),
+    }
+}
+
+/// Follow the segments of the record at `tip` back to the full index and
+/// merge them into it
+fn merge_segments(
+    file: &mut File,
+    mut record: Record,
+    tip: u64,
+    file_len: u64,
+    version: u32,
+    crypto: Option<&Crypto>,
+) -> Result<(Record, Chain)> {
+    let mut chain = Chain::full(tip);
+    let mut layers = vec![std::mem::take(&mut record.entries)];
+    let mut prev = record.prev;
+    while let Some(offset) = prev {
+        // Records only ever extend earlier ones
+        if offset >= chain.base || offset < HEADER_SIZE {
+            return Err(Error::Corruption(format!(
+                "Index segment at {} extends a later record",
+                chain.base
             )));
         }
-        refs.insert(name.to_string(), commit_hash);
-        Ok(())
+        let (older, _) = Record::read_at(file, offset, file_len, version, crypto)?
+            .ok_or_else(|| Error::Corruption(format!("Index segment at {} is missing", offset)))?;
+        layers.push(older.entries);
+        prev = older.prev;
+        chain.base = offset;
+    }
+    chain.segments = layers.len() - 1;
+
+    // Oldest first, so later segments win
+    let mut merged = HashMap::new();
+    for (i, layer) in layers.into_iter().rev().enumerate() {
+        if i > 0 {
+            chain
+                .since_base
+                .extend(layer.iter().map(|(hash, _, _)| *hash));
+        }
+        merged.extend(
+            layer
+                .into_iter()
+                .map(|(hash, offset, size)| (hash, (offset, size))),
+        );
+    }
+    if merged.len() as u64 != record.object_count {
+        return Err(Error::Corruption(format!(
+            "Index holds {} objects, commit record says {}",
+            merged.len(),
+            record.object_count
+        )));
     }
 
-    /// Delete a branch
-    pub fn delete_branch(&self, name: &str) -> Result<()> {
-        let head = self.head.read();
-        if head.as_str() == name {
-            return Err(Error::BranchNotFound(
-                "Cannot delete current branch".to_string(),
-            ));
+    record.entries = merged
+        .into_iter()
+        .map(|(hash, (offset, size))| (hash, offset, size))
+        .collect();
+    record.prev = None;
+    Ok((record, chain))
+}
+
+/// Follow the log segments of the record at `newest` back to the whole
+/// reflog and join them
+fn merge_log(
+    file: &mut File,
+    mut record: Record,
+    newest: u64,
+    file_len: u64,
+    version: u32,
+    crypto: Option<&Crypto>,
+) -> Result<(Record, Carried)> {
+    let mut layers = vec![(newest, std::mem::take(&mut record.reflog))];
+    let mut prev = record.log_prev;
+    while let Some(offset) = prev {
+        let later = layers.last().expect("starts with the newest").0;
+        if offset >= later || offset < HEADER_SIZE {
+            return Err(Error::Corruption(format!(
+                "Reflog segment at {} extends a later record",
+                later
+            )));
         }
-        drop(head);
+        let (older, _) = Record::read_at(file, offset, file_len, version, crypto)?
+            .ok_or_else(|| Error::Corruption(format!("Reflog segment at {} is missing", offset)))?;
+        layers.push((offset, older.reflog));
+        prev = older.log_prev;
+    }
 
-        let mut refs = self.refs.write();
-        refs.remove(name)
-            .ok_or_else(|| Error::BranchNotFound(name.to_string()))?;
-        Ok(())
+    // The newest record may point past itself at the newest with entries
+    let base = layers.len() - 1;
+    let tip = if layers[0].1.is_empty() && base > 0 {
+        1
+    } else {
+        0
+    };
+    let chain = LogChain {
+        base: layers[base].0,
+        tip: layers[tip].0,
+        segments: base - tip,
+        since_base: layers[tip..base].iter().map(|(_, layer)| layer.len()).sum(),
+    };
+
+    let log: Vec<_> = layers
+        .into_iter()
+        .rev()
+        .flat_map(|(_, layer)| layer)
+        .collect();
+    if log.len() as u64 != record.log_len {
+        return Err(Error::Corruption(format!(
+            "Reflog holds {} entries, commit record says {}",
+            log.len(),
+            record.log_len
+        )));
     }
 
-    /// Get the number of objects in the store
-    pub fn object_count(&self) -> usize {
-        let index = self.index.read();
-        index.entries.len()
+    let carried = Carried {
+        log_synced: log.len(),
+        log_chain: Some(chain),
+        dicts_at: None,
+    };
+    record.reflog = log;
+    record.log_prev = None;
+    Ok((record, carried))
+}
+
+/// Fill in the dictionaries of the record at `offset` from the record
+/// it points at, if it does not hold them itself
+fn load_dictionaries(
+    file: &mut File,
+    mut record: Record,
+    offset: u64,
+    file_len: u64,
+    version: u32,
+    crypto: Option<&Crypto>,
+) -> Result<Record> {
+    let Some(dicts_at) = record.dicts_at.take() else {
+        return Ok(record);
+    };
+    let holder = Record::read_at(file, dicts_at, file_len, version, crypto)?
+        .map(|(holder, _)| holder)
+        .filter(|holder| dicts_at < offset && dicts_at >= HEADER_SIZE && holder.dicts_at.is_none())
+        .ok_or_else(|| {
+            Error::Corruption(format!(
+                "Dictionaries of the record at {} are missing at {}",
+                offset, dicts_at
+            ))
+        })?;
+    record.dictionaries = holder.dictionaries;
+    Ok(record)
+}
+
+/// Load the bare index and refs written before commit records existed
+fn load_legacy(file: &mut File, header: &[u8; HEADER_SIZE as usize]) -> Result<Record> {
+    let object_count = u64::from_le_bytes(header[16..24].try_into().unwrap());
+    let index_offset = u64::from_le_bytes(header[24..32].try_into().unwrap());
+    let refs_offset = u64::from_le_bytes(header[32..40].try_into().unwrap());
+    let refs_count = u64::from_le_bytes(header[40..48].try_into().unwrap());
+    let head_len = u16::from_le_bytes(header[48..50].try_into().unwrap()) as usize;
+    let head = if head_len > 0 && head_len <= 14 {
+        String::from_utf8_lossy(&header[50..50 + head_len]).to_string()
+    } else {
+        "main".to_string()
+    };
+
+    let mut entries = Vec::new();
+    if index_offset > 0 && object_count > 0 {
+        file.seek(SeekFrom::Start(index_offset))?;
+        for _ in 0..object_count {
+            let mut entry_buf = [0u8; INDEX_ENTRY_SIZE as usize];
+            file.read_exact(&mut entry_buf)?;
+
+            let hash = Hash::from_bytes(entry_buf[0..32].try_into().unwrap());
+            let offset = u64::from_le_bytes(entry_buf[32..40].try_into().unwrap());
+            let size = u32::from_le_bytes(entry_buf[40..44].try_into().unwrap());
+            entries.push((hash, offset, size));
+        }
     }
 
-    /// Flush changes and write index to disk
-    pub fn sync(&self) -> Result<()> {
-        let index = self.index.read();
-        let refs = self.refs.read();
-        let head = self.head.read();
-        let write_offset = *self.write_offset.read();
-        let mut file = self.file.write();
+    let mut refs = Vec::new();
+    if refs_offset > 0 && refs_count > 0 {
+        file.seek(SeekFrom::Start(refs_offset))?;
+        for _ in 0..refs_count {
+            let mut len_buf = [0u8; 2];
+            file.read_exact(&mut len_buf)?;
+            let name_len = u16::from_le_bytes(len_buf) as usize;
+
+            let mut name_buf = vec![0u8; name_len];
+            file.read_exact(&mut name_buf)?;
+            let name = String::from_utf8_lossy(&name_buf).to_string();
+
+            let mut hash_buf = [0u8; 32];
+            file.read_exact(&mut hash_buf)?;
+            refs.push((name, Hash::from_bytes(hash_buf)));
+        }
+    }
 
-        // Calculate where refs will be written (after index)
-        let index_size = index.entries.len() * 44; // 32 hash + 8 offset + 4 size
-        let refs_offset = write_offset + index_size as u64;
-
-        // Update header
-        file.seek(SeekFrom::Start(16))?;
-        file.write_all(&(index.entries.len() as u64).to_le_bytes())?;
-        file.write_all(&write_offset.to_le_bytes())?;
-        // Write refs offset at byte 32
-        file.seek(SeekFrom::Start(32))?;
-        file.write_all(&refs_offset.to_le_bytes())?;
-        file.write_all(&(refs.len() as u64).to_le_bytes())?;
-        // Write HEAD ref name length and content at byte 48
-        let head_bytes = head.as_bytes();
-        file.seek(SeekFrom::Start(48))?;
-        file.write_all(&(head_bytes.len() as u16).to_le_bytes())?;
-        // Head name (up to 14 bytes to fit in header)
-        let head_slice = &head_bytes[..head_bytes.len().min(14)];
-        file.write_all(head_slice)?;
-
-        // Write index at write_offset
-        file.seek(SeekFrom::Start(write_offset))?;
-
-        // Sort by hash for determinism
-        let mut entries: Vec<_> = index.entries.iter().collect();
-        entries.sort_by_key(|(h, _)| h.as_bytes());
-
-        for (hash, entry) in entries {
-            file.write_all(hash.as_bytes())?;
-            file.write_all(&entry.offset.to_le_bytes())?;
-            file.write_all(&entry.size.to_le_bytes())?;
-        }
-
-        // Write refs after index
-        // Format: for each ref: name_len (u16) + name + hash (32 bytes)
-        let mut ref_list: Vec<_> = refs.iter().collect();
-        ref_list.sort_by_key(|(name, _)| *name);
-
-        for (name, hash) in ref_list {
-            let name_bytes = name.as_bytes();
-            file.write_all(&(name_bytes.len() as u16).to_le_bytes())?;
-            file.write_all(name_bytes)?;
-            file.write_all(hash.as_bytes())?;
-        }
-
-        file.sync_all()?;
-        Ok(())
+    Ok(Record {
+        object_count: entries.len() as u64,
+        entries,
+        prev: None,
+        refs,
+        head: Head::Branch(head),
+        reflog: Vec::new(),
+        log_prev: None,
+        log_len: 0,
+        dicts_at: None,
+        dictionaries: Vec::new(),
+    })
+}
+
+/// Write a fresh header with no index and no refs
+fn write_empty_header(file: &mut File, crypto: Option<&Crypto>) -> Result<()> {
+    let mut header = [0u8; HEADER_SIZE as usize];
+    header[0..8].copy_from_slice(MAGIC);
+    header[8..12].copy_from_slice(&VERSION.to_le_bytes());
+    header[12..16].copy_from_slice(&encryption_flag(crypto).to_le_bytes());
+    // object_count: 0
+    // index_offset: 0 (will be updated)
+    if let Some(crypto) = crypto {
+        header[KDF_OFFSET..KDF_OFFSET + KdfParams::ENCODED_SIZE]
+            .copy_from_slice(&crypto.params().encode());
     }
+    file.write_all(&header)?;
+    file.sync_all()?;
+    Ok(())
+}
 
-    /// Get the file path
-    pub fn path(&self) -> &Path {
-        &self.path
+/// `FLAG_ENCRYPTED` if there is a cipher
+fn encryption_flag(crypto: Option<&Crypto>) -> u32 {
+    crypto.map_or(0, |_| FLAG_ENCRYPTED)
+}
+
+/// Seal an object's stored bytes, bound to its hash
+fn seal(crypto: Option<&Crypto>, hash: &Hash, data: Vec<u8>) -> Result<Vec<u8>> {
+    match crypto {
+        Some(crypto) => crypto.seal(hash.as_bytes(), &data),
+        None => Ok(data),
+    }
+}
+
+/// Undo `seal`
+fn unseal<'a>(crypto: Option<&Crypto>, hash: &Hash, data: &'a [u8]) -> Result<Cow<'a, [u8]>> {
+    match crypto {
+        Some(crypto) => Ok(Cow::Owned(crypto.unseal(hash.as_bytes(), data)?)),
+        None => Ok(Cow::Borrowed(data)),
+    }
+}
+
+/// Read and decode an object straight from the file, rebuilding deltas
+fn read_blob(
+    file: &mut File,
+    index: &Index,
+    dicts: &Dictionaries,
+    crypto: Option<&Crypto>,
+    hash: &Hash,
+    depth: usize,
+) -> Result<Blob> {
+    if depth > MAX_DELTA_DEPTH {
+        return Err(Error::Corruption(format!(
+            "Delta chain through {} is too deep",
+            hash.to_hex()
+        )));
     }
+    let entry = index
+        .entries
+        .get(hash)
+        .ok_or_else(|| Error::NotFound(hash.to_hex()))?;
+    let data = read_raw(file, entry)?;
+    let data = unseal(crypto, hash, &data)?;
+    pack::decode(&data, dicts, |base| {
+        read_blob(file, index, dicts, crypto, base, depth + 1)
+    })
+}
+
+/// Read the stored (compressed) bytes of an object
+fn read_raw(file: &mut File, entry: &IndexEntry) -> Result<Vec<u8>> {
+    file.seek(SeekFrom::Start(entry.offset))?;
+    let mut data = vec![0u8; entry.size as usize];
+    file.read_exact(&mut data)?;
+    Ok(data)
+}
+
+/// A path next to the database file, e.g. `.indra` → `.indra.gc`
+pub(super) fn sidecar_path(path: &Path, suffix: &str) -> std::path::PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".");
+    name.push(suffix);
+    name.into()
 }
 
 impl Drop for ObjectStore {
@@ -479,6 +1539,7 @@ impl Drop for ObjectStore {
 mod tests {
     use super::*;
     use crate::model::{Commit, Edge, EdgeType, Thought};
+    use crate::store::{BlobType, EncryptionKey, HEAD_REF};
     use tempfile::tempdir;
 
     #[test]
@@ -583,10 +1644,10 @@ mod tests {
         let commit_hash = Hash::digest(b"commit");
 
         // Default HEAD is main
-        assert_eq!(store.head(), "main");
+        assert_eq!(store.head(), Head::Branch("main".into()));
 
         // Set main to point to a commit
-        store.set_ref("main", commit_hash);
+        store.set_ref("main", commit_hash, "test").unwrap();
         assert_eq!(store.get_ref("main"), Some(commit_hash));
 
         // Create a new branch
@@ -594,7 +1655,719 @@ mod tests {
         assert_eq!(store.get_ref("feature"), Some(commit_hash));
 
         // Switch HEAD
-        store.set_head("feature").unwrap();
-        assert_eq!(store.head(), "feature");
+        store
+            .set_head(Head::Branch("feature".into()), "checkout")
+            .unwrap();
+        assert_eq!(store.head(), Head::Branch("feature".into()));
+    }
+
+    #[test]
+    fn test_refs_survive_reopen() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.indra");
+        let commit_hash = Hash::digest(b"commit");
+        let long = format!("experiments/{}", "x".repeat(300));
+        {
+            let store = ObjectStore::create(&path).unwrap();
+            store.set_ref("main", commit_hash, "commit: first").unwrap();
+            store.create_branch(&long, commit_hash).unwrap();
+            store
+                .set_head(Head::Branch(long.clone()), "checkout")
+                .unwrap();
+            store.sync().unwrap();
+        }
+        {
+            let store = ObjectStore::open(&path).unwrap();
+            assert_eq!(store.head(), Head::Branch(long.clone()));
+            assert_eq!(store.get_ref(&long), Some(commit_hash));
+            store
+                .set_head(Head::Detached(commit_hash), "checkout")
+                .unwrap();
+            store.sync().unwrap();
+        }
+
+        let store = ObjectStore::open(&path).unwrap();
+        assert_eq!(store.head(), Head::Detached(commit_hash));
+        let log = store.reflog();
+        let names: Vec<_> = log.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, vec!["main", long.as_str(), HEAD_REF, HEAD_REF]);
+        assert_eq!(log[0].reason, "commit: first");
+        assert!(log.windows(2).all(|w| w[0].timestamp <= w[1].timestamp));
+    }
+
+    /// Write two syncs and return (file after first sync, file after second)
+    /// along with the hashes the first and second states point `main` at.
+    fn two_syncs(path: &Path) -> (Vec<u8>, Vec<u8>, Hash, Hash) {
+        let store = ObjectStore::create(path).unwrap();
+        let first = store.put_thought(&Thought::new("First")).unwrap();
+        store.set_ref("main", first, "test").unwrap();
+        store.sync().unwrap();
+        let before = std::fs::read(path).unwrap();
+
+        let second = store.put_thought(&Thought::new("Second")).unwrap();
+        store.create_branch("feature", second).unwrap();
+        store.set_ref("main", second, "test").unwrap();
+        store.sync().unwrap();
+        let after = std::fs::read(path).unwrap();
+
+        (before, after, first, second)
+    }
+
+    #[test]
+    fn test_sync_crash_at_every_offset() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.indra");
+        let (before, after, first, _) = two_syncs(&path);
+
+        // The header is only rewritten after the record is durable, so a
+        // crash anywhere while appending leaves the old header in place.
+        let crashed = dir.path().join("crashed.indra");
+        for cut in before.len()..=after.len() {
+            let mut bytes = after[..cut].to_vec();
+            bytes[..HEADER_SIZE as usize].copy_from_slice(&before[..HEADER_SIZE as usize]);
+            std::fs::write(&crashed, &bytes).unwrap();
+
+            let store = ObjectStore::open(&crashed)
+                .unwrap_or_else(|e| panic!("open failed at offset {}: {}", cut, e));
+            assert_eq!(store.get_ref("main"), Some(first), "offset {}", cut);
+            assert_eq!(store.get_ref("feature"), None, "offset {}", cut);
+            assert_eq!(store.get_thought(&first).unwrap().content, "First");
+            assert_eq!(store.object_count(), 1, "offset {}", cut);
+        }
+    }
+
+    #[test]
+    fn test_torn_header_recovers() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.indra");
+        let (before, after, first, second) = two_syncs(&path);
+
+        let crashed = dir.path().join("crashed.indra");
+        for torn in 12..=HEADER_SIZE as usize {
+            let mut bytes = after.clone();
+            bytes[torn..HEADER_SIZE as usize].copy_from_slice(&before[torn..HEADER_SIZE as usize]);
+            std::fs::write(&crashed, &bytes).unwrap();
+
+            let store = ObjectStore::open(&crashed)
+                .unwrap_or_else(|e| panic!("open failed with header torn at {}: {}", torn, e));
+            let main = store.get_ref("main").unwrap();
+            assert!(main == first || main == second, "torn at {}", torn);
+            // Whatever state was recovered must be internally consistent
+            assert!(store.get_thought(&main).is_ok(), "torn at {}", torn);
+        }
+    }
+
+    #[test]
+    fn test_recovered_store_accepts_writes() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.indra");
+        let (before, after, first, _) = two_syncs(&path);
+
+        // Crash halfway through the second record
+        let cut = before.len() + (after.len() - before.len()) / 2;
+        let mut bytes = after[..cut].to_vec();
+        bytes[..HEADER_SIZE as usize].copy_from_slice(&before[..HEADER_SIZE as usize]);
+        std::fs::write(&path, &bytes).unwrap();
+
+        let third;
+        {
+            let store = ObjectStore::open(&path).unwrap();
+            third = store.put_thought(&Thought::new("Third")).unwrap();
+            store.set_ref("main", third, "test").unwrap();
+            store.sync().unwrap();
+        }
+
+        let store = ObjectStore::open(&path).unwrap();
+        assert_eq!(store.get_ref("main"), Some(third));
+        assert_eq!(store.get_thought(&first).unwrap().content, "First");
+        assert_eq!(store.get_thought(&third).unwrap().content, "Third");
+    }
+
+    #[test]
+    fn test_sync_writes_index_segments() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.indra");
+        let store = ObjectStore::create(&path).unwrap();
+        let mut hashes: Vec<_> = (0..200)
+            .map(|i| {
+                store
+                    .put_thought(&Thought::new(format!("Base {}", i)))
+                    .unwrap()
+            })
+            .collect();
+        store.sync().unwrap();
+
+        // Each sync appends one object and a record with one index entry
+        for i in 0..MAX_SEGMENTS + 8 {
+            let len = std::fs::metadata(&path).unwrap().len();
+            hashes.push(
+                store
+                    .put_thought(&Thought::new(format!("Added {}", i)))
+                    .unwrap(),
+            );
+            store.sync().unwrap();
+            let grown = std::fs::metadata(&path).unwrap().len() - len;
+            assert!(grown < 50 * INDEX_ENTRY_SIZE, "sync {} wrote {}", i, grown);
+            let index = store.index.read();
+            let chain = index.chain.as_ref().unwrap();
+            assert!((1..=MAX_SEGMENTS).contains(&chain.segments));
+            assert_eq!(chain.since_base.len(), i + 1);
+        }
+        drop(store);
+
+        let store = ObjectStore::open(&path).unwrap();
+        assert_eq!(store.object_count(), hashes.len());
+        for hash in &hashes {
+            assert!(store.get_thought(hash).is_ok());
+        }
+        {
+            let index = store.index.read();
+            let chain = index.chain.as_ref().unwrap();
+            assert_eq!(chain.segments, 8);
+            assert_eq!(chain.since_base.len(), MAX_SEGMENTS + 8);
+        }
+
+        // Once segments cover a quarter of the index it is written whole
+        let mut rewrites = 0;
+        for i in 0..40 {
+            hashes.push(
+                store
+                    .put_thought(&Thought::new(format!("More {}", i)))
+                    .unwrap(),
+            );
+            store.sync().unwrap();
+            if store.index.read().chain.as_ref().unwrap().segments == 0 {
+                rewrites += 1;
+            }
+        }
+        assert_eq!(rewrites, 1);
+
+        // Removals cannot be expressed as a segment
+        store.remove_objects(&hashes[..1]).unwrap();
+        store.sync().unwrap();
+        assert_eq!(store.index.read().chain.as_ref().unwrap().segments, 0);
+        drop(store);
+
+        let store = ObjectStore::open(&path).unwrap();
+        assert_eq!(store.object_count(), hashes.len() - 1);
+        assert!(!store.contains(&hashes[0]));
+    }
+
+    #[test]
+    fn test_sync_writes_reflog_segments() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.indra");
+        let store = ObjectStore::create(&path).unwrap();
+
+        // One ref move per sync, like a commit; the log is written whole
+        // only each time it has grown by a quarter
+        let mut rewrites = 0;
+        for i in 0..1000u32 {
+            store
+                .set_ref("main", Hash::digest(&i.to_le_bytes()), "commit: next")
+                .unwrap();
+            store.sync().unwrap();
+            let carried = store.carried.read();
+            let chain = carried.log_chain.as_ref().unwrap();
+            assert!(chain.segments <= MAX_SEGMENTS);
+            if chain.segments == 0 {
+                rewrites += 1;
+            }
+        }
+        assert!(rewrites < 40, "{} rewrites", rewrites);
+        // Writing the whole log every time would take about 50 MB
+        let len = std::fs::metadata(&path).unwrap().len();
+        assert!(len < 1 << 20, "file is {} bytes", len);
+
+        // A sync without ref moves points at the newest segment
+        let tip = store.carried.read().log_chain.as_ref().unwrap().tip;
+        store.put_thought(&Thought::new("No ref moves")).unwrap();
+        store.sync().unwrap();
+        assert_eq!(store.carried.read().log_chain.as_ref().unwrap().tip, tip);
+        let log = store.reflog();
+        drop(store);
+
+        let store = ObjectStore::open(&path).unwrap();
+        assert_eq!(store.reflog(), log);
+        assert_eq!(store.carried.read().log_chain.as_ref().unwrap().tip, tip);
+        store
+            .set_ref("main", Hash::digest(b"after reopen"), "commit: last")
+            .unwrap();
+        store.sync().unwrap();
+        drop(store);
+
+        let store = ObjectStore::open(&path).unwrap();
+        let reopened = store.reflog();
+        assert_eq!(reopened.len(), log.len() + 1);
+        assert_eq!(reopened[..log.len()], log[..]);
+        assert_eq!(reopened.last().unwrap().reason, "commit: last");
+
+        // gc starts a new file with the whole log
+        store.set_ref("main", Hash::ZERO, "reset").unwrap();
+        let reopened = store.reflog();
+        store.gc(&[]).unwrap();
+        drop(store);
+        assert_eq!(ObjectStore::open(&path).unwrap().reflog(), reopened);
+    }
+
+    #[test]
+    fn test_verified_reads() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.indra");
+        let store = ObjectStore::create(&path).unwrap();
+        let first = store.put_thought(&Thought::new("First")).unwrap();
+        let second = store.put_thought(&Thought::new("Second")).unwrap();
+
+        // Point `first` at the bytes of `second`: it decodes, but wrongly
+        let offset = {
+            let mut index = store.index.write();
+            let entry = index.entries[&second].clone();
+            index.entries.insert(first, entry.clone());
+            entry.offset
+        };
+        match store.get(&first) {
+            Err(Error::CorruptObject {
+                hash,
+                offset: at,
+                reason,
+            }) => {
+                assert_eq!(hash, first);
+                assert_eq!(at, offset);
+                assert_eq!(reason, format!("hashes to {}", second.to_hex()));
+            }
+            other => panic!("expected corruption, got {:?}", other.map(|b| b.hash())),
+        }
+        assert_eq!(store.verification_failures(), 1);
+        assert_eq!(store.get_thought(&second).unwrap().content, "Second");
+
+        // Undecodable bytes are corruption too, not an IO error
+        {
+            let mut index = store.index.write();
+            index.entries.get_mut(&first).unwrap().offset = 0;
+        }
+        assert!(matches!(
+            store.get(&first),
+            Err(Error::CorruptObject { offset: 0, .. })
+        ));
+        assert_eq!(store.verification_failures(), 2);
+        store.remove_objects(&[first]).unwrap();
+        drop(store);
+
+        // Opting out skips the re-hash
+        let options = StoreOptions::default().with_verify_reads(false);
+        let store = ObjectStore::open_with_options(&path, options).unwrap();
+        {
+            let mut index = store.index.write();
+            let entry = index.entries[&second].clone();
+            index.entries.insert(first, entry);
+        }
+        assert_eq!(store.get(&first).unwrap().hash(), second);
+        assert_eq!(store.verification_failures(), 0);
+    }
+
+    #[test]
+    fn test_sync_without_changes_is_noop() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.indra");
+        {
+            let store = ObjectStore::create(&path).unwrap();
+            store.put_thought(&Thought::new("Only write")).unwrap();
+            store.sync().unwrap();
+        }
+        let len = std::fs::metadata(&path).unwrap().len();
+
+        {
+            let store = ObjectStore::open(&path).unwrap();
+            store.sync().unwrap();
+        }
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), len);
+    }
+
+    #[test]
+    fn test_gc_removes_unreachable_objects() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.indra");
+        let store = ObjectStore::create(&path).unwrap();
+
+        let commit = Commit::initial(Hash::ZERO, "Empty tree", "test");
+        let commit_hash = store.put_commit(&commit).unwrap();
+        store.set_ref("main", commit_hash, "test").unwrap();
+        let orphan = store.put_thought(&Thought::new("Never committed")).unwrap();
+        store.sync().unwrap();
+
+        let stats = store.gc(&[]).unwrap();
+        assert_eq!(stats.objects_before, 2);
+        assert_eq!(stats.objects_after, 1);
+        assert!(store.contains(&commit_hash));
+        assert!(!store.contains(&orphan));
+        assert!(!dir.path().join("test.indra.gc").exists());
+
+        // Appends after gc land in the compacted file
+        let later = store.put_thought(&Thought::new("After gc")).unwrap();
+        store.sync().unwrap();
+        drop(store);
+
+        let store = ObjectStore::open(&path).unwrap();
+        assert_eq!(
+            store.get_commit(&commit_hash).unwrap().message,
+            "Empty tree"
+        );
+        assert_eq!(store.get_thought(&later).unwrap().content, "After gc");
+        assert_eq!(store.get_ref("main"), Some(commit_hash));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_failed_gc_keeps_writing_to_the_original_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.indra");
+        let store = ObjectStore::create(&path).unwrap();
+
+        let commit = Commit::initial(Hash::ZERO, "Empty tree", "test");
+        let commit_hash = store.put_commit(&commit).unwrap();
+        store.set_ref("main", commit_hash, "test").unwrap();
+        let orphan = store.put_thought(&Thought::new("Never committed")).unwrap();
+        store.sync().unwrap();
+
+        // Keep the open file reachable under another name and put a
+        // directory in its place so the final rename fails
+        let moved = dir.path().join("moved.indra");
+        std::fs::hard_link(&path, &moved).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        std::fs::create_dir(&path).unwrap();
+        std::fs::write(path.join("occupied"), b"").unwrap();
+
+        assert!(store.gc(&[]).is_err());
+        assert!(!dir.path().join("test.indra.gc").exists());
+        assert!(store.contains(&orphan));
+
+        let later = store.put_thought(&Thought::new("After gc")).unwrap();
+        store.sync().unwrap();
+        drop(store);
+
+        std::fs::remove_dir_all(&path).unwrap();
+        std::fs::rename(&moved, &path).unwrap();
+        let store = ObjectStore::open(&path).unwrap();
+        assert_eq!(store.get_thought(&later).unwrap().content, "After gc");
+        assert_eq!(store.get_ref("main"), Some(commit_hash));
+    }
+
+    #[test]
+    fn test_repack_stores_versions_as_deltas() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.indra");
+        let store = ObjectStore::create(&path).unwrap();
+
+        let embedding: Vec<f32> = (0..384).map(|i| (i as f32).cos()).collect();
+        let mut thought = Thought::with_id("note", "Version 0").with_embedding(embedding);
+        let mut hashes = Vec::new();
+        for i in 0..30 {
+            thought.content = format!("Version {}", i);
+            thought.modified_at = i;
+            hashes.push(store.put_thought(&thought).unwrap());
+        }
+        let commit = store
+            .put_commit(&Commit::initial(Hash::ZERO, "Empty tree", "test"))
+            .unwrap();
+        store.set_ref("main", commit, "test").unwrap();
+        store.sync().unwrap();
+
+        let stats = store.repack().unwrap();
+        assert_eq!(stats.objects, 31);
+        assert_eq!(stats.deltas, 29);
+        assert!(stats.bytes_after * 4 < stats.bytes_before, "{:?}", stats);
+        assert!(!dir.path().join("test.indra.repack").exists());
+        drop(store);
+
+        let store = ObjectStore::open(&path).unwrap();
+        for (i, hash) in hashes.iter().enumerate() {
+            let thought = store.get_thought(hash).unwrap();
+            assert_eq!(thought.content, format!("Version {}", i));
+        }
+
+        // Keep only the oldest version, the far end of the delta chain: gc
+        // drops its base, so it has to be stored whole again
+        let oldest = hashes[0];
+        let root = store
+            .put_commit(&Commit::initial(oldest, "Oldest", "test"))
+            .unwrap();
+        store.set_ref("main", root, "test").unwrap();
+        store.gc(&[]).unwrap();
+        assert_eq!(store.object_count(), 2);
+        drop(store);
+
+        let store = ObjectStore::open(&path).unwrap();
+        assert_eq!(store.get_thought(&oldest).unwrap().content, "Version 0");
+    }
+
+    #[test]
+    fn test_repack_trains_dictionaries() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.indra");
+        let store = ObjectStore::create(&path).unwrap();
+
+        let edge = |i: usize| {
+            Edge::new(
+                format!("thought-{}", i),
+                format!("thought-{}", i + 1),
+                EdgeType::new("supports"),
+            )
+        };
+        let hashes: Vec<_> = (0..300)
+            .map(|i| store.put_edge(&edge(i)).unwrap())
+            .collect();
+        store.sync().unwrap();
+
+        let stats = store.repack().unwrap();
+        assert_eq!(stats.dictionaries, 1);
+        assert!(
+            stats.ratio_after() > stats.ratio_before() * 1.5,
+            "{:?}",
+            stats
+        );
+
+        // New small objects are compressed with the dictionary too, and
+        // records after the first refer to the dictionary instead of
+        // repeating it
+        let dicts_at = store.carried.read().dicts_at;
+        assert!(dicts_at.is_some());
+        let mut later = Vec::new();
+        for i in 1000..1010 {
+            let len = std::fs::metadata(&path).unwrap().len();
+            later.push(store.put_edge(&edge(i)).unwrap());
+            store.sync().unwrap();
+            let grown = std::fs::metadata(&path).unwrap().len() - len;
+            assert!(grown < 1024, "sync wrote {} bytes", grown);
+        }
+        assert_eq!(store.carried.read().dicts_at, dicts_at);
+        drop(store);
+
+        let store = ObjectStore::open(&path).unwrap();
+        for (i, hash) in hashes.iter().enumerate() {
+            assert_eq!(
+                store.get_edge(hash).unwrap().source.as_str(),
+                format!("thought-{}", i)
+            );
+        }
+        for (i, hash) in later.iter().enumerate() {
+            assert_eq!(
+                store.get_edge(hash).unwrap().source.as_str(),
+                format!("thought-{}", 1000 + i)
+            );
+        }
+        assert_eq!(store.carried.read().dicts_at, dicts_at);
+    }
+
+    #[test]
+    fn test_encrypted_store() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.indra");
+        let options = |passphrase: &str| {
+            no_wait()
+                .with_key(EncryptionKey::Passphrase(passphrase.into()))
+                .with_kdf_iterations(1000)
+        };
+
+        let (kept, dropped) = {
+            let store = ObjectStore::create_with_options(&path, options("hunter2")).unwrap();
+            assert!(store.is_encrypted());
+            let kept = store
+                .put_thought(&Thought::new("Top secret plans"))
+                .unwrap();
+            let dropped = store.put_thought(&Thought::new("Scratch")).unwrap();
+            let commit = store
+                .put_commit(&Commit::initial(kept, "Initial", "test"))
+                .unwrap();
+            store.set_ref("main", commit, "commit: Initial").unwrap();
+            store.create_branch("secret-branch", commit).unwrap();
+            store.sync().unwrap();
+            (kept, dropped)
+        };
+
+        // Neither objects nor refs are readable on disk
+        let bytes = std::fs::read(&path).unwrap();
+        for needle in [&b"Top secret"[..], b"secret-branch", b"Initial"] {
+            assert!(!bytes.windows(needle.len()).any(|w| w == needle));
+        }
+
+        assert!(matches!(
+            ObjectStore::open_with_options(&path, no_wait()),
+            Err(Error::KeyRequired)
+        ));
+        assert!(matches!(
+            ObjectStore::open_with_options(&path, options("wrong")),
+            Err(Error::WrongKey)
+        ));
+
+        let store = ObjectStore::open_with_options(&path, options("hunter2")).unwrap();
+        assert_eq!(
+            store.get_thought(&kept).unwrap().content,
+            "Top secret plans"
+        );
+        assert!(store.get_ref("secret-branch").is_some());
+
+        // Compaction keeps the file sealed and readable
+        store.gc(&[]).unwrap();
+        store.repack().unwrap();
+        assert!(!store.contains(&dropped));
+        drop(store);
+        let store = ObjectStore::open_with_options(&path, options("hunter2")).unwrap();
+        assert!(store.is_encrypted());
+        assert_eq!(
+            store.get_thought(&kept).unwrap().content,
+            "Top secret plans"
+        );
+    }
+
+    #[test]
+    fn test_wrong_key_on_fresh_encrypted_store() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.indra");
+        let key = |passphrase: &str| {
+            no_wait()
+                .with_key(EncryptionKey::Passphrase(passphrase.into()))
+                .with_kdf_iterations(1000)
+        };
+        drop(ObjectStore::create_with_options(&path, key("right")).unwrap());
+
+        assert!(matches!(
+            ObjectStore::open_with_options(&path, key("wrong")),
+            Err(Error::WrongKey)
+        ));
+
+        // A key given for a plain store is ignored
+        let plain = dir.path().join("plain.indra");
+        drop(ObjectStore::create(&plain).unwrap());
+        let store = ObjectStore::open_with_options(&plain, key("any")).unwrap();
+        assert!(!store.is_encrypted());
+    }
+
+    #[test]
+    fn test_reads_see_appends_after_mapping() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.indra");
+        let store = ObjectStore::create(&path).unwrap();
+
+        let first = store.put_thought(&Thought::new("Mapped early")).unwrap();
+        assert_eq!(store.get_thought(&first).unwrap().content, "Mapped early");
+
+        // Written past the end of the existing map
+        let second = store.put_thought(&Thought::new("Appended later")).unwrap();
+        assert_eq!(
+            store.get_thought(&second).unwrap().content,
+            "Appended later"
+        );
+        assert_eq!(store.get_thought(&first).unwrap().content, "Mapped early");
+    }
+
+    #[test]
+    fn test_concurrent_readers() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.indra");
+        let store = ObjectStore::create(&path).unwrap();
+
+        let hashes: Vec<_> = (0..64)
+            .map(|i| {
+                store
+                    .put_thought(&Thought::new(format!("Thought {}", i)))
+                    .unwrap()
+            })
+            .collect();
+
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for (i, hash) in hashes.iter().enumerate() {
+                        let thought = store.get_thought(hash).unwrap();
+                        assert_eq!(thought.content, format!("Thought {}", i));
+                    }
+                });
+            }
+        });
+    }
+
+    fn no_wait() -> StoreOptions {
+        StoreOptions::default().with_lock_timeout(std::time::Duration::ZERO)
+    }
+
+    #[test]
+    fn test_writer_excludes_other_openers() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.indra");
+        let store = ObjectStore::create(&path).unwrap();
+        store.sync().unwrap();
+
+        assert!(matches!(
+            ObjectStore::open_with_options(&path, no_wait()),
+            Err(Error::Locked)
+        ));
+        assert!(matches!(
+            ObjectStore::open_with_options(&path, no_wait().with_read_only(true)),
+            Err(Error::Locked)
+        ));
+
+        drop(store);
+        ObjectStore::open_with_options(&path, no_wait()).unwrap();
+    }
+
+    #[test]
+    fn test_readers_share_lock() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.indra");
+        let hash = {
+            let store = ObjectStore::create(&path).unwrap();
+            store.put_thought(&Thought::new("Shared")).unwrap()
+        };
+
+        let first = ObjectStore::open_with_options(&path, no_wait().with_read_only(true)).unwrap();
+        let second = ObjectStore::open_with_options(&path, no_wait().with_read_only(true)).unwrap();
+        assert_eq!(first.get_thought(&hash).unwrap().content, "Shared");
+        assert_eq!(second.get_thought(&hash).unwrap().content, "Shared");
+
+        // A writer has to wait for every reader
+        assert!(matches!(
+            ObjectStore::open_with_options(&path, no_wait()),
+            Err(Error::Locked)
+        ));
+    }
+
+    #[test]
+    fn test_open_waits_for_lock() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.indra");
+        let store = ObjectStore::create(&path).unwrap();
+        store.sync().unwrap();
+
+        let holder = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            drop(store);
+        });
+
+        let options = StoreOptions::default().with_lock_timeout(std::time::Duration::from_secs(5));
+        ObjectStore::open_with_options(&path, options).unwrap();
+        holder.join().unwrap();
+    }
+
+    #[test]
+    fn test_read_only_rejects_writes() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.indra");
+        ObjectStore::create(&path).unwrap();
+
+        let store = ObjectStore::open_with_options(&path, no_wait().with_read_only(true)).unwrap();
+        assert!(store.is_read_only());
+        assert!(matches!(
+            store.put_thought(&Thought::new("Nope")),
+            Err(Error::ReadOnly)
+        ));
+        assert!(matches!(
+            store.create_branch("feature", Hash::ZERO),
+            Err(Error::ReadOnly)
+        ));
+
+        store
+            .set_ref("main", Hash::digest(b"unpersisted"), "test")
+            .unwrap();
+        assert!(matches!(store.sync(), Err(Error::ReadOnly)));
     }
 }
@@ -0,0 +1,464 @@
+//! Append-only commit records that make `ObjectStore::sync` crash-safe
+//!
+//! Every sync appends a self-validating record holding the index, refs and
+//! HEAD after the objects, flushes it, and only then points the header at
+//! it. Records are never overwritten, so a crash at any point leaves either
+//! the previous record or the new one intact. On open the record the header
+//! points at is validated; if it is missing or torn the file is scanned
+//! backwards for the last valid record.
+//!
+//! From version 2 a record need not repeat the whole index. A segment
+//! holds only the entries added since the record it extends (`prev`), and
+//! the index is rebuilt by following `prev` links back to a record holding
+//! the full index and merging on the way. Refs and HEAD are always
+//! complete in every record.
+//!
+//! The reflog is chained the same way, through its own `log_prev` link: a record holds only the ref movements since the record
+//! it extends, and a record with no new movements points at the newest one
+//! that has some. Dictionaries are written once, by the first record after
+//! they change, and later records point at that one (`dicts_at`).
+//!
+//! Record layout:
+//! ```text
+//!   - magic: 8 bytes ("INDRAREC")
+//!   - body_len: 8 bytes (u64 LE)
+//!   - entry_count: 8 bytes (u64 LE)
+//!   - refs_count: 8 bytes (u64 LE)
+//!   [body]
+//!     - index: entry_count × (hash: 32, offset: u64 LE, size: u32 LE)
+//!     - refs: refs_count × (name_len: u16 LE, name, hash: 32)
+//!     - head (version 1): name_len (u16 LE), name
+//!     - head (version 2+):
+//!         kind: u8 (0 = branch, 1 = detached)
+//!         branch: name_len (u16 LE), name | detached: hash: 32
+//!     - reflog (version 2+): count (u64 LE), then per entry:
+//!         name_len (u16 LE), name, old: 32, new: 32,
+//!         timestamp (u64 LE), reason_len (u32 LE), reason
+//!     - dictionaries (version 2+): count (u8), then per entry:
+//!         blob_type: u8, len (u32 LE), zstd dictionary
+//!     - prev (version 2+): u64 LE, offset of the record this one extends,
+//!         0 if it holds the full index
+//!     - object_count (version 2+): u64 LE, objects in the merged index
+//!     - log_prev (version 2+): u64 LE, offset of the record whose reflog
+//!         this one's entries continue, 0 if it holds the whole reflog
+//!     - log_len (version 2+): u64 LE, entries in the merged reflog
+//!     - dicts_at (version 2+): u64 LE, offset of the record holding the
+//!         dictionaries, 0 if this one does
+//!   - checksum: 32 bytes (BLAKE3 of everything above)
+//! ```
+//!
+//! Index entries and refs use the same encoding as the original layout, so
+//! the header's `index_offset`/`refs_offset` point straight into the record.
+//! The body layout depends on the file's format version, which callers pass
+//! in from the header.
+//!
+//! In an encrypted store the body is sealed (see `crypto`) with the prelude
+//! as associated data, and `body_len` counts the sealed bytes. The checksum
+//! still covers what is on disk, so a torn record is told apart from one
+//! sealed under a different key.
+
+use crate::model::Hash;
+use crate::store::crypto::{Crypto, SEAL_OVERHEAD};
+use crate::store::refs::{Head, RefLogEntry};
+use crate::store::BlobType;
+use crate::Result;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+
+/// Magic bytes at the start of every record
+pub(super) const RECORD_MAGIC: &[u8; 8] = b"INDRAREC";
+
+/// Size of the fixed fields before the body
+pub(super) const PRELUDE_SIZE: u64 = 32;
+
+/// Size of the trailing checksum
+const CHECKSUM_SIZE: u64 = 32;
+
+/// Size of one encoded index entry
+pub(super) const INDEX_ENTRY_SIZE: u64 = 44;
+
+/// Chunk size used when scanning for records during recovery
+const SCAN_CHUNK: u64 = 1 << 20;
+
+/// Where the records of a store stand, so the next sync can write a segment
+#[derive(Clone, Debug)]
+pub(super) struct Chain {
+    /// Offset of the newest record holding the full index
+    pub base: u64,
+    /// Offset of the newest record
+    pub tip: u64,
+    /// Segments between `base` and `tip`, counting `tip`
+    pub segments: usize,
+    /// Hashes indexed by those segments
+    pub since_base: Vec<Hash>,
+}
+
+impl Chain {
+    /// A chain whose newest record at `offset` holds the full index
+    pub fn full(offset: u64) -> Self {
+        Chain {
+            base: offset,
+            tip: offset,
+            segments: 0,
+            since_base: Vec::new(),
+        }
+    }
+}
+
+/// Where the reflog segments of a store stand, so the next sync can write
+/// only the new entries
+#[derive(Clone, Debug)]
+pub(super) struct LogChain {
+    /// Offset of the newest record holding the whole reflog
+    pub base: u64,
+    /// Offset of the newest record holding reflog entries
+    pub tip: u64,
+    /// Segments between `base` and `tip`, counting `tip`
+    pub segments: usize,
+    /// Entries held by those segments
+    pub since_base: usize,
+}
+
+impl LogChain {
+    /// A chain whose newest record at `offset` holds the whole reflog
+    pub fn full(offset: u64) -> Self {
+        LogChain {
+            base: offset,
+            tip: offset,
+            segments: 0,
+            since_base: 0,
+        }
+    }
+}
+
+/// A decoded commit record: the durable state as of one sync
+pub(super) struct Record {
+    /// (hash, offset, size) for every object, or for those added since
+    /// `prev` in a segment
+    pub entries: Vec<(Hash, u64, u32)>,
+    /// Offset of the record a segment extends (always `None` in version 1)
+    pub prev: Option<u64>,
+    /// Number of objects in the merged index
+    pub object_count: u64,
+    /// Branch name → commit hash
+    pub refs: Vec<(String, Hash)>,
+    /// Current HEAD
+    pub head: Head,
+    /// Every ref movement, oldest first, or in a log segment those since
+    /// `log_prev` (always empty in version 1)
+    pub reflog: Vec<RefLogEntry>,
+    /// Offset of the record a log segment extends (always `None` in
+    /// version 1)
+    pub log_prev: Option<u64>,
+    /// Number of entries in the merged reflog
+    pub log_len: u64,
+    /// Compression dictionary per blob type (always empty in version 1, and
+    /// when `dicts_at` names the record holding them)
+    pub dictionaries: Vec<(BlobType, Vec<u8>)>,
+    /// Offset of the record holding the dictionaries (always `None` in
+    /// version 1)
+    pub dicts_at: Option<u64>,
+}
+
+impl Record {
+    /// Encode the record in the layout of format `version`, including
+    /// prelude and checksum
+    ///
+    /// Fields version 1 cannot hold are dropped: it has no detached HEAD, no
+    /// reflog and no dictionaries. Index and log segments need version 2.
+    /// With `crypto` the body is sealed.
+    pub fn encode(&self, version: u32, crypto: Option<&Crypto>) -> Result<Vec<u8>> {
+        debug_assert!(
+            version >= 2
+                || self.prev.is_none() && self.log_prev.is_none() && self.dicts_at.is_none()
+        );
+        let mut body = Vec::with_capacity(self.entries.len() * INDEX_ENTRY_SIZE as usize);
+        for (hash, offset, size) in &self.entries {
+            body.extend_from_slice(hash.as_bytes());
+            body.extend_from_slice(&offset.to_le_bytes());
+            body.extend_from_slice(&size.to_le_bytes());
+        }
+        for (name, hash) in &self.refs {
+            put_name(&mut body, name);
+            body.extend_from_slice(hash.as_bytes());
+        }
+        if version == 1 {
+            put_name(&mut body, self.head.branch().unwrap_or("main"));
+        } else {
+            match &self.head {
+                Head::Branch(name) => {
+                    body.push(0);
+                    put_name(&mut body, name);
+                }
+                Head::Detached(hash) => {
+                    body.push(1);
+                    body.extend_from_slice(hash.as_bytes());
+                }
+            }
+            body.extend_from_slice(&(self.reflog.len() as u64).to_le_bytes());
+            for entry in &self.reflog {
+                put_name(&mut body, &entry.name);
+                body.extend_from_slice(entry.old.as_bytes());
+                body.extend_from_slice(entry.new.as_bytes());
+                body.extend_from_slice(&entry.timestamp.to_le_bytes());
+                body.extend_from_slice(&(entry.reason.len() as u32).to_le_bytes());
+                body.extend_from_slice(entry.reason.as_bytes());
+            }
+            body.push(self.dictionaries.len() as u8);
+            for (blob_type, dict) in &self.dictionaries {
+                body.push(blob_type.as_byte());
+                body.extend_from_slice(&(dict.len() as u32).to_le_bytes());
+                body.extend_from_slice(dict);
+            }
+            body.extend_from_slice(&self.prev.unwrap_or(0).to_le_bytes());
+            body.extend_from_slice(&self.object_count.to_le_bytes());
+            body.extend_from_slice(&self.log_prev.unwrap_or(0).to_le_bytes());
+            body.extend_from_slice(&self.log_len.to_le_bytes());
+            body.extend_from_slice(&self.dicts_at.unwrap_or(0).to_le_bytes());
+        }
+
+        let body_len = body.len() + crypto.map_or(0, |_| SEAL_OVERHEAD);
+        let mut out = Vec::with_capacity(body_len + (PRELUDE_SIZE + CHECKSUM_SIZE) as usize);
+        out.extend_from_slice(RECORD_MAGIC);
+        out.extend_from_slice(&(body_len as u64).to_le_bytes());
+        out.extend_from_slice(&(self.entries.len() as u64).to_le_bytes());
+        out.extend_from_slice(&(self.refs.len() as u64).to_le_bytes());
+        match crypto {
+            Some(crypto) => {
+                let sealed = crypto.seal(&out, &body)?;
+                out.extend(sealed);
+            }
+            None => out.extend(body),
+        }
+        let checksum = Hash::digest(&out);
+        out.extend_from_slice(checksum.as_bytes());
+        Ok(out)
+    }
+
+    /// Offset of the refs section relative to the start of the record
+    pub fn refs_offset(&self) -> u64 {
+        PRELUDE_SIZE + self.entries.len() as u64 * INDEX_ENTRY_SIZE
+    }
+
+    /// Read and validate the record starting at `offset`, decoding the body
+    /// as format `version`
+    ///
+    /// Returns `Ok(None)` for anything that is not a complete, intact record
+    /// (out of bounds, wrong magic, truncated, checksum mismatch, body that
+    /// does not match `version`). An intact record that `crypto` cannot
+    /// unseal fails with `Error::WrongKey`.
+    pub fn read_at(
+        file: &mut File,
+        offset: u64,
+        file_len: u64,
+        version: u32,
+        crypto: Option<&Crypto>,
+    ) -> Result<Option<(Record, u64)>> {
+        let Some(max_body) = file_len.checked_sub(offset + PRELUDE_SIZE + CHECKSUM_SIZE) else {
+            return Ok(None);
+        };
+
+        let mut prelude = [0u8; PRELUDE_SIZE as usize];
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut prelude)?;
+        if &prelude[0..8] != RECORD_MAGIC {
+            return Ok(None);
+        }
+
+        let body_len = u64::from_le_bytes(prelude[8..16].try_into().unwrap());
+        let entry_count = u64::from_le_bytes(prelude[16..24].try_into().unwrap());
+        let refs_count = u64::from_le_bytes(prelude[24..32].try_into().unwrap());
+        if body_len > max_body {
+            return Ok(None);
+        }
+
+        let mut rest = vec![0u8; (body_len + CHECKSUM_SIZE) as usize];
+        file.read_exact(&mut rest)?;
+        let (body, checksum) = rest.split_at(body_len as usize);
+
+        let expected = Hash::digest_many(&[&prelude, body]);
+        if expected.as_bytes() != checksum {
+            return Ok(None);
+        }
+
+        let record = match crypto {
+            Some(crypto) => {
+                let body = crypto.unseal(&prelude, body)?;
+                Self::decode_body(&body, entry_count, refs_count, version)
+            }
+            None => Self::decode_body(body, entry_count, refs_count, version),
+        };
+        Ok(record.map(|r| (r, PRELUDE_SIZE + body_len + CHECKSUM_SIZE)))
+    }
+
+    /// Find the last valid record in the file, scanning backwards
+    ///
+    /// Returns the record, its offset and its encoded length.
+    pub fn scan_last(
+        file: &mut File,
+        start: u64,
+        file_len: u64,
+        version: u32,
+        crypto: Option<&Crypto>,
+    ) -> Result<Option<(Record, u64, u64)>> {
+        let overlap = RECORD_MAGIC.len() as u64 - 1;
+        let mut chunk_end = file_len;
+
+        while chunk_end > start {
+            let chunk_start = chunk_end.saturating_sub(SCAN_CHUNK).max(start);
+            let read_end = (chunk_end + overlap).min(file_len);
+
+            let mut buf = vec![0u8; (read_end - chunk_start) as usize];
+            file.seek(SeekFrom::Start(chunk_start))?;
+            file.read_exact(&mut buf)?;
+
+            let candidates: Vec<usize> = buf
+                .windows(RECORD_MAGIC.len())
+                .enumerate()
+                .filter(|(_, w)| w == RECORD_MAGIC)
+                .map(|(i, _)| i)
+                .collect();
+
+            for pos in candidates.into_iter().rev() {
+                let offset = chunk_start + pos as u64;
+                if offset >= chunk_end {
+                    // Belongs to the previous (later) chunk, already tried
+                    continue;
+                }
+                if let Some((record, len)) = Self::read_at(file, offset, file_len, version, crypto)?
+                {
+                    return Ok(Some((record, offset, len)));
+                }
+            }
+
+            chunk_end = chunk_start;
+        }
+
+        Ok(None)
+    }
+
+    fn decode_body(body: &[u8], entry_count: u64, refs_count: u64, version: u32) -> Option<Record> {
+        let mut cursor = body;
+
+        let index_len = entry_count.checked_mul(INDEX_ENTRY_SIZE)?;
+        if index_len > cursor.len() as u64 {
+            return None;
+        }
+        let mut entries = Vec::with_capacity(entry_count as usize);
+        for chunk in cursor[..index_len as usize].chunks_exact(INDEX_ENTRY_SIZE as usize) {
+            let hash = Hash::from_bytes(chunk[0..32].try_into().unwrap());
+            let offset = u64::from_le_bytes(chunk[32..40].try_into().unwrap());
+            let size = u32::from_le_bytes(chunk[40..44].try_into().unwrap());
+            entries.push((hash, offset, size));
+        }
+        cursor = &cursor[index_len as usize..];
+
+        let mut refs = Vec::new();
+        for _ in 0..refs_count {
+            let name = take_name(&mut cursor)?;
+            let hash = take_hash(&mut cursor)?;
+            refs.push((name, hash));
+        }
+
+        let mut reflog = Vec::new();
+        let mut dictionaries = Vec::new();
+        let mut prev = None;
+        let mut object_count = entries.len() as u64;
+        let mut log_prev = None;
+        let mut log_len = 0;
+        let mut dicts_at = None;
+        let head = if version == 1 {
+            Head::Branch(take_name(&mut cursor)?)
+        } else {
+            let (&kind, rest) = cursor.split_first()?;
+            cursor = rest;
+            let head = match kind {
+                0 => Head::Branch(take_name(&mut cursor)?),
+                1 => Head::Detached(take_hash(&mut cursor)?),
+                _ => return None,
+            };
+
+            let count = u64::from_le_bytes(take(&mut cursor, 8)?.try_into().unwrap());
+            for _ in 0..count {
+                let name = take_name(&mut cursor)?;
+                let old = take_hash(&mut cursor)?;
+                let new = take_hash(&mut cursor)?;
+                let timestamp = u64::from_le_bytes(take(&mut cursor, 8)?.try_into().unwrap());
+                let reason_len = u32::from_le_bytes(take(&mut cursor, 4)?.try_into().unwrap());
+                let reason =
+                    String::from_utf8(take(&mut cursor, reason_len as usize)?.to_vec()).ok()?;
+                reflog.push(RefLogEntry {
+                    name,
+                    old,
+                    new,
+                    timestamp,
+                    reason,
+                });
+            }
+
+            let (&count, rest) = cursor.split_first()?;
+            cursor = rest;
+            for _ in 0..count {
+                let blob_type = BlobType::from_byte(take(&mut cursor, 1)?[0])?;
+                let len = u32::from_le_bytes(take(&mut cursor, 4)?.try_into().unwrap());
+                dictionaries.push((blob_type, take(&mut cursor, len as usize)?.to_vec()));
+            }
+
+            let offset = u64::from_le_bytes(take(&mut cursor, 8)?.try_into().unwrap());
+            prev = (offset != 0).then_some(offset);
+            object_count = u64::from_le_bytes(take(&mut cursor, 8)?.try_into().unwrap());
+            let offset = u64::from_le_bytes(take(&mut cursor, 8)?.try_into().unwrap());
+            log_prev = (offset != 0).then_some(offset);
+            log_len = u64::from_le_bytes(take(&mut cursor, 8)?.try_into().unwrap());
+            let offset = u64::from_le_bytes(take(&mut cursor, 8)?.try_into().unwrap());
+            dicts_at = (offset != 0).then_some(offset);
+            if dicts_at.is_some() && !dictionaries.is_empty() {
+                return None;
+            }
+            head
+        };
+        if !cursor.is_empty() {
+            return None;
+        }
+
+        Some(Record {
+            entries,
+            prev,
+            object_count,
+            refs,
+            head,
+            reflog,
+            log_prev,
+            log_len,
+            dictionaries,
+            dicts_at,
+        })
+    }
+}
+
+/// Append a u16-length-prefixed string
+fn put_name(out: &mut Vec<u8>, name: &str) {
+    out.extend_from_slice(&(name.len() as u16).to_le_bytes());
+    out.extend_from_slice(name.as_bytes());
+}
+
+/// Split `len` bytes off the front of the cursor
+fn take<'a>(cursor: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+    if cursor.len() < len {
+        return None;
+    }
+    let (head, rest) = cursor.split_at(len);
+    *cursor = rest;
+    Some(head)
+}
+
+/// Read a u16-length-prefixed UTF-8 string
+fn take_name(cursor: &mut &[u8]) -> Option<String> {
+    let len = u16::from_le_bytes(take(cursor, 2)?.try_into().unwrap()) as usize;
+    String::from_utf8(take(cursor, len)?.to_vec()).ok()
+}
+
+/// Read a 32-byte hash
+fn take_hash(cursor: &mut &[u8]) -> Option<Hash> {
+    Some(Hash::from_bytes(take(cursor, 32)?.try_into().unwrap()))
+}
@@ -0,0 +1,64 @@
+//! Advisory inter-process locking
+//!
+//! Each open store holds an OS-level lock on a `<db>.lock` file next to the
+//! database: shared for read-only stores, exclusive for writable ones. The
+//! lock lives on a separate file so it keeps working when `gc` swaps a new
+//! database file into place.
+
+use super::file_store::sidecar_path;
+use crate::{Error, Result};
+use std::fs::{File, OpenOptions, TryLockError};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Longest pause between two attempts to take a contended lock
+const MAX_BACKOFF: Duration = Duration::from_millis(50);
+
+/// The lock file of the database at `db_path`
+pub(crate) fn lock_path(db_path: &Path) -> PathBuf {
+    sidecar_path(db_path, "lock")
+}
+
+/// A held lock, released when dropped
+#[derive(Debug)]
+pub(crate) struct FileLock {
+    _file: File,
+}
+
+impl FileLock {
+    /// Lock the database at `db_path`, waiting up to `timeout`
+    ///
+    /// Returns `Error::Locked` if the lock is still held by another process
+    /// (or another store in this process) when the timeout expires.
+    pub(crate) fn acquire(db_path: &Path, exclusive: bool, timeout: Duration) -> Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(lock_path(db_path))?;
+
+        let deadline = Instant::now() + timeout;
+        let mut backoff = Duration::from_millis(1);
+        loop {
+            let attempt = if exclusive {
+                file.try_lock()
+            } else {
+                file.try_lock_shared()
+            };
+
+            match attempt {
+                Ok(()) => return Ok(FileLock { _file: file }),
+                Err(TryLockError::WouldBlock) => {}
+                Err(TryLockError::Error(e)) => return Err(e.into()),
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(Error::Locked);
+            }
+            std::thread::sleep(backoff.min(deadline - now));
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+}
@@ -0,0 +1,227 @@
+//! In-memory storage backend
+//!
+//! Keeps every blob uncompressed in a hash map. Nothing touches disk and
+//! everything is lost when the store is dropped, which suits ephemeral
+//! scratch memories and tests.
+
+use crate::model::Hash;
+use crate::store::refs::RefTable;
+use crate::store::{reachable_objects, Blob, GcStats, Head, RefLogEntry, RepackStats, Storage};
+use crate::trie::{NodeCache, DEFAULT_NODE_CACHE_SIZE};
+use crate::{Error, Result};
+use parking_lot::RwLock;
+use std::collections::HashMap;
+
+/// A content-addressed object store held entirely in memory
+pub struct MemoryStore {
+    /// Objects by hash
+    objects: RwLock<HashMap<Hash, Blob>>,
+    /// Refs, HEAD and reflog
+    refs: RwLock<RefTable>,
+    /// Decoded trie nodes, so lookups skip deserializing
+    node_cache: NodeCache,
+}
+
+impl MemoryStore {
+    /// Create an empty store with a `main` branch
+    pub fn new() -> Self {
+        MemoryStore {
+            objects: RwLock::new(HashMap::new()),
+            refs: RwLock::new(RefTable::new()),
+            node_cache: NodeCache::new(DEFAULT_NODE_CACHE_SIZE),
+        }
+    }
+
+    /// Total uncompressed size of all objects
+    fn data_size(&self) -> u64 {
+        let objects = self.objects.read();
+        objects.values().map(|blob| blob.size() as u64).sum()
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Storage for MemoryStore {
+    fn put(&self, blob: &Blob) -> Result<Hash> {
+        let hash = blob.hash();
+        self.objects
+            .write()
+            .entry(hash)
+            .or_insert_with(|| blob.clone());
+        Ok(hash)
+    }
+
+    fn get(&self, hash: &Hash) -> Result<Blob> {
+        let objects = self.objects.read();
+        objects
+            .get(hash)
+            .cloned()
+            .ok_or_else(|| Error::NotFound(hash.to_hex()))
+    }
+
+    fn contains(&self, hash: &Hash) -> bool {
+        self.objects.read().contains_key(hash)
+    }
+
+    fn hashes(&self) -> Vec<Hash> {
+        self.objects.read().keys().copied().collect()
+    }
+
+    fn object_count(&self) -> usize {
+        self.objects.read().len()
+    }
+
+    /// Objects are kept uncompressed
+    fn stored_size(&self, hash: &Hash) -> Option<u64> {
+        self.objects.read().get(hash).map(|blob| blob.size() as u64)
+    }
+
+    fn node_cache(&self) -> Option<&NodeCache> {
+        Some(&self.node_cache)
+    }
+
+    fn remove_objects(&self, hashes: &[Hash]) -> Result<usize> {
+        let mut objects = self.objects.write();
+        self.node_cache.clear();
+        Ok(hashes
+            .iter()
+            .filter(|hash| objects.remove(hash).is_some())
+            .count())
+    }
+
+    fn head(&self) -> Head {
+        self.refs.read().head.clone()
+    }
+
+    fn set_head(&self, head: Head, reason: &str) -> Result<()> {
+        self.refs.write().set_head(head, reason)
+    }
+
+    fn get_ref(&self, ref_name: &str) -> Option<Hash> {
+        self.refs.read().refs.get(ref_name).copied()
+    }
+
+    fn set_ref(&self, ref_name: &str, commit_hash: Hash, reason: &str) -> Result<()> {
+        self.refs.write().set_ref(ref_name, commit_hash, reason)
+    }
+
+    fn list_refs(&self) -> Vec<(String, Hash)> {
+        let refs = self.refs.read();
+        refs.refs.iter().map(|(k, v)| (k.clone(), *v)).collect()
+    }
+
+    fn create_branch(&self, name: &str, commit_hash: Hash) -> Result<()> {
+        self.refs.write().create_branch(name, commit_hash)
+    }
+
+    fn delete_branch(&self, name: &str) -> Result<()> {
+        self.refs.write().delete_branch(name)
+    }
+
+    fn reflog(&self) -> Vec<RefLogEntry> {
+        self.refs.read().log.clone()
+    }
+
+    /// Nothing to flush
+    fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Drop unreachable objects
+    ///
+    /// Byte counts are uncompressed object sizes.
+    fn gc(&self, keep: &[Hash]) -> Result<GcStats> {
+        let mut roots = self.refs.read().roots();
+        roots.extend_from_slice(keep);
+        let live = reachable_objects(self, roots)?;
+
+        let objects_before = self.object_count();
+        let bytes_before = self.data_size();
+        self.objects.write().retain(|hash, _| live.contains(hash));
+        self.node_cache.clear();
+
+        Ok(GcStats {
+            objects_before,
+            objects_after: self.object_count(),
+            bytes_before,
+            bytes_after: self.data_size(),
+        })
+    }
+
+    /// Objects are kept uncompressed, so there is nothing to pack
+    fn repack(&self) -> Result<RepackStats> {
+        let size = self.data_size();
+        Ok(RepackStats {
+            objects: self.object_count(),
+            uncompressed_bytes: size,
+            stored_bytes_before: size,
+            stored_bytes_after: size,
+            bytes_before: size,
+            bytes_after: size,
+            ..Default::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{Commit, Thought};
+
+    #[test]
+    fn test_memory_store_roundtrip() {
+        let store = MemoryStore::new();
+        let thought = Thought::new("Only in memory");
+        let hash = store.put_thought(&thought).unwrap();
+
+        assert!(store.contains(&hash));
+        assert_eq!(store.get_thought(&hash).unwrap().content, "Only in memory");
+        assert!(store.get_edge(&hash).is_err());
+
+        // Deduplicated like the file store
+        store.put_thought(&thought).unwrap();
+        assert_eq!(store.object_count(), 1);
+    }
+
+    #[test]
+    fn test_memory_store_refs() {
+        let store = MemoryStore::new();
+        assert_eq!(store.head(), Head::Branch("main".into()));
+        assert_eq!(store.head_commit(), None);
+
+        let commit = Commit::initial(Hash::ZERO, "Initial", "test");
+        let hash = store.put_commit(&commit).unwrap();
+        store.set_ref("main", hash, "commit: Initial").unwrap();
+        assert_eq!(store.head_commit(), Some(hash));
+
+        store.create_branch("feature", hash).unwrap();
+        assert!(store.create_branch("feature", hash).is_err());
+        store
+            .set_head(Head::Branch("feature".into()), "checkout")
+            .unwrap();
+        assert!(store.delete_branch("feature").is_err());
+        assert!(store
+            .set_head(Head::Branch("missing".into()), "checkout")
+            .is_err());
+        assert_eq!(store.reflog().len(), 3);
+    }
+
+    #[test]
+    fn test_memory_store_gc() {
+        let store = MemoryStore::new();
+        let commit = store
+            .put_commit(&Commit::initial(Hash::ZERO, "Empty tree", "test"))
+            .unwrap();
+        store.set_ref("main", commit, "commit: Empty tree").unwrap();
+        let orphan = store.put_thought(&Thought::new("Never committed")).unwrap();
+
+        let stats = store.gc(&[]).unwrap();
+        assert_eq!(stats.objects_removed(), 1);
+        assert!(store.contains(&commit));
+        assert!(!store.contains(&orphan));
+    }
+}
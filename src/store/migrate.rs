@@ -0,0 +1,378 @@
+//! On-disk format migrations
+//!
+//! Every change to the file layout bumps `VERSION` and registers a
+//! `Migration` that upgrades a file from the previous version in place.
+//! `migrate` backs the file up next to itself, then applies the registered
+//! steps one version at a time until the file is current. The header
+//! version is bumped only after a step has finished and been flushed, so an
+//! interrupted migration resumes from the last completed step.
+//!
+//! Files from older versions stay readable: `ObjectStore` opens them
+//! read-only, and refuses writable opens with `Error::MigrationRequired`.
+//! Encrypted files need their key to be migrated.
+
+use super::crypto::Crypto;
+use super::file_store::{
+    load_state, open_crypto, read_version, sidecar_path, write_record, write_version,
+};
+use crate::store::{EncryptionKey, FileLock, StoreOptions};
+use crate::{Error, Result, VERSION};
+use std::fs::{File, OpenOptions};
+use std::path::{Path, PathBuf};
+
+/// A single upgrade step from `from` to `from + 1`
+pub struct Migration {
+    /// Version this step upgrades from
+    pub from: u32,
+    /// What the step changes, shown to the user
+    pub description: &'static str,
+    /// Rewrite the file in place
+    ///
+    /// Must be safe to re-run on a file it was interrupted on: the header
+    /// still says `from` until the step returns.
+    pub apply: fn(&mut MigrationFile) -> Result<()>,
+}
+
+/// A database file being migrated
+pub struct MigrationFile {
+    /// The file, open for reading and writing
+    pub file: File,
+    /// Cipher of an encrypted file
+    crypto: Option<Crypto>,
+}
+
+/// Registered migrations, in version order
+pub const MIGRATIONS: &[Migration] = &[Migration {
+    from: 1,
+    description: "Move HEAD into commit records and add a reflog and index segments",
+    apply: v1_to_v2,
+}];
+
+/// Re-append the current state as a v2 commit record
+///
+/// v1 kept HEAD in a 14-byte header slot (or a v1 record) and had no
+/// reflog. The new record starts with an empty reflog, no dictionaries and
+/// the full index, which later syncs extend with segments. Objects are left
+/// in place; only the index and refs are rewritten. Deltas, encryption and
+/// attachments need nothing from existing files.
+fn v1_to_v2(file: &mut MigrationFile) -> Result<()> {
+    reencode_record(file, 1)
+}
+
+/// Append the state of a `from` file as a `from + 1` commit record
+///
+/// The header's version and record pointer change in a single write, since
+/// an older reader cannot parse the new record.
+fn reencode_record(target: &mut MigrationFile, from: u32) -> Result<()> {
+    let crypto = target.crypto.as_ref();
+    let (record, end, ..) = load_state(&mut target.file, from, crypto)?;
+    write_record(&mut target.file, &record, end, from + 1, crypto)?;
+    Ok(())
+}
+
+/// Outcome of a migration
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MigrationReport {
+    /// Version the file was at
+    pub from: u32,
+    /// Version the file is at now
+    pub to: u32,
+    /// Copy of the original file, if anything was migrated
+    pub backup: Option<PathBuf>,
+    /// Descriptions of the steps applied, in order
+    pub applied: Vec<&'static str>,
+}
+
+impl MigrationReport {
+    /// Whether the file was already current
+    pub fn is_noop(&self) -> bool {
+        self.applied.is_empty()
+    }
+}
+
+/// Read the format version of a database file without opening it
+pub fn file_version(path: impl AsRef<Path>) -> Result<u32> {
+    read_version(&mut File::open(path)?)
+}
+
+/// Upgrade a database file to the current `VERSION`
+///
+/// Takes the exclusive lock (waiting up to `options.lock_timeout`) and
+/// copies the file to `<db>.v<N>.bak` before touching it. Does nothing if
+/// the file is already current. Encrypted files need `options.key`.
+pub fn migrate(path: impl AsRef<Path>, options: StoreOptions) -> Result<MigrationReport> {
+    let path = path.as_ref();
+    if options.read_only {
+        return Err(Error::ReadOnly);
+    }
+    if !path.exists() {
+        return Err(std::io::Error::from(std::io::ErrorKind::NotFound).into());
+    }
+    let _lock = FileLock::acquire(path, true, options.lock_timeout)?;
+    run_migrations(path, MIGRATIONS, VERSION, options.key.as_ref())
+}
+
+/// Apply `migrations` until the file at `path` reaches `target`
+///
+/// The caller must hold the exclusive lock.
+fn run_migrations(
+    path: &Path,
+    migrations: &[Migration],
+    target: u32,
+    key: Option<&EncryptionKey>,
+) -> Result<MigrationReport> {
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let from = read_version(&mut file)?;
+    if from == 0 || from > target {
+        return Err(Error::VersionMismatch {
+            expected: target,
+            found: from,
+        });
+    }
+
+    // Resolve the whole chain before changing anything
+    let steps = (from..target)
+        .map(|version| {
+            migrations
+                .iter()
+                .find(|m| m.from == version)
+                .ok_or_else(|| {
+                    Error::InvalidFile(format!("No migration from format version {}", version))
+                })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    if steps.is_empty() {
+        return Ok(MigrationReport {
+            from,
+            to: from,
+            backup: None,
+            applied: Vec::new(),
+        });
+    }
+
+    let crypto = open_crypto(&mut file, key)?;
+    let backup = sidecar_path(path, &format!("v{}.bak", from));
+    std::fs::copy(path, &backup)?;
+
+    let mut target_file = MigrationFile { file, crypto };
+    let mut applied = Vec::with_capacity(steps.len());
+    for step in steps {
+        (step.apply)(&mut target_file)?;
+        target_file.file.sync_all()?;
+        write_version(&mut target_file.file, step.from + 1)?;
+        applied.push(step.description);
+    }
+
+    Ok(MigrationReport {
+        from,
+        to: target,
+        backup: Some(backup),
+        applied,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{ObjectStore, Storage};
+    use crate::Database;
+    use std::io::{Seek, SeekFrom, Write};
+    use tempfile::tempdir;
+
+    /// Copy a checked-in v1 fixture into `dir`
+    fn fixture(dir: &Path, name: &str) -> PathBuf {
+        let source = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/v1")
+            .join(name);
+        let path = dir.join(name);
+        std::fs::copy(source, &path).unwrap();
+        path
+    }
+
+    /// Check the contents every v1 fixture was generated with
+    fn assert_fixture_contents(db: &Database) {
+        let note = db.get_thought(&"note".into()).unwrap().unwrap();
+        assert_eq!(note.content, "Second draft");
+        assert_eq!(db.list_thoughts().unwrap().len(), 3);
+        assert_eq!(db.log(None).unwrap().len(), 5);
+        assert_eq!(
+            db.neighbors(&"cat".into(), crate::TraversalDirection::Outgoing)
+                .unwrap()
+                .len(),
+            1
+        );
+
+        let branches: std::collections::HashMap<_, _> = db.list_branches().into_iter().collect();
+        assert_eq!(branches.len(), 2);
+        assert_ne!(branches["feature"], branches["main"]);
+    }
+
+    #[test]
+    fn test_v1_fixtures_readable() {
+        let dir = tempdir().unwrap();
+        for name in ["legacy.indra", "journal.indra"] {
+            let path = fixture(dir.path(), name);
+            assert_eq!(file_version(&path).unwrap(), 1);
+
+            let db = Database::open_read_only(&path).unwrap();
+            assert_fixture_contents(&db);
+            assert!(db.fsck(false).unwrap().is_ok(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_migrate_current_file_is_noop() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.indra");
+        ObjectStore::create(&path).unwrap();
+
+        let report = migrate(&path, StoreOptions::default()).unwrap();
+        assert!(report.is_noop());
+        assert_eq!(report.from, VERSION);
+        assert_eq!(report.backup, None);
+    }
+
+    /// A v1 → v2 step that appends a marker byte
+    fn append_marker(target: &mut MigrationFile) -> Result<()> {
+        target.file.seek(SeekFrom::End(0))?;
+        target.file.write_all(b"!")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_migrations_backs_up_and_bumps_version() {
+        let dir = tempdir().unwrap();
+        let path = fixture(dir.path(), "journal.indra");
+        let original = std::fs::read(&path).unwrap();
+
+        let steps = [Migration {
+            from: 1,
+            description: "append marker",
+            apply: append_marker,
+        }];
+        let report = run_migrations(&path, &steps, 2, None).unwrap();
+        assert_eq!(report.from, 1);
+        assert_eq!(report.to, 2);
+        assert_eq!(report.applied, vec!["append marker"]);
+
+        let backup = report.backup.unwrap();
+        assert_eq!(backup, dir.path().join("journal.indra.v1.bak"));
+        assert_eq!(std::fs::read(&backup).unwrap(), original);
+
+        let migrated = std::fs::read(&path).unwrap();
+        assert_eq!(file_version(&path).unwrap(), 2);
+        assert_eq!(migrated.len(), original.len() + 1);
+    }
+
+    #[test]
+    fn test_run_migrations_missing_step_changes_nothing() {
+        let dir = tempdir().unwrap();
+        let path = fixture(dir.path(), "legacy.indra");
+        let original = std::fs::read(&path).unwrap();
+
+        let steps = [Migration {
+            from: 2,
+            description: "unreachable",
+            apply: append_marker,
+        }];
+        assert!(run_migrations(&path, &steps, 3, None).is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), original);
+        assert!(!dir.path().join("legacy.indra.v1.bak").exists());
+    }
+
+    #[test]
+    fn test_newer_version_rejected() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.indra");
+        ObjectStore::create(&path).unwrap();
+        {
+            let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+            write_version(&mut file, VERSION + 1).unwrap();
+        }
+
+        assert!(matches!(
+            migrate(&path, StoreOptions::default()),
+            Err(Error::VersionMismatch { .. })
+        ));
+        assert!(matches!(
+            ObjectStore::open_with_options(&path, StoreOptions::default().with_read_only(true)),
+            Err(Error::VersionMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_migrate_encrypted_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.indra");
+        let options = StoreOptions::default()
+            .with_key(EncryptionKey::Passphrase("hunter2".into()))
+            .with_kdf_iterations(1000);
+        let hash = {
+            let store = ObjectStore::create_with_options(&path, options.clone()).unwrap();
+            let hash = store.put_thought(&crate::Thought::new("Sealed")).unwrap();
+            store.set_ref("main", hash, "test").unwrap();
+            hash
+        };
+
+        // Rewrite the newest record as v1 to get a file that needs migrating
+        {
+            let mut file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&path)
+                .unwrap();
+            let crypto = open_crypto(&mut file, options.key.as_ref()).unwrap();
+            let (record, end, ..) = load_state(&mut file, VERSION, crypto.as_ref()).unwrap();
+            write_record(&mut file, &record, end, 1, crypto.as_ref()).unwrap();
+        }
+
+        assert!(matches!(
+            migrate(&path, StoreOptions::default()),
+            Err(Error::KeyRequired)
+        ));
+        assert_eq!(file_version(&path).unwrap(), 1);
+
+        let report = migrate(&path, options.clone()).unwrap();
+        assert_eq!((report.from, report.to), (1, VERSION));
+        let store = ObjectStore::open_with_options(&path, options).unwrap();
+        assert!(store.is_encrypted());
+        assert_eq!(store.get_ref("main"), Some(hash));
+    }
+
+    #[test]
+    fn test_v1_fixtures_migrate() {
+        let dir = tempdir().unwrap();
+        for name in ["legacy.indra", "journal.indra"] {
+            let path = fixture(dir.path(), name);
+            assert!(matches!(
+                ObjectStore::open(&path),
+                Err(Error::MigrationRequired { found: 1, .. })
+            ));
+
+            let report = migrate(&path, StoreOptions::default()).unwrap();
+            assert_eq!((report.from, report.to), (1, VERSION));
+            assert_eq!(report.applied.len(), MIGRATIONS.len());
+            assert!(report.backup.unwrap().exists());
+            assert_eq!(file_version(&path).unwrap(), VERSION);
+
+            let mut db = Database::open(&path).unwrap();
+            assert_fixture_contents(&db);
+            assert_eq!(db.current_branch().as_deref(), Some("main"));
+            assert!(db.reflog(None, None).is_empty());
+            assert!(db.fsck(false).unwrap().is_ok(), "{}", name);
+
+            db.checkout("feature").unwrap();
+            assert_eq!(db.list_thoughts().unwrap().len(), 4);
+            assert_eq!(db.reflog(None, None).len(), 1);
+            drop(db);
+
+            // Checkout and reflog survive a reopen of the migrated file
+            let db = Database::open(&path).unwrap();
+            assert_eq!(db.current_branch().as_deref(), Some("feature"));
+            assert_eq!(db.reflog(None, None).len(), 1);
+            drop(db);
+            assert!(migrate(&path, StoreOptions::default()).unwrap().is_noop());
+        }
+    }
+}
@@ -1,10 +1,39 @@
 //! Content-addressed object store
 //!
 //! This module implements the core storage layer using content-addressed blobs.
-//! Objects are stored by their BLAKE3 hash and compressed with zstd.
+//! Objects are stored by their BLAKE3 hash and compressed with zstd, either
+//! on their own or, after a repack, as deltas against similar objects or
+//! against a dictionary trained per blob type.
+//!
+//! Backends implement `Storage`: `ObjectStore` keeps everything in a single
+//! file, `MemoryStore` keeps it in memory.
 
+mod attachment;
 mod blob;
+mod crypto;
+mod dict;
 mod file_store;
+mod journal;
+mod lock;
+mod memory;
+mod migrate;
+mod options;
+mod pack;
+mod reachable;
+mod refs;
+mod storage;
 
+pub(crate) use attachment::attachment_chunks;
+pub use attachment::{put_attachment, AttachmentReader, ATTACHMENT_CHUNK_SIZE};
 pub use blob::{Blob, BlobType};
-pub use file_store::ObjectStore;
+pub use crypto::{EncryptionKey, DEFAULT_KDF_ITERATIONS};
+pub use dict::{DICT_SIZE, SMALL_BLOB_SIZE};
+pub use file_store::{FileUsage, GcStats, ObjectStore};
+pub(crate) use lock::{lock_path, FileLock};
+pub use memory::MemoryStore;
+pub use migrate::{file_version, migrate, Migration, MigrationFile, MigrationReport, MIGRATIONS};
+pub use options::{StoreOptions, DEFAULT_LOCK_TIMEOUT};
+pub use pack::{RepackStats, DELTA_WINDOW, MAX_DELTA_DEPTH};
+pub use reachable::reachable_objects;
+pub use refs::{validate_ref_name, Head, RefLogEntry, HEAD_REF, MAX_REF_NAME_LEN};
+pub use storage::Storage;
@@ -0,0 +1,88 @@
+//! Options for opening an object store
+
+use crate::store::{EncryptionKey, DEFAULT_KDF_ITERATIONS};
+use crate::trie::DEFAULT_NODE_CACHE_SIZE;
+use std::time::Duration;
+
+/// Default time to wait for another process to release the database lock
+pub const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Options controlling how an `ObjectStore` is opened
+#[derive(Clone, Debug)]
+pub struct StoreOptions {
+    /// Open without write access, taking a shared lock instead of an
+    /// exclusive one so that several readers can run side by side
+    pub read_only: bool,
+    /// How long to wait for a conflicting lock before giving up with
+    /// `Error::Locked`
+    pub lock_timeout: Duration,
+    /// Key for encrypted stores
+    ///
+    /// New stores created with a key are encrypted. Existing stores that
+    /// are not encrypted open as usual and ignore it.
+    pub key: Option<EncryptionKey>,
+    /// PBKDF2 iterations when creating an encrypted store
+    pub kdf_iterations: u32,
+    /// Re-hash every object read and fail with `Error::CorruptObject` if it
+    /// does not match the hash it was requested by
+    pub verify_reads: bool,
+    /// Decoded trie nodes to keep in memory, 0 to decode on every read
+    pub node_cache_size: usize,
+}
+
+impl Default for StoreOptions {
+    fn default() -> Self {
+        StoreOptions {
+            read_only: false,
+            lock_timeout: DEFAULT_LOCK_TIMEOUT,
+            key: None,
+            kdf_iterations: DEFAULT_KDF_ITERATIONS,
+            verify_reads: true,
+            node_cache_size: DEFAULT_NODE_CACHE_SIZE,
+        }
+    }
+}
+
+impl StoreOptions {
+    /// Default options: writable, waiting up to `DEFAULT_LOCK_TIMEOUT`
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Open read-only
+    pub fn with_read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Set how long to wait for the lock
+    pub fn with_lock_timeout(mut self, timeout: Duration) -> Self {
+        self.lock_timeout = timeout;
+        self
+    }
+
+    /// Encrypt new stores with, and open encrypted stores with, this key
+    pub fn with_key(mut self, key: EncryptionKey) -> Self {
+        self.key = Some(key);
+        self
+    }
+
+    /// Set the PBKDF2 iteration count for new encrypted stores
+    pub fn with_kdf_iterations(mut self, iterations: u32) -> Self {
+        self.kdf_iterations = iterations;
+        self
+    }
+
+    /// Skip re-hashing objects on read, for hot paths that can trust the
+    /// file
+    pub fn with_verify_reads(mut self, verify: bool) -> Self {
+        self.verify_reads = verify;
+        self
+    }
+
+    /// Set how many decoded trie nodes to cache
+    pub fn with_node_cache_size(mut self, nodes: usize) -> Self {
+        self.node_cache_size = nodes;
+        self
+    }
+}
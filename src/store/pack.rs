@@ -0,0 +1,325 @@
+//! Delta-compressed objects and repacking
+//!
+//! An object can be stored as a delta against a base object instead of on
+//! its own. The delta is a zstd frame compressed with the base's
+//! uncompressed bytes as a raw-content dictionary, so whatever the two
+//! share (the embedding of a thought whose text changed by one word, most
+//! of a trie node) costs next to nothing.
+//!
+//! Stored delta layout:
+//! ```text
+//!   - tag: 1 byte (DELTA_TAG)
+//!   - blob_type: 1 byte
+//!   - base: 32 bytes (hash of the base object)
+//!   - delta: zstd frame, dictionary = base data
+//! ```
+//!
+//! Plain objects start with their blob type byte, which never equals
+//! `DELTA_TAG` (or `dict::DICT_TAG`). Deltas are only written by `repack`;
+//! `put` always stores objects whole.
+
+use crate::model::{Hash, Thought};
+use crate::store::dict::{Dictionaries, Trainer};
+use crate::store::{Blob, BlobType};
+use crate::{Error, Result};
+use std::collections::VecDeque;
+use std::io::Read;
+
+/// First byte of a delta-encoded object
+pub const DELTA_TAG: u8 = 0x80;
+
+/// Longest chain of deltas `repack` builds before storing an object whole
+pub const MAX_DELTA_DEPTH: usize = 16;
+
+/// How many preceding objects `repack` tries as a base for each object
+pub const DELTA_WINDOW: usize = 10;
+
+/// zstd level, same as `Blob::compress`
+const LEVEL: i32 = 3;
+
+/// tag + type + base hash
+const DELTA_PREFIX: usize = 2 + 32;
+
+/// Outcome of a repack
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RepackStats {
+    /// Objects written
+    pub objects: usize,
+    /// Objects stored as deltas
+    pub deltas: usize,
+    /// Blob types with a trained compression dictionary
+    pub dictionaries: usize,
+    /// Total size of every object uncompressed
+    pub uncompressed_bytes: u64,
+    /// Total stored size of every object before repacking
+    pub stored_bytes_before: u64,
+    /// Total stored size of every object after repacking
+    pub stored_bytes_after: u64,
+    /// File size before repacking
+    pub bytes_before: u64,
+    /// File size after repacking
+    pub bytes_after: u64,
+}
+
+impl RepackStats {
+    /// Number of bytes the file shrank by
+    pub fn bytes_reclaimed(&self) -> u64 {
+        self.bytes_before.saturating_sub(self.bytes_after)
+    }
+
+    /// Uncompressed size over stored size, before repacking
+    pub fn ratio_before(&self) -> f64 {
+        ratio(self.uncompressed_bytes, self.stored_bytes_before)
+    }
+
+    /// Uncompressed size over stored size, after repacking
+    pub fn ratio_after(&self) -> f64 {
+        ratio(self.uncompressed_bytes, self.stored_bytes_after)
+    }
+}
+
+fn ratio(uncompressed: u64, stored: u64) -> f64 {
+    if stored == 0 {
+        1.0
+    } else {
+        uncompressed as f64 / stored as f64
+    }
+}
+
+/// What `pack_objects` produced besides the emitted objects
+pub(crate) struct Packed {
+    /// Objects emitted as deltas
+    pub deltas: usize,
+    /// Dictionaries trained from the objects; non-delta objects were
+    /// compressed with them
+    pub dictionaries: Dictionaries,
+    /// Total size of every object uncompressed
+    pub uncompressed_bytes: u64,
+}
+
+/// The base a stored object is a delta against, if it is one
+pub(crate) fn delta_base(raw: &[u8]) -> Option<Hash> {
+    if raw.len() < DELTA_PREFIX || raw[0] != DELTA_TAG {
+        return None;
+    }
+    Some(Hash::from_bytes(raw[2..DELTA_PREFIX].try_into().unwrap()))
+}
+
+/// Encode `target` as a delta against `base`
+pub(crate) fn encode_delta(base_hash: &Hash, base: &Blob, target: &Blob) -> Result<Vec<u8>> {
+    let mut compressor = zstd::bulk::Compressor::with_dictionary(LEVEL, &base.data)?;
+    let delta = compressor.compress(&target.data)?;
+
+    let mut output = Vec::with_capacity(DELTA_PREFIX + delta.len());
+    output.push(DELTA_TAG);
+    output.push(target.blob_type.as_byte());
+    output.extend_from_slice(base_hash.as_bytes());
+    output.extend(delta);
+    Ok(output)
+}
+
+/// Decode a stored object, fetching its base through `base` if it is a
+/// delta
+pub(crate) fn decode(
+    raw: &[u8],
+    dicts: &Dictionaries,
+    base: impl FnOnce(&Hash) -> Result<Blob>,
+) -> Result<Blob> {
+    let Some(base_hash) = delta_base(raw) else {
+        return dicts.decompress(raw);
+    };
+
+    let blob_type = BlobType::from_byte(raw[1])
+        .ok_or_else(|| Error::Corruption(format!("Invalid blob type: {}", raw[1])))?;
+    let base = base(&base_hash)?;
+
+    let mut decoder =
+        zstd::stream::read::Decoder::with_dictionary(&raw[DELTA_PREFIX..], &base.data)?;
+    let mut data = Vec::new();
+    decoder.read_to_end(&mut data)?;
+    Ok(Blob::new(blob_type, data))
+}
+
+/// Order in which `pack_objects` writes objects
+///
+/// Versions of the same thought end up adjacent, newest first, so the
+/// version read most often is stored whole and older ones are deltas
+/// against it. Everything else is grouped by type, largest first.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct PackKey {
+    blob_type: u8,
+    thought_id: String,
+    newest: std::cmp::Reverse<u64>,
+    size: std::cmp::Reverse<usize>,
+    hash: [u8; 32],
+}
+
+impl PackKey {
+    fn new(hash: &Hash, blob: &Blob) -> Self {
+        let (thought_id, modified_at) = match blob.blob_type {
+            BlobType::Thought => bincode::deserialize::<Thought>(&blob.data)
+                .map(|t| (t.id.0, t.modified_at))
+                .unwrap_or_default(),
+            _ => Default::default(),
+        };
+        PackKey {
+            blob_type: blob.blob_type.as_byte(),
+            thought_id,
+            newest: std::cmp::Reverse(modified_at),
+            size: std::cmp::Reverse(blob.size()),
+            hash: *hash.as_bytes(),
+        }
+    }
+}
+
+/// Choose a representation for every object in `hashes` and hand each to
+/// `emit` with its stored bytes
+///
+/// A first pass orders the objects and trains compression dictionaries
+/// from them (see `dict`). Each object is then tried as a delta against the
+/// previous `DELTA_WINDOW` objects of the same type and stored as the
+/// smallest of those deltas, or whole if nothing beats compressing it
+/// alone. Bases are always emitted before the deltas that use them.
+pub(crate) fn pack_objects(
+    hashes: Vec<Hash>,
+    get: impl Fn(&Hash) -> Result<Blob>,
+    mut emit: impl FnMut(Hash, &[u8]) -> Result<()>,
+) -> Result<Packed> {
+    let mut trainer = Trainer::default();
+    let mut uncompressed_bytes = 0;
+    let mut keys = hashes
+        .iter()
+        .map(|hash| {
+            let blob = get(hash)?;
+            trainer.add(&blob);
+            uncompressed_bytes += blob.size() as u64;
+            Ok(PackKey::new(hash, &blob))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    keys.sort();
+    let dicts = trainer.train()?;
+
+    let mut window: VecDeque<(Hash, Blob, usize)> = VecDeque::with_capacity(DELTA_WINDOW + 1);
+    let mut deltas = 0;
+    for key in keys {
+        let hash = Hash::from_bytes(key.hash);
+        let blob = get(&hash)?;
+        if window
+            .back()
+            .is_some_and(|(_, prev, _)| prev.blob_type != blob.blob_type)
+        {
+            window.clear();
+        }
+
+        let mut best = dicts.compress(&blob)?;
+        let mut depth = 0;
+        for (base_hash, base, base_depth) in &window {
+            if *base_depth >= MAX_DELTA_DEPTH {
+                continue;
+            }
+            let delta = encode_delta(base_hash, base, &blob)?;
+            if delta.len() < best.len() {
+                best = delta;
+                depth = base_depth + 1;
+            }
+        }
+
+        if depth > 0 {
+            deltas += 1;
+        }
+        emit(hash, &best)?;
+
+        window.push_back((hash, blob, depth));
+        if window.len() > DELTA_WINDOW {
+            window.pop_front();
+        }
+    }
+
+    Ok(Packed {
+        deltas,
+        dictionaries: dicts,
+        uncompressed_bytes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn versions(count: usize) -> Vec<Blob> {
+        let embedding: Vec<f32> = (0..384).map(|i| (i as f32 * 0.37).sin()).collect();
+        (0..count)
+            .map(|i| {
+                let mut thought = Thought::with_id("note", format!("Draft number {}", i))
+                    .with_embedding(embedding.clone());
+                thought.modified_at = i as u64;
+                Blob::new(BlobType::Thought, bincode::serialize(&thought).unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_delta_roundtrip() {
+        let blobs = versions(2);
+        let base_hash = blobs[0].hash();
+        let delta = encode_delta(&base_hash, &blobs[0], &blobs[1]).unwrap();
+
+        assert_eq!(delta_base(&delta), Some(base_hash));
+        assert!(delta.len() < blobs[1].compress().unwrap().len() / 4);
+        assert_eq!(delta_base(&blobs[1].compress().unwrap()), None);
+
+        let restored = decode(&delta, &Dictionaries::default(), |hash| {
+            assert_eq!(*hash, base_hash);
+            Ok(blobs[0].clone())
+        })
+        .unwrap();
+        assert_eq!(restored.hash(), blobs[1].hash());
+    }
+
+    #[test]
+    fn test_pack_objects_bounds_chains() {
+        let blobs: HashMap<Hash, Blob> = versions(40).into_iter().map(|b| (b.hash(), b)).collect();
+
+        let mut stored: HashMap<Hash, Vec<u8>> = HashMap::new();
+        let mut order = Vec::new();
+        let packed = pack_objects(
+            blobs.keys().copied().collect(),
+            |hash| Ok(blobs[hash].clone()),
+            |hash, data| {
+                if let Some(base) = delta_base(data) {
+                    assert!(stored.contains_key(&base), "base emitted first");
+                }
+                stored.insert(hash, data.to_vec());
+                order.push(hash);
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(stored.len(), 40);
+        assert_eq!(packed.deltas, 39);
+
+        // Newest version is stored whole
+        assert_eq!(delta_base(&stored[&order[0]]), None);
+
+        let dicts = &packed.dictionaries;
+        let load = |hash: &Hash| {
+            fn load(
+                stored: &HashMap<Hash, Vec<u8>>,
+                dicts: &Dictionaries,
+                hash: &Hash,
+                depth: usize,
+            ) -> Blob {
+                assert!(depth <= MAX_DELTA_DEPTH);
+                decode(&stored[hash], dicts, |base| {
+                    Ok(load(stored, dicts, base, depth + 1))
+                })
+                .unwrap()
+            }
+            load(&stored, dicts, hash, 0)
+        };
+        for hash in blobs.keys() {
+            assert_eq!(load(hash).hash(), *hash);
+        }
+    }
+}
@@ -0,0 +1,64 @@
+//! Reachability analysis over the object graph
+//!
+//! Objects form a DAG rooted at the refs:
+//! - commits point at their tree and their parents
+//! - trie nodes point at child nodes and at thought/edge blobs
+//! - thoughts point at the root objects of their attachments
+//! - attachment chunk lists point at their chunks
+//! - edges and attachment data chunks are leaves
+
+use crate::model::{Commit, Hash, Thought};
+use crate::store::{attachment_chunks, BlobType, Storage};
+use crate::trie::TrieNode;
+use crate::Result;
+use std::collections::HashSet;
+
+/// Collect the hashes of every object reachable from the given roots
+///
+/// Roots are usually the commit hashes the refs point at. Zero hashes are
+/// ignored. A dangling pointer anywhere in the graph is reported as
+/// `Error::NotFound` rather than silently skipped, so callers that delete
+/// unreachable objects never act on a partial view.
+pub fn reachable_objects(
+    store: &dyn Storage,
+    roots: impl IntoIterator<Item = Hash>,
+) -> Result<HashSet<Hash>> {
+    let mut seen = HashSet::new();
+    let mut stack: Vec<Hash> = roots.into_iter().filter(|h| !h.is_zero()).collect();
+
+    while let Some(hash) = stack.pop() {
+        if !seen.insert(hash) {
+            continue;
+        }
+
+        let blob = store.get(&hash)?;
+        match blob.blob_type {
+            BlobType::Commit => {
+                let commit: Commit = bincode::deserialize(&blob.data)?;
+                if !commit.tree.is_zero() {
+                    stack.push(commit.tree);
+                }
+                stack.extend(commit.parents);
+            }
+            BlobType::Tree => {
+                let node: TrieNode = bincode::deserialize(&blob.data)?;
+                if let Some(value) = node.value() {
+                    stack.push(value);
+                }
+                if let TrieNode::Branch { children, .. } = node {
+                    stack.extend(children.into_values());
+                }
+            }
+            BlobType::Thought => {
+                let thought: Thought = bincode::deserialize(&blob.data)?;
+                stack.extend(thought.attachments.into_iter().map(|a| a.hash));
+            }
+            BlobType::Attachment => {
+                stack.extend(attachment_chunks(&blob)?.unwrap_or_default());
+            }
+            BlobType::Edge => {}
+        }
+    }
+
+    Ok(seen)
+}
@@ -0,0 +1,289 @@
+//! Refs, HEAD and the reflog
+//!
+//! Branch names are hierarchical and may be any length up to
+//! `MAX_REF_NAME_LEN` (`experiments/try-mongodb`). HEAD either names a
+//! branch or is detached at a commit. Every movement of a ref or of HEAD is
+//! appended to the reflog with the old and new commit, a timestamp and a
+//! reason; entries are never rewritten.
+
+use crate::model::Hash;
+use crate::{Error, Result};
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Name under which movements of HEAD itself are logged
+pub const HEAD_REF: &str = "HEAD";
+
+/// Longest allowed ref name in bytes
+pub const MAX_REF_NAME_LEN: usize = u16::MAX as usize;
+
+/// What HEAD points at
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Head {
+    /// A branch; commits advance it
+    Branch(String),
+    /// A commit, not on any branch; commits advance HEAD only
+    Detached(Hash),
+}
+
+impl Head {
+    /// The branch name, unless HEAD is detached
+    pub fn branch(&self) -> Option<&str> {
+        match self {
+            Head::Branch(name) => Some(name),
+            Head::Detached(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Head {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Head::Branch(name) => write!(f, "{}", name),
+            Head::Detached(hash) => write!(f, "detached at {}", hash.short()),
+        }
+    }
+}
+
+/// One movement of a ref
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RefLogEntry {
+    /// Branch name, or `HEAD_REF` for HEAD itself
+    pub name: String,
+    /// Commit before the move (zero if the ref was created)
+    pub old: Hash,
+    /// Commit after the move (zero if the ref was deleted)
+    pub new: Hash,
+    /// Unix millis
+    pub timestamp: u64,
+    /// Why the ref moved, e.g. `commit: Add cat`
+    pub reason: String,
+}
+
+/// Check that a branch name is usable
+///
+/// Names are `/`-separated components. Components must be non-empty, must
+/// not start with `.`, and must not contain whitespace, control characters
+/// or any of `~^:?*[\`. `HEAD` is reserved.
+pub fn validate_ref_name(name: &str) -> Result<()> {
+    let invalid = |why: &str| Err(Error::InvalidRefName(format!("'{}': {}", name, why)));
+
+    if name.is_empty() {
+        return invalid("empty name");
+    }
+    if name.len() > MAX_REF_NAME_LEN {
+        return invalid("name too long");
+    }
+    if name == HEAD_REF {
+        return invalid("reserved name");
+    }
+    for component in name.split('/') {
+        if component.is_empty() {
+            return invalid("empty path component");
+        }
+        if component.starts_with('.') {
+            return invalid("path component starts with '.'");
+        }
+    }
+    if let Some(c) = name
+        .chars()
+        .find(|c| c.is_whitespace() || c.is_control() || "~^:?*[\\".contains(*c))
+    {
+        return invalid(&format!("contains {:?}", c));
+    }
+    Ok(())
+}
+
+/// Current time in unix millis
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Refs, HEAD and reflog of a store
+///
+/// Shared by every storage backend so they log ref movements the same way.
+#[derive(Clone, Debug)]
+pub(crate) struct RefTable {
+    /// Branch name → commit hash
+    pub refs: HashMap<String, Hash>,
+    /// Current HEAD
+    pub head: Head,
+    /// Every ref movement, oldest first
+    pub log: Vec<RefLogEntry>,
+}
+
+impl RefTable {
+    /// A table with an empty `main` branch checked out
+    pub fn new() -> Self {
+        let mut refs = HashMap::new();
+        refs.insert("main".to_string(), Hash::ZERO);
+        RefTable {
+            refs,
+            head: Head::Branch("main".to_string()),
+            log: Vec::new(),
+        }
+    }
+
+    /// The commit HEAD resolves to, if any
+    pub fn head_commit(&self) -> Option<Hash> {
+        match &self.head {
+            Head::Branch(name) => self.refs.get(name).copied(),
+            Head::Detached(hash) => Some(*hash),
+        }
+        .filter(|h| !h.is_zero())
+    }
+
+    fn record(&mut self, name: &str, old: Hash, new: Hash, reason: &str) {
+        self.log.push(RefLogEntry {
+            name: name.to_string(),
+            old,
+            new,
+            timestamp: now_millis(),
+            reason: reason.to_string(),
+        });
+    }
+
+    pub fn set_head(&mut self, head: Head, reason: &str) -> Result<()> {
+        if let Head::Branch(name) = &head {
+            if !self.refs.contains_key(name) {
+                return Err(Error::RefNotFound(name.clone()));
+            }
+        }
+        let old = self.head_commit().unwrap_or(Hash::ZERO);
+        self.head = head;
+        let new = self.head_commit().unwrap_or(Hash::ZERO);
+        self.record(HEAD_REF, old, new, reason);
+        Ok(())
+    }
+
+    pub fn set_ref(&mut self, name: &str, commit_hash: Hash, reason: &str) -> Result<()> {
+        validate_ref_name(name)?;
+        let old = self
+            .refs
+            .insert(name.to_string(), commit_hash)
+            .unwrap_or(Hash::ZERO);
+        self.record(name, old, commit_hash, reason);
+        Ok(())
+    }
+
+    pub fn create_branch(&mut self, name: &str, commit_hash: Hash) -> Result<()> {
+        validate_ref_name(name)?;
+        if self.refs.contains_key(name) {
+            return Err(Error::BranchNotFound(format!(
+                "Branch '{}' already exists",
+                name
+            )));
+        }
+        self.refs.insert(name.to_string(), commit_hash);
+        self.record(name, Hash::ZERO, commit_hash, "branch: created");
+        Ok(())
+    }
+
+    pub fn delete_branch(&mut self, name: &str) -> Result<()> {
+        if self.head.branch() == Some(name) {
+            return Err(Error::BranchNotFound(
+                "Cannot delete current branch".to_string(),
+            ));
+        }
+        let old = self
+            .refs
+            .remove(name)
+            .ok_or_else(|| Error::BranchNotFound(name.to_string()))?;
+        self.record(name, old, Hash::ZERO, "branch: deleted");
+        Ok(())
+    }
+
+    /// Commits named by any ref or by a detached HEAD
+    pub fn roots(&self) -> Vec<Hash> {
+        let mut roots: Vec<Hash> = self.refs.values().copied().collect();
+        if let Head::Detached(hash) = self.head {
+            roots.push(hash);
+        }
+        roots
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ref_name_validation() {
+        for name in [
+            "main",
+            "experiments/try-mongodb",
+            "a/b/c",
+            "v1.2",
+            "fix_#42",
+        ] {
+            assert!(validate_ref_name(name).is_ok(), "{}", name);
+        }
+        let long = "x".repeat(200);
+        assert!(validate_ref_name(&long).is_ok());
+
+        for name in [
+            "", "HEAD", "/lead", "trail/", "a//b", ".hidden", "a/.b", "sp ace", "a:b", "a~1",
+            "wild*",
+        ] {
+            assert!(
+                matches!(validate_ref_name(name), Err(Error::InvalidRefName(_))),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_ref_table_logs_every_move() {
+        let mut table = RefTable::new();
+        let a = Hash::digest(b"a");
+        let b = Hash::digest(b"b");
+
+        table.set_ref("main", a, "commit: first").unwrap();
+        table.create_branch("topic/x", a).unwrap();
+        table
+            .set_head(Head::Branch("topic/x".into()), "checkout")
+            .unwrap();
+        table.set_ref("topic/x", b, "commit: second").unwrap();
+        table.set_head(Head::Detached(a), "checkout").unwrap();
+        table.delete_branch("topic/x").unwrap();
+
+        let moves: Vec<_> = table
+            .log
+            .iter()
+            .map(|e| (e.name.as_str(), e.old, e.new))
+            .collect();
+        assert_eq!(
+            moves,
+            vec![
+                ("main", Hash::ZERO, a),
+                ("topic/x", Hash::ZERO, a),
+                (HEAD_REF, a, a),
+                ("topic/x", a, b),
+                (HEAD_REF, b, a),
+                ("topic/x", b, Hash::ZERO),
+            ]
+        );
+        assert_eq!(table.head_commit(), Some(a));
+        assert_eq!(table.roots().len(), 2);
+    }
+
+    #[test]
+    fn test_set_ref_rejects_names_records_cannot_hold() {
+        let mut table = RefTable::new();
+        let long = "x".repeat(MAX_REF_NAME_LEN + 1);
+        assert!(matches!(
+            table.set_ref(&long, Hash::digest(b"a"), "backup"),
+            Err(Error::InvalidRefName(_))
+        ));
+        assert!(table
+            .set_ref("bad name", Hash::digest(b"a"), "test")
+            .is_err());
+        assert_eq!(table.refs.len(), 1);
+        assert!(table.log.is_empty());
+    }
+}
@@ -0,0 +1,166 @@
+//! The storage backend abstraction
+//!
+//! Everything above the store (tries, graph views, branch operations and
+//! `Database`) talks to a `&dyn Storage`, so the single-file `ObjectStore`
+//! can be swapped for `MemoryStore` or any other backend.
+
+use crate::model::{Commit, Edge, Hash, Thought};
+use crate::store::{Blob, BlobType, FileUsage, GcStats, Head, RefLogEntry, RepackStats};
+use crate::trie::NodeCache;
+use crate::{Error, Result};
+
+/// A content-addressed blob store with named refs and a HEAD
+///
+/// Implementations must be safe to share between threads. Typed helpers
+/// for thoughts, edges and commits are provided on top of `put`/`get`.
+pub trait Storage: Send + Sync {
+    /// Store a blob, returns its hash
+    ///
+    /// Storing a blob that is already present is a no-op.
+    fn put(&self, blob: &Blob) -> Result<Hash>;
+
+    /// Retrieve a blob by hash
+    fn get(&self, hash: &Hash) -> Result<Blob>;
+
+    /// Check if a hash exists
+    fn contains(&self, hash: &Hash) -> bool;
+
+    /// List the hash of every stored object
+    fn hashes(&self) -> Vec<Hash>;
+
+    /// Get the number of objects in the store
+    fn object_count(&self) -> usize;
+
+    /// Bytes an object takes up in the backend, if present
+    fn stored_size(&self, hash: &Hash) -> Option<u64>;
+
+    /// Drop objects, returning how many were present
+    ///
+    /// Does not check whether anything still references them; used by
+    /// `fsck --repair` to discard corrupt entries.
+    fn remove_objects(&self, hashes: &[Hash]) -> Result<usize>;
+
+    // === Ref Management ===
+
+    /// Get what HEAD points at
+    fn head(&self) -> Head;
+
+    /// Point HEAD at a branch or detach it at a commit, logging `reason`
+    ///
+    /// Fails with `Error::RefNotFound` for a branch that does not exist.
+    fn set_head(&self, head: Head, reason: &str) -> Result<()>;
+
+    /// Get the commit hash for a ref
+    fn get_ref(&self, ref_name: &str) -> Option<Hash>;
+
+    /// Set a ref to point to a commit, logging `reason`
+    ///
+    /// Fails with `Error::InvalidRefName` unless the name passes
+    /// `validate_ref_name`.
+    fn set_ref(&self, ref_name: &str, commit_hash: Hash, reason: &str) -> Result<()>;
+
+    /// List all refs
+    fn list_refs(&self) -> Vec<(String, Hash)>;
+
+    /// Create a new branch at the given commit
+    ///
+    /// Fails with `Error::InvalidRefName` unless the name passes
+    /// `validate_ref_name`.
+    fn create_branch(&self, name: &str, commit_hash: Hash) -> Result<()>;
+
+    /// Delete a branch
+    fn delete_branch(&self, name: &str) -> Result<()>;
+
+    /// Every ref movement so far, oldest first
+    fn reflog(&self) -> Vec<RefLogEntry>;
+
+    // === Persistence ===
+
+    /// Make everything written so far durable
+    fn sync(&self) -> Result<()>;
+
+    /// Remove objects no longer reachable from any ref or from `keep`
+    ///
+    /// `keep` names objects that nothing committed points at yet but that
+    /// must survive, such as attachments added to the working tree.
+    fn gc(&self, keep: &[Hash]) -> Result<GcStats>;
+
+    /// Store similar objects as deltas against each other where the
+    /// backend supports it
+    fn repack(&self) -> Result<RepackStats>;
+
+    // === Provided ===
+
+    /// Cache of decoded trie nodes shared by every trie on this store
+    ///
+    /// Backends without one decode every node they are asked for.
+    fn node_cache(&self) -> Option<&NodeCache> {
+        None
+    }
+
+    /// Size of the backing file and how much of it the current state needs
+    /// besides the objects
+    ///
+    /// Backends that do not keep a file return `None`.
+    fn file_usage(&self) -> Result<Option<FileUsage>> {
+        Ok(None)
+    }
+
+    /// Get the current HEAD commit hash
+    fn head_commit(&self) -> Option<Hash> {
+        match self.head() {
+            Head::Branch(name) => self.get_ref(&name),
+            Head::Detached(hash) => Some(hash),
+        }
+        .filter(|h| !h.is_zero())
+    }
+
+    /// Store a thought and return its hash
+    fn put_thought(&self, thought: &Thought) -> Result<Hash> {
+        let data = bincode::serialize(thought)?;
+        self.put(&Blob::new(BlobType::Thought, data))
+    }
+
+    /// Retrieve a thought by hash
+    fn get_thought(&self, hash: &Hash) -> Result<Thought> {
+        get_typed(self, hash, BlobType::Thought)
+    }
+
+    /// Store an edge and return its hash
+    fn put_edge(&self, edge: &Edge) -> Result<Hash> {
+        let data = bincode::serialize(edge)?;
+        self.put(&Blob::new(BlobType::Edge, data))
+    }
+
+    /// Retrieve an edge by hash
+    fn get_edge(&self, hash: &Hash) -> Result<Edge> {
+        get_typed(self, hash, BlobType::Edge)
+    }
+
+    /// Store a commit and return its hash
+    fn put_commit(&self, commit: &Commit) -> Result<Hash> {
+        let data = bincode::serialize(commit)?;
+        self.put(&Blob::new(BlobType::Commit, data))
+    }
+
+    /// Retrieve a commit by hash
+    fn get_commit(&self, hash: &Hash) -> Result<Commit> {
+        get_typed(self, hash, BlobType::Commit)
+    }
+}
+
+/// Fetch a blob and decode it, checking its type first
+fn get_typed<S, T>(store: &S, hash: &Hash, expected: BlobType) -> Result<T>
+where
+    S: Storage + ?Sized,
+    T: serde::de::DeserializeOwned,
+{
+    let blob = store.get(hash)?;
+    if blob.blob_type != expected {
+        return Err(Error::Corruption(format!(
+            "Expected {:?}, got {:?}",
+            expected, blob.blob_type
+        )));
+    }
+    Ok(bincode::deserialize(&blob.data)?)
+}
@@ -0,0 +1,207 @@
+//! Bottom-up construction of a trie from sorted keys
+//!
+//! Inserting keys one at a time rebuilds the path to every key. With the
+//! keys in order, the builder knows a subtree is finished as soon as a key
+//! diverges from it, so it stores each node once, when it is complete, and
+//! only keeps the branches along the path of the latest key in memory. The
+//! shape is the canonical one `insert` produces, so the root hash is the
+//! same.
+
+use super::tree::{common_prefix_len, store_node};
+use super::TrieNode;
+use crate::model::Hash;
+use crate::store::Storage;
+use crate::{Error, Result};
+use std::collections::BTreeMap;
+
+/// Builds a trie from strictly increasing keys
+pub(super) struct Builder<'s> {
+    store: &'s dyn Storage,
+    /// Branches on the path to `last`, outermost first
+    open: Vec<OpenBranch>,
+    /// The latest key, not yet placed
+    last: Option<(Vec<u8>, Hash)>,
+}
+
+/// A branch that may still gain children
+struct OpenBranch {
+    /// Length of the key up to the byte that picks a child
+    split: usize,
+    value: Option<Hash>,
+    children: BTreeMap<u8, Hash>,
+}
+
+impl OpenBranch {
+    fn new(split: usize, value: Option<Hash>) -> Self {
+        OpenBranch {
+            split,
+            value,
+            children: BTreeMap::new(),
+        }
+    }
+}
+
+impl<'s> Builder<'s> {
+    pub(super) fn new(store: &'s dyn Storage) -> Self {
+        Builder {
+            store,
+            open: Vec::new(),
+            last: None,
+        }
+    }
+
+    pub(super) fn push(&mut self, key: Vec<u8>, value: Hash) -> Result<()> {
+        let Some((last_key, last_value)) = self.last.take() else {
+            self.last = Some((key, value));
+            return Ok(());
+        };
+        if key <= last_key {
+            return Err(Error::UnsortedKeys(format!(
+                "{:?} after {:?}",
+                String::from_utf8_lossy(&key),
+                String::from_utf8_lossy(&last_key)
+            )));
+        }
+
+        // Nothing after `key` can share more than `common` bytes with the
+        // last key, so every subtree below that is finished
+        let common = common_prefix_len(&key, &last_key);
+        match self.open.last() {
+            Some(branch) if branch.split >= common => {
+                self.place_leaf(&last_key, last_value)?;
+                self.close_above(&last_key, common)?;
+            }
+            _ if common == last_key.len() => {
+                // The last key is a prefix of this one
+                self.open.push(OpenBranch::new(common, Some(last_value)));
+            }
+            _ => {
+                self.open.push(OpenBranch::new(common, None));
+                self.place_leaf(&last_key, last_value)?;
+            }
+        }
+        self.last = Some((key, value));
+        Ok(())
+    }
+
+    /// Store what is left and return the root hash
+    pub(super) fn finish(mut self) -> Result<Hash> {
+        let Some((last_key, last_value)) = self.last.take() else {
+            return Ok(Hash::ZERO);
+        };
+        if self.open.is_empty() {
+            return store_node(self.store, &TrieNode::leaf(last_key, last_value));
+        }
+        self.place_leaf(&last_key, last_value)?;
+        while self.open.len() > 1 {
+            let branch = self.open.pop().expect("checked above");
+            self.attach(&last_key, branch)?;
+        }
+        let root = self.open.pop().expect("one branch is left");
+        self.store_branch(&last_key, 0, root)
+    }
+
+    /// Store `key` as a leaf under the innermost open branch
+    fn place_leaf(&mut self, key: &[u8], value: Hash) -> Result<()> {
+        let branch = self.open.last_mut().expect("a leaf goes under a branch");
+        let leaf = TrieNode::leaf(key[branch.split + 1..].to_vec(), value);
+        let hash = store_node(self.store, &leaf)?;
+        branch.children.insert(key[branch.split], hash);
+        Ok(())
+    }
+
+    /// Store the open branches that split after more than `split` bytes of
+    /// `key`, leaving one that splits at `split` innermost
+    fn close_above(&mut self, key: &[u8], split: usize) -> Result<()> {
+        while let Some(branch) = self.open.pop_if(|branch| branch.split > split) {
+            if self.open.last().is_none_or(|parent| parent.split < split) {
+                // The keys diverge between two open branches
+                self.open.push(OpenBranch::new(split, None));
+            }
+            self.attach(key, branch)?;
+        }
+        Ok(())
+    }
+
+    /// Store a finished branch as a child of the innermost open one
+    fn attach(&mut self, key: &[u8], branch: OpenBranch) -> Result<()> {
+        let split = self
+            .open
+            .last()
+            .expect("a branch goes under a branch")
+            .split;
+        let hash = self.store_branch(key, split + 1, branch)?;
+        self.open
+            .last_mut()
+            .expect("checked above")
+            .children
+            .insert(key[split], hash);
+        Ok(())
+    }
+
+    /// Store a finished branch whose prefix begins at `start` bytes of `key`
+    fn store_branch(&self, key: &[u8], start: usize, branch: OpenBranch) -> Result<Hash> {
+        store_node(
+            self.store,
+            &TrieNode::Branch {
+                prefix: key[start..branch.split].to_vec(),
+                children: branch.children,
+                value: branch.value,
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::model::Hash;
+    use crate::store::{MemoryStore, Storage};
+    use crate::trie::MerkleTrie;
+    use crate::Error;
+
+    fn entries(keys: &[&[u8]]) -> Vec<crate::Result<(Vec<u8>, Hash)>> {
+        keys.iter()
+            .map(|key| Ok((key.to_vec(), Hash::digest(key))))
+            .collect()
+    }
+
+    #[test]
+    fn test_build_from_sorted_matches_inserts_and_stores_only_its_nodes() {
+        let shapes: [&[&[u8]]; 6] = [
+            &[],
+            &[b"only"],
+            &[b"", b"a", b"ab", b"abc"],
+            &[b"a", b"abc", b"abd", b"ac", b"b", b"bcd"],
+            &[b"e:a:b", b"t:", b"t:1", b"t:10", b"t:2", b"t:20", b"t:21"],
+            &[b"x1", b"x2", b"y", b"yy1", b"yy2", b"yz"],
+        ];
+        for keys in shapes {
+            let store = MemoryStore::new();
+            let built = MerkleTrie::build_from_sorted(&store, entries(keys)).unwrap();
+
+            let other = MemoryStore::new();
+            let mut inserted = MerkleTrie::new(&other);
+            for key in keys.iter().rev() {
+                inserted.insert(key, Hash::digest(key)).unwrap();
+            }
+            assert_eq!(built.root_hash(), inserted.root_hash(), "{:?}", keys);
+            assert_eq!(inserted.commit().unwrap(), built.root_hash());
+            // The build stored the nodes of the tree and nothing else
+            assert_eq!(store.object_count(), other.object_count(), "{:?}", keys);
+            for key in keys {
+                assert_eq!(built.get(key).unwrap(), Some(Hash::digest(key)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_build_from_sorted_rejects_unsorted_keys() {
+        let store = MemoryStore::new();
+        for keys in [&[b"b" as &[u8], b"a"], &[b"a", b"a"]] {
+            assert!(matches!(
+                MerkleTrie::build_from_sorted(&store, entries(keys)),
+                Err(Error::UnsortedKeys(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,180 @@
+//! Bounded LRU cache of decoded trie nodes
+//!
+//! Every lookup walks from the root, so the upper levels of a tree are
+//! decoded over and over. Stores keep one `NodeCache` shared by every
+//! `MerkleTrie` built on them. Nodes are content-addressed, so an entry can
+//! never go stale; it only has to be dropped when the object itself is
+//! removed from the store.
+
+use super::TrieNode;
+use crate::model::Hash;
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Decoded nodes a store caches unless configured otherwise
+pub const DEFAULT_NODE_CACHE_SIZE: usize = 16_384;
+
+/// Cache counters
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct CacheStats {
+    /// Lookups answered from the cache
+    pub hits: u64,
+    /// Lookups that had to decode the node
+    pub misses: u64,
+    /// Nodes dropped to stay within capacity
+    pub evictions: u64,
+    /// Nodes currently cached
+    pub len: usize,
+    /// Most nodes the cache holds
+    pub capacity: usize,
+}
+
+impl CacheStats {
+    /// Fraction of lookups served from the cache
+    pub fn hit_rate(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            0.0
+        } else {
+            self.hits as f64 / total as f64
+        }
+    }
+}
+
+/// A thread-safe LRU cache of decoded trie nodes, keyed by hash
+pub struct NodeCache {
+    capacity: usize,
+    inner: Mutex<Lru>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
+
+/// Entries with their last use, and uses in order
+#[derive(Default)]
+struct Lru {
+    entries: HashMap<Hash, (Arc<TrieNode>, u64)>,
+    order: BTreeMap<u64, Hash>,
+    tick: u64,
+}
+
+impl NodeCache {
+    /// Create a cache holding up to `capacity` nodes; 0 disables it
+    pub fn new(capacity: usize) -> Self {
+        NodeCache {
+            capacity,
+            inner: Mutex::new(Lru::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        }
+    }
+
+    /// Look up a node, counting a hit or a miss
+    pub fn get(&self, hash: &Hash) -> Option<Arc<TrieNode>> {
+        let mut lru = self.inner.lock();
+        let tick = lru.tick + 1;
+        let found = match lru.entries.get_mut(hash) {
+            Some((node, used)) => {
+                let previous = std::mem::replace(used, tick);
+                Some((node.clone(), previous))
+            }
+            None => None,
+        };
+        match found {
+            Some((node, previous)) => {
+                lru.tick = tick;
+                lru.order.remove(&previous);
+                lru.order.insert(tick, *hash);
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(node)
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    /// Add a node, evicting the least recently used ones beyond capacity
+    pub fn insert(&self, hash: Hash, node: Arc<TrieNode>) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut lru = self.inner.lock();
+        lru.tick += 1;
+        let tick = lru.tick;
+        if let Some((_, previous)) = lru.entries.insert(hash, (node, tick)) {
+            lru.order.remove(&previous);
+        }
+        lru.order.insert(tick, hash);
+
+        while lru.entries.len() > self.capacity {
+            let Some((_, oldest)) = lru.order.pop_first() else {
+                break;
+            };
+            lru.entries.remove(&oldest);
+            self.evictions.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Drop every entry, keeping the counters
+    pub fn clear(&self) {
+        let mut lru = self.inner.lock();
+        lru.entries.clear();
+        lru.order.clear();
+    }
+
+    /// Current counters
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            len: self.inner.lock().entries.len(),
+            capacity: self.capacity,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaf(i: u8) -> (Hash, Arc<TrieNode>) {
+        let node = TrieNode::leaf(vec![i], Hash::digest(&[i]));
+        (node.hash(), Arc::new(node))
+    }
+
+    #[test]
+    fn test_node_cache_evicts_least_recently_used() {
+        let cache = NodeCache::new(2);
+        let (a, node_a) = leaf(1);
+        let (b, node_b) = leaf(2);
+        let (c, node_c) = leaf(3);
+
+        cache.insert(a, node_a);
+        cache.insert(b, node_b);
+        assert!(cache.get(&a).is_some());
+        // b is now the oldest
+        cache.insert(c, node_c);
+        assert!(cache.get(&b).is_none());
+        assert!(cache.get(&a).is_some());
+        assert!(cache.get(&c).is_some());
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.evictions), (3, 1, 1));
+        assert_eq!((stats.len, stats.capacity), (2, 2));
+
+        cache.clear();
+        assert!(cache.get(&a).is_none());
+
+        let disabled = NodeCache::new(0);
+        let (d, node_d) = leaf(4);
+        disabled.insert(d, node_d);
+        assert!(disabled.get(&d).is_none());
+    }
+}
@@ -4,9 +4,19 @@
 //! - Each node's hash is derived from its children's hashes
 //! - Unchanged subtrees share storage across commits
 //! - The root hash uniquely identifies the entire graph state
+//!
+//! Decoded nodes are cached per store in a `NodeCache`.
 
+mod build;
+mod cache;
 mod node;
+mod proof;
+mod range;
 mod tree;
 
+pub use cache::{CacheStats, NodeCache, DEFAULT_NODE_CACHE_SIZE};
 pub use node::TrieNode;
+pub use proof::{verify_proof, Proof};
+pub use range::{cursor_token, page_start, parse_cursor_token, Page, Range};
+pub(crate) use tree::load_node;
 pub use tree::MerkleTrie;
@@ -1,6 +1,7 @@
 //! Trie node types
 
 use crate::model::Hash;
+use crate::store::{Blob, BlobType};
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
 
@@ -9,7 +10,7 @@ use std::collections::BTreeMap;
 /// We use a radix trie structure where:
 /// - Keys are thought/edge IDs converted to bytes
 /// - Values are content hashes (for leaves) or child node hashes (for branches)
-#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
 pub enum TrieNode {
     /// A branch node with children indexed by key prefix
     Branch {
@@ -52,10 +53,10 @@ impl TrieNode {
         }
     }
 
-    /// Compute the hash of this node
+    /// Compute the hash this node is stored under
     pub fn hash(&self) -> Hash {
         let data = bincode::serialize(self).expect("serialization should not fail");
-        Hash::digest(&data)
+        Blob::new(BlobType::Tree, data).hash()
     }
 
     /// Check if this node is empty
@@ -0,0 +1,151 @@
+//! Merkle inclusion proofs
+//!
+//! A proof is the chain of nodes from a tree's root down to the node holding
+//! a key. Each node is identified by the hash of its encoding and names its
+//! children by hash, so anyone holding the root hash can check the chain
+//! without access to the rest of the store.
+
+use super::TrieNode;
+use crate::model::Hash;
+use crate::Result;
+use serde::{Deserialize, Serialize};
+
+/// Nodes from the root of a tree to the node holding one key
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Proof {
+    pub nodes: Vec<TrieNode>,
+}
+
+impl Proof {
+    /// The tree hash the proof starts from
+    pub fn root(&self) -> Option<Hash> {
+        self.nodes.first().map(TrieNode::hash)
+    }
+
+    /// The value the proof ends at, if it ends at one
+    pub fn value(&self) -> Option<Hash> {
+        self.nodes.last().and_then(TrieNode::value)
+    }
+
+    /// Each node as stored: hashing a node's bytes, prefixed with the tree
+    /// blob type byte, gives the hash its parent refers to it by
+    pub fn encode(&self) -> Result<Vec<Vec<u8>>> {
+        self.nodes
+            .iter()
+            .map(|node| Ok(bincode::serialize(node)?))
+            .collect()
+    }
+
+    /// Rebuild a proof from `encode`d nodes
+    pub fn decode(nodes: &[Vec<u8>]) -> Result<Self> {
+        let nodes = nodes
+            .iter()
+            .map(|data| Ok(bincode::deserialize(data)?))
+            .collect::<Result<_>>()?;
+        Ok(Proof { nodes })
+    }
+}
+
+/// Check that `proof` shows `key` mapping to `value` in the tree at `root`
+pub fn verify_proof(root: Hash, key: &[u8], value: Hash, proof: &Proof) -> bool {
+    let mut expected = root;
+    let mut remaining = key;
+    for (i, node) in proof.nodes.iter().enumerate() {
+        if node.hash() != expected {
+            return false;
+        }
+        let last = i + 1 == proof.nodes.len();
+        match node {
+            TrieNode::Leaf {
+                key_suffix,
+                value: leaf_value,
+            } => return last && key_suffix.as_slice() == remaining && *leaf_value == value,
+            TrieNode::Branch {
+                prefix,
+                children,
+                value: branch_value,
+            } => {
+                let Some(after_prefix) = remaining.strip_prefix(prefix.as_slice()) else {
+                    return false;
+                };
+                let Some((next_byte, rest)) = after_prefix.split_first() else {
+                    return last && *branch_value == Some(value);
+                };
+                let Some(child) = children.get(next_byte) else {
+                    return false;
+                };
+                expected = *child;
+                remaining = rest;
+            }
+            TrieNode::Empty => return false,
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+    use crate::trie::MerkleTrie;
+
+    #[test]
+    fn test_proofs_verify_against_the_root() {
+        let store = MemoryStore::new();
+        let mut trie = MerkleTrie::new(&store);
+        let keys: [&[u8]; 5] = [b"t:a", b"t:ab", b"t:abc", b"t:b", b"e:a:b:relates_to"];
+        for key in keys {
+            trie.insert(key, Hash::digest(key)).unwrap();
+        }
+        let root = trie.commit().unwrap();
+        let trie = MerkleTrie::from_root(&store, root).unwrap();
+
+        for key in keys {
+            let proof = trie.prove(key).unwrap().unwrap();
+            let value = Hash::digest(key);
+            assert_eq!(proof.value(), Some(value));
+            assert_eq!(proof.root(), Some(root));
+            assert!(verify_proof(root, key, value, &proof));
+
+            // Wrong value, key or root
+            assert!(!verify_proof(root, key, Hash::digest(b"other"), &proof));
+            assert!(!verify_proof(root, b"t:zz", value, &proof));
+            assert!(!verify_proof(Hash::digest(b"root"), key, value, &proof));
+
+            let decoded = Proof::decode(&proof.encode().unwrap()).unwrap();
+            assert_eq!(decoded, proof);
+        }
+        assert!(trie.prove(b"t:missing").unwrap().is_none());
+        assert!(trie.prove(b"t:").unwrap().is_none());
+
+        // A tampered node breaks the chain
+        let mut proof = trie.prove(b"t:abc").unwrap().unwrap();
+        if let Some(TrieNode::Leaf { value, .. }) = proof.nodes.last_mut() {
+            *value = Hash::digest(b"forged");
+        }
+        assert!(!verify_proof(
+            root,
+            b"t:abc",
+            Hash::digest(b"forged"),
+            &proof
+        ));
+        proof.nodes.pop();
+        assert!(!verify_proof(
+            root,
+            b"t:abc",
+            Hash::digest(b"t:abc"),
+            &proof
+        ));
+
+        // Uncommitted changes prove against the root they will commit to
+        let mut trie = MerkleTrie::from_root(&store, root).unwrap();
+        trie.insert(b"t:abd", Hash::digest(b"new")).unwrap();
+        let proof = trie.prove(b"t:abd").unwrap().unwrap();
+        assert!(verify_proof(
+            trie.root_hash(),
+            b"t:abd",
+            Hash::digest(b"new"),
+            &proof
+        ));
+    }
+}
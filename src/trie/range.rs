@@ -0,0 +1,304 @@
+//! Ordered, lazy scans over trie keys
+//!
+//! `Range` walks the trie depth-first, children in byte order, so keys come
+//! out sorted. Nodes are loaded only when the walk reaches them, subtrees
+//! that end before the start bound are skipped without loading, and the walk
+//! stops at the end bound. Memory use depends on the depth and fan-out of the
+//! trie, not on how many keys it holds.
+//!
+//! Listings page through a scan with cursor tokens: a token names the last
+//! key of a page and the next page starts right after it.
+
+use super::tree::{Child, Node};
+use super::{load_node, TrieNode};
+use crate::model::Hash;
+use crate::store::Storage;
+use crate::{Error, Result};
+use std::ops::Bound;
+
+/// Iterator over `(key, value)` pairs of a trie in key order
+pub struct Range<'t> {
+    store: &'t dyn Storage,
+    /// Work left, next item on top
+    stack: Vec<Pending<'t>>,
+    start: Bound<Vec<u8>>,
+    end: Bound<Vec<u8>>,
+}
+
+/// A value, or a subtree whose keys all begin with `key`
+struct Pending<'t> {
+    key: Vec<u8>,
+    item: Item<'t>,
+}
+
+enum Item<'t> {
+    Value(Hash),
+    Dirty(&'t Node),
+    Stored(Hash),
+}
+
+impl<'t> Range<'t> {
+    pub(super) fn new(
+        store: &'t dyn Storage,
+        root: &'t Node,
+        start: Bound<Vec<u8>>,
+        end: Bound<Vec<u8>>,
+    ) -> Self {
+        Range {
+            store,
+            stack: vec![Pending {
+                key: Vec::new(),
+                item: Item::Dirty(root),
+            }],
+            start,
+            end,
+        }
+    }
+
+    /// Whether every key beginning with `prefix` sorts before the start
+    fn before_start(&self, prefix: &[u8]) -> bool {
+        match &self.start {
+            Bound::Included(start) | Bound::Excluded(start) => {
+                prefix < start.as_slice() && !start.starts_with(prefix)
+            }
+            Bound::Unbounded => false,
+        }
+    }
+
+    /// Whether `key` is below the start bound
+    fn skipped(&self, key: &[u8]) -> bool {
+        match &self.start {
+            Bound::Included(start) => key < start.as_slice(),
+            Bound::Excluded(start) => key <= start.as_slice(),
+            Bound::Unbounded => false,
+        }
+    }
+
+    /// Whether `key`, and so every key beginning with it, is past the end
+    fn past_end(&self, key: &[u8]) -> bool {
+        match &self.end {
+            Bound::Included(end) => key > end.as_slice(),
+            Bound::Excluded(end) => key >= end.as_slice(),
+            Bound::Unbounded => false,
+        }
+    }
+
+    fn push_leaf(&mut self, mut key: Vec<u8>, suffix: &[u8], value: Hash) {
+        key.extend(suffix);
+        self.stack.push(Pending {
+            key,
+            item: Item::Value(value),
+        });
+    }
+
+    fn push_branch(
+        &mut self,
+        mut key: Vec<u8>,
+        prefix: &[u8],
+        value: Option<Hash>,
+        children: impl DoubleEndedIterator<Item = (u8, Item<'t>)>,
+    ) {
+        key.extend(prefix);
+        for (byte, item) in children.rev() {
+            let mut child_key = key.clone();
+            child_key.push(byte);
+            if !self.before_start(&child_key) {
+                self.stack.push(Pending {
+                    key: child_key,
+                    item,
+                });
+            }
+        }
+        if let Some(value) = value {
+            self.stack.push(Pending {
+                key,
+                item: Item::Value(value),
+            });
+        }
+    }
+}
+
+impl Iterator for Range<'_> {
+    type Item = Result<(Vec<u8>, Hash)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(Pending { key, item }) = self.stack.pop() {
+            if self.past_end(&key) {
+                self.stack.clear();
+                return None;
+            }
+            match item {
+                Item::Value(value) => {
+                    if !self.skipped(&key) {
+                        return Some(Ok((key, value)));
+                    }
+                }
+                Item::Dirty(Node::Leaf { key_suffix, value }) => {
+                    self.push_leaf(key, key_suffix, *value)
+                }
+                Item::Dirty(Node::Branch {
+                    prefix,
+                    children,
+                    value,
+                }) => {
+                    let children = children.iter().map(|(byte, child)| {
+                        let item = match child {
+                            Child::Dirty(node) => Item::Dirty(node),
+                            Child::Stored(hash) => Item::Stored(*hash),
+                        };
+                        (*byte, item)
+                    });
+                    self.push_branch(key, prefix, *value, children);
+                }
+                Item::Dirty(Node::Empty) => {}
+                Item::Stored(hash) => match load_node(self.store, &hash) {
+                    Ok(node) => match &*node {
+                        TrieNode::Leaf { key_suffix, value } => {
+                            self.push_leaf(key, key_suffix, *value)
+                        }
+                        TrieNode::Branch {
+                            prefix,
+                            children,
+                            value,
+                        } => {
+                            let children = children
+                                .iter()
+                                .map(|(byte, hash)| (*byte, Item::Stored(*hash)));
+                            self.push_branch(key, prefix, *value, children);
+                        }
+                        TrieNode::Empty => {}
+                    },
+                    Err(e) => {
+                        self.stack.clear();
+                        return Some(Err(e));
+                    }
+                },
+            }
+        }
+        None
+    }
+}
+
+/// The end bound of a scan over keys beginning with `prefix`
+pub(super) fn prefix_end(prefix: &[u8]) -> Bound<Vec<u8>> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < u8::MAX {
+            end.push(last + 1);
+            return Bound::Excluded(end);
+        }
+    }
+    Bound::Unbounded
+}
+
+/// One page of an ordered listing
+#[derive(Clone, Debug)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Token for the page after this one, `None` on the last page
+    pub next: Option<String>,
+}
+
+/// Token resuming a listing after `key`
+pub fn cursor_token(key: &[u8]) -> String {
+    hex::encode(key)
+}
+
+/// The key a `cursor_token` resumes after
+pub fn parse_cursor_token(token: &str) -> Result<Vec<u8>> {
+    hex::decode(token).map_err(|_| Error::InvalidCursor(token.to_string()))
+}
+
+/// Where the page after `cursor` starts
+///
+/// An empty page could not tell whether more items follow, so `limit` must
+/// be at least one.
+pub fn page_start(cursor: Option<&str>, limit: usize) -> Result<Bound<Vec<u8>>> {
+    if limit == 0 {
+        return Err(Error::InvalidLimit("a page holds at least one item".into()));
+    }
+    match cursor {
+        Some(token) => Ok(Bound::Excluded(parse_cursor_token(token)?)),
+        None => Ok(Bound::Unbounded),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+    use crate::trie::MerkleTrie;
+    use std::collections::BTreeMap;
+
+    type Bounds<'a> = (Bound<&'a [u8]>, Bound<&'a [u8]>);
+
+    #[test]
+    fn test_range_matches_sorted_keys() {
+        let store = MemoryStore::new();
+        let mut trie = MerkleTrie::new(&store);
+        let mut expected = BTreeMap::new();
+        for i in 0..300u32 {
+            let key = format!("t:{}", i * 7 % 1000).into_bytes();
+            let value = Hash::digest(&key);
+            trie.insert(&key, value).unwrap();
+            expected.insert(key, value);
+        }
+        for key in [b"t:" as &[u8], b"t:1", b"e:1"] {
+            trie.insert(key, Hash::digest(key)).unwrap();
+            expected.insert(key.to_vec(), Hash::digest(key));
+        }
+
+        // Half committed, half still in memory
+        let root = trie.commit().unwrap();
+        let mut trie = MerkleTrie::from_root(&store, root).unwrap();
+        for i in 300..400u32 {
+            let key = format!("t:{}", i * 7 % 1000).into_bytes();
+            trie.insert(&key, Hash::digest(&key)).unwrap();
+            expected.insert(key.clone(), Hash::digest(&key));
+        }
+
+        let bounds: [Bounds; 6] = [
+            (Bound::Unbounded, Bound::Unbounded),
+            (Bound::Included(b"t:1"), Bound::Excluded(b"t:5")),
+            (Bound::Excluded(b"t:1"), Bound::Included(b"t:5")),
+            (Bound::Included(b"t:105"), Bound::Unbounded),
+            (Bound::Unbounded, Bound::Excluded(b"t:")),
+            (Bound::Excluded(b"t:999"), Bound::Unbounded),
+        ];
+        for (start, end) in bounds {
+            let scanned: Vec<_> = trie.range(start, end).map(|r| r.unwrap()).collect();
+            let wanted: Vec<_> = expected
+                .range::<[u8], _>((start, end))
+                .map(|(k, v)| (k.clone(), *v))
+                .collect();
+            assert_eq!(scanned, wanted, "{:?}..{:?}", start, end);
+        }
+
+        let prefixed: Vec<_> = trie.scan_prefix(b"t:1").map(|r| r.unwrap().0).collect();
+        assert!(prefixed.iter().all(|key| key.starts_with(b"t:1")));
+        assert_eq!(
+            prefixed.len(),
+            expected.keys().filter(|k| k.starts_with(b"t:1")).count()
+        );
+    }
+
+    #[test]
+    fn test_prefix_end_and_cursor_tokens() {
+        assert_eq!(prefix_end(b"t:"), Bound::Excluded(b"t;".to_vec()));
+        assert_eq!(prefix_end(&[1, 0xff]), Bound::Excluded(vec![2]));
+        assert_eq!(prefix_end(&[0xff]), Bound::Unbounded);
+
+        let token = cursor_token(b"t:abc");
+        assert_eq!(parse_cursor_token(&token).unwrap(), b"t:abc");
+        assert!(matches!(
+            parse_cursor_token("not hex"),
+            Err(Error::InvalidCursor(_))
+        ));
+
+        assert_eq!(
+            page_start(Some(&token), 1).unwrap(),
+            Bound::Excluded(b"t:abc".to_vec())
+        );
+        assert!(matches!(page_start(None, 0), Err(Error::InvalidLimit(_))));
+    }
+}
@@ -1,10 +1,19 @@
 //! Merkle trie implementation for the graph state
-
-use super::TrieNode;
+//!
+//! Nodes changed by `insert` and `remove` stay in memory as dirty nodes,
+//! linked to their parent directly rather than by hash. `commit` hashes
+//! and stores only the dirty nodes of the final tree, so a batch of changes
+//! writes each changed node once instead of every intermediate version.
+
+use super::build::Builder;
+use super::range::prefix_end;
+use super::{Proof, Range, TrieNode};
 use crate::model::Hash;
-use crate::store::{Blob, BlobType, ObjectStore};
+use crate::store::{Blob, BlobType, Storage};
 use crate::Result;
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::sync::Arc;
 
 /// A merkle trie that stores thoughts and edges
 ///
@@ -12,245 +21,387 @@ use std::collections::HashMap;
 /// - "t:" prefix for thoughts (keyed by ThoughtId)
 /// - "e:" prefix for edges (keyed by canonical edge key)
 pub struct MerkleTrie<'a> {
-    store: &'a ObjectStore,
-    /// Root node (cached in memory)
-    root: TrieNode,
-    /// Cache of loaded nodes
-    cache: HashMap<Hash, TrieNode>,
+    store: &'a dyn Storage,
+    /// Root node, with any uncommitted changes below it
+    root: Node,
+}
+
+/// A node as the trie holds it in memory
+#[derive(Debug, Default)]
+pub(super) enum Node {
+    Branch {
+        prefix: Vec<u8>,
+        children: BTreeMap<u8, Child>,
+        value: Option<Hash>,
+    },
+    Leaf {
+        key_suffix: Vec<u8>,
+        value: Hash,
+    },
+    #[default]
+    Empty,
+}
+
+/// A child of an in-memory branch
+#[derive(Debug)]
+pub(super) enum Child {
+    /// In the store, unchanged since it was loaded or committed
+    Stored(Hash),
+    /// Changed since the last commit
+    Dirty(Box<Node>),
+}
+
+impl Node {
+    fn leaf(key_suffix: &[u8], value: Hash) -> Self {
+        Node::Leaf {
+            key_suffix: key_suffix.to_vec(),
+            value,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        matches!(self, Node::Empty)
+    }
+
+    /// The stored form of this node, given the hash of each child
+    fn encode(&self, child_hash: impl Fn(&Child) -> Hash) -> TrieNode {
+        match self {
+            Node::Branch {
+                prefix,
+                children,
+                value,
+            } => TrieNode::Branch {
+                prefix: prefix.clone(),
+                children: children
+                    .iter()
+                    .map(|(byte, child)| (*byte, child_hash(child)))
+                    .collect(),
+                value: *value,
+            },
+            Node::Leaf { key_suffix, value } => TrieNode::leaf(key_suffix.clone(), *value),
+            Node::Empty => TrieNode::Empty,
+        }
+    }
+}
+
+impl From<&TrieNode> for Node {
+    fn from(node: &TrieNode) -> Self {
+        match node {
+            TrieNode::Branch {
+                prefix,
+                children,
+                value,
+            } => Node::Branch {
+                prefix: prefix.clone(),
+                children: children
+                    .iter()
+                    .map(|(byte, hash)| (*byte, Child::Stored(*hash)))
+                    .collect(),
+                value: *value,
+            },
+            TrieNode::Leaf { key_suffix, value } => Node::leaf(key_suffix, *value),
+            TrieNode::Empty => Node::Empty,
+        }
+    }
+}
+
+fn dirty(node: Node) -> Child {
+    Child::Dirty(Box::new(node))
 }
 
 impl<'a> MerkleTrie<'a> {
     /// Create a new empty trie
-    pub fn new(store: &'a ObjectStore) -> Self {
+    pub fn new(store: &'a dyn Storage) -> Self {
         MerkleTrie {
             store,
-            root: TrieNode::empty(),
-            cache: HashMap::new(),
+            root: Node::Empty,
         }
     }
 
     /// Load a trie from a root hash
-    pub fn from_root(store: &'a ObjectStore, root_hash: Hash) -> Result<Self> {
-        let mut trie = MerkleTrie {
-            store,
-            root: TrieNode::empty(),
-            cache: HashMap::new(),
-        };
-
+    pub fn from_root(store: &'a dyn Storage, root_hash: Hash) -> Result<Self> {
+        let mut trie = MerkleTrie::new(store);
         if !root_hash.is_zero() {
-            trie.root = trie.load_node(&root_hash)?;
+            trie.root = Node::from(&*trie.load_node(&root_hash)?);
         }
-
         Ok(trie)
     }
 
-    /// Get the root hash
+    /// Build a trie from entries in strictly increasing key order
+    ///
+    /// Each node is stored once, as soon as it is complete, and the root
+    /// hash is the one inserting the same entries would give. Keys out of
+    /// order fail with `Error::UnsortedKeys`.
+    pub fn build_from_sorted(
+        store: &'a dyn Storage,
+        entries: impl IntoIterator<Item = Result<(Vec<u8>, Hash)>>,
+    ) -> Result<Self> {
+        let mut builder = Builder::new(store);
+        for entry in entries {
+            let (key, value) = entry?;
+            builder.push(key, value)?;
+        }
+        let root = builder.finish()?;
+        MerkleTrie::from_root(store, root)
+    }
+
+    /// Get the root hash, which `commit` will return
+    ///
+    /// Dirty nodes are hashed but not stored.
     pub fn root_hash(&self) -> Hash {
         if self.root.is_empty() {
             Hash::ZERO
         } else {
-            self.root.hash()
+            hash_node(&self.root)
         }
     }
 
     /// Insert a key-value pair
     pub fn insert(&mut self, key: &[u8], value: Hash) -> Result<()> {
-        self.root = self.insert_recursive(&self.root.clone(), key, 0, value)?;
-        Ok(())
+        let mut root = std::mem::take(&mut self.root);
+        let result = self.insert_node(&mut root, key, value);
+        self.root = root;
+        result
     }
 
     /// Get a value by key
     pub fn get(&self, key: &[u8]) -> Result<Option<Hash>> {
-        self.get_recursive(&self.root, key, 0)
+        self.get_node(&self.root, key)
     }
 
     /// Remove a key
     pub fn remove(&mut self, key: &[u8]) -> Result<Option<Hash>> {
-        let (new_root, removed) = self.remove_recursive(&self.root.clone(), key, 0)?;
-        self.root = new_root;
-        Ok(removed)
+        let mut root = std::mem::take(&mut self.root);
+        let result = self.remove_node(&mut root, key);
+        self.root = root;
+        result
     }
 
-    /// Persist all nodes to the store and return the root hash
-    pub fn commit(&self) -> Result<Hash> {
+    /// Store the dirty nodes and return the root hash
+    pub fn commit(&mut self) -> Result<Hash> {
         if self.root.is_empty() {
             return Ok(Hash::ZERO);
         }
-        self.persist_node(&self.root)
+        let mut root = std::mem::take(&mut self.root);
+        let result = self.persist(&mut root);
+        self.root = root;
+        result
     }
 
-    /// List all keys with a given prefix
+    /// List all keys with a given prefix, in key order
     pub fn list_prefix(&self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Hash)>> {
-        let mut results = Vec::new();
-        self.collect_prefix(&self.root, prefix, 0, Vec::new(), &mut results)?;
-        Ok(results)
+        self.scan_prefix(prefix).collect()
     }
 
-    // === Internal helpers ===
+    /// Iterate over the keys between two bounds in order, loading nodes as
+    /// the scan reaches them
+    pub fn range(&self, start: Bound<&[u8]>, end: Bound<&[u8]>) -> Range<'_> {
+        Range::new(
+            self.store,
+            &self.root,
+            start.map(<[u8]>::to_vec),
+            end.map(<[u8]>::to_vec),
+        )
+    }
 
-    fn load_node(&mut self, hash: &Hash) -> Result<TrieNode> {
-        if let Some(node) = self.cache.get(hash) {
-            return Ok(node.clone());
-        }
+    /// Iterate over the keys beginning with `prefix` in order
+    pub fn scan_prefix(&self, prefix: &[u8]) -> Range<'_> {
+        Range::new(
+            self.store,
+            &self.root,
+            Bound::Included(prefix.to_vec()),
+            prefix_end(prefix),
+        )
+    }
 
-        let blob = self.store.get(hash)?;
-        if blob.blob_type != BlobType::Tree {
-            return Err(crate::Error::Corruption(format!(
-                "Expected Tree, got {:?}",
-                blob.blob_type
-            )));
+    /// Prove that `key` is in the trie, or `None` if it is not
+    ///
+    /// The proof checks against `root_hash`; uncommitted changes are
+    /// included as they would be committed.
+    pub fn prove(&self, key: &[u8]) -> Result<Option<Proof>> {
+        let mut nodes = Vec::new();
+        let mut remaining = key;
+        let mut node = encode_dirty(&self.root);
+        // Set while the path runs through uncommitted nodes
+        let mut in_memory = Some(&self.root);
+        loop {
+            let step = match &node {
+                TrieNode::Branch {
+                    prefix, children, ..
+                } => remaining
+                    .strip_prefix(prefix.as_slice())
+                    .and_then(|after| after.split_first())
+                    .and_then(|(byte, rest)| Some((*byte, rest, *children.get(byte)?))),
+                _ => None,
+            };
+            let Some((byte, rest, child_hash)) = step else {
+                let holds_key = match &node {
+                    TrieNode::Leaf { key_suffix, .. } => key_suffix.as_slice() == remaining,
+                    TrieNode::Branch { prefix, value, .. } => {
+                        prefix.as_slice() == remaining && value.is_some()
+                    }
+                    TrieNode::Empty => false,
+                };
+                nodes.push(node);
+                return Ok(holds_key.then_some(Proof { nodes }));
+            };
+
+            in_memory = match in_memory {
+                Some(Node::Branch { children, .. }) => match children.get(&byte) {
+                    Some(Child::Dirty(child)) => Some(child),
+                    _ => None,
+                },
+                _ => None,
+            };
+            let next = match in_memory {
+                Some(child) => encode_dirty(child),
+                None => TrieNode::clone(&*self.load_node(&child_hash)?),
+            };
+            nodes.push(std::mem::replace(&mut node, next));
+            remaining = rest;
         }
+    }
+
+    // === Internal helpers ===
 
-        let node: TrieNode = bincode::deserialize(&blob.data)?;
-        self.cache.insert(*hash, node.clone());
-        Ok(node)
+    fn load_node(&self, hash: &Hash) -> Result<Arc<TrieNode>> {
+        load_node(self.store, hash)
     }
 
-    fn persist_node(&self, node: &TrieNode) -> Result<Hash> {
-        let data = bincode::serialize(node)?;
-        let blob = Blob::new(BlobType::Tree, data);
-        self.store.put(&blob)
+    /// Store the dirty nodes under `node`, then `node` itself
+    fn persist(&self, node: &mut Node) -> Result<Hash> {
+        if let Node::Branch { children, .. } = node {
+            for child in children.values_mut() {
+                if let Child::Dirty(dirty) = child {
+                    *child = Child::Stored(self.persist(dirty)?);
+                }
+            }
+        }
+        store_node(
+            self.store,
+            &node.encode(|child| match child {
+                Child::Stored(hash) => *hash,
+                Child::Dirty(_) => unreachable!("children are stored first"),
+            }),
+        )
     }
 
-    fn insert_recursive(
-        &mut self,
-        node: &TrieNode,
-        key: &[u8],
-        depth: usize,
-        value: Hash,
-    ) -> Result<TrieNode> {
-        let remaining = &key[depth..];
+    /// Load a stored child so it can be changed in place
+    fn make_dirty<'n>(&self, child: &'n mut Child) -> Result<&'n mut Node> {
+        if let Child::Stored(hash) = *child {
+            *child = dirty(Node::from(&*self.load_node(&hash)?));
+        }
+        match child {
+            Child::Dirty(node) => Ok(node),
+            Child::Stored(_) => unreachable!("child was just loaded"),
+        }
+    }
 
+    fn insert_node(&self, node: &mut Node, key: &[u8], value: Hash) -> Result<()> {
         match node {
-            TrieNode::Empty => {
-                // Create a new leaf
-                Ok(TrieNode::leaf(remaining.to_vec(), value))
-            }
-            TrieNode::Leaf {
+            Node::Empty => *node = Node::leaf(key, value),
+            Node::Leaf {
                 key_suffix,
                 value: existing_value,
             } => {
-                if key_suffix == remaining {
+                if key_suffix.as_slice() == key {
                     // Same key, update value
-                    Ok(TrieNode::leaf(remaining.to_vec(), value))
-                } else {
-                    // Need to split into a branch
-                    let common_len = common_prefix_len(remaining, key_suffix);
-                    let common = remaining[..common_len].to_vec();
-
-                    let mut children = std::collections::BTreeMap::new();
-
-                    // Add existing leaf
-                    if common_len < key_suffix.len() {
-                        let existing_suffix = key_suffix[common_len..].to_vec();
-                        let existing_first = existing_suffix[0];
-                        let existing_node =
-                            TrieNode::leaf(existing_suffix[1..].to_vec(), *existing_value);
-                        let existing_hash = self.persist_node(&existing_node)?;
-                        children.insert(existing_first, existing_hash);
-                    }
-
-                    // Add new leaf
-                    if common_len < remaining.len() {
-                        let new_suffix = remaining[common_len..].to_vec();
-                        let new_first = new_suffix[0];
-                        let new_node = TrieNode::leaf(new_suffix[1..].to_vec(), value);
-                        let new_hash = self.persist_node(&new_node)?;
-                        children.insert(new_first, new_hash);
-                    }
+                    *existing_value = value;
+                    return Ok(());
+                }
 
-                    // Handle case where one key is prefix of another
-                    let branch_value = if common_len == remaining.len() {
-                        Some(value)
-                    } else if common_len == key_suffix.len() {
-                        Some(*existing_value)
+                // Split into a branch holding both keys
+                let common_len = common_prefix_len(key, key_suffix);
+                let mut children = BTreeMap::new();
+                let mut branch_value = None;
+                for (suffix, v) in [(key_suffix.as_slice(), *existing_value), (key, value)] {
+                    if common_len < suffix.len() {
+                        let child = Node::leaf(&suffix[common_len + 1..], v);
+                        children.insert(suffix[common_len], dirty(child));
                     } else {
-                        None
-                    };
-
-                    Ok(TrieNode::Branch {
-                        prefix: common,
-                        children,
-                        value: branch_value,
-                    })
+                        // One key is a prefix of the other
+                        branch_value = Some(v);
+                    }
                 }
+                *node = Node::Branch {
+                    prefix: key[..common_len].to_vec(),
+                    children,
+                    value: branch_value,
+                };
             }
-            TrieNode::Branch {
+            Node::Branch {
                 prefix,
                 children,
                 value: branch_value,
             } => {
-                let common_len = common_prefix_len(remaining, prefix);
+                let common_len = common_prefix_len(key, prefix);
 
                 if common_len < prefix.len() {
-                    // Need to split the branch
-                    let common = remaining[..common_len].to_vec();
-                    let old_suffix = prefix[common_len..].to_vec();
-                    let old_first = old_suffix[0];
-
-                    // Create new branch for the old subtree
-                    let old_branch = TrieNode::Branch {
-                        prefix: old_suffix[1..].to_vec(),
-                        children: children.clone(),
-                        value: *branch_value,
+                    // Split the branch, moving the old subtree down a level
+                    let old_branch = Node::Branch {
+                        prefix: prefix[common_len + 1..].to_vec(),
+                        children: std::mem::take(children),
+                        value: branch_value.take(),
                     };
-                    let old_hash = self.persist_node(&old_branch)?;
+                    let mut new_children = BTreeMap::new();
+                    new_children.insert(prefix[common_len], dirty(old_branch));
 
-                    let mut new_children = std::collections::BTreeMap::new();
-                    new_children.insert(old_first, old_hash);
-
-                    // Add the new key
-                    let new_value = if common_len == remaining.len() {
+                    let new_value = if common_len == key.len() {
                         Some(value)
                     } else {
-                        let new_suffix = remaining[common_len..].to_vec();
-                        let new_first = new_suffix[0];
-                        let new_node = TrieNode::leaf(new_suffix[1..].to_vec(), value);
-                        let new_hash = self.persist_node(&new_node)?;
-                        new_children.insert(new_first, new_hash);
+                        let new_node = Node::leaf(&key[common_len + 1..], value);
+                        new_children.insert(key[common_len], dirty(new_node));
                         None
                     };
 
-                    Ok(TrieNode::Branch {
-                        prefix: common,
+                    *node = Node::Branch {
+                        prefix: key[..common_len].to_vec(),
                         children: new_children,
                         value: new_value,
-                    })
-                } else {
-                    // Prefix matches, continue down
-                    let after_prefix = &remaining[prefix.len()..];
-
-                    if after_prefix.is_empty() {
-                        // Key ends at this branch
-                        Ok(TrieNode::Branch {
-                            prefix: prefix.clone(),
-                            children: children.clone(),
-                            value: Some(value),
-                        })
-                    } else {
-                        let next_byte = after_prefix[0];
-                        let mut new_children = children.clone();
-
-                        if let Some(child_hash) = children.get(&next_byte) {
-                            // Recurse into existing child
-                            let child = self.load_node(child_hash)?;
-                            let new_child =
-                                self.insert_recursive(&child, &after_prefix[1..], 0, value)?;
-                            let new_hash = self.persist_node(&new_child)?;
-                            new_children.insert(next_byte, new_hash);
-                        } else {
-                            // Create new child
-                            let new_node = TrieNode::leaf(after_prefix[1..].to_vec(), value);
-                            let new_hash = self.persist_node(&new_node)?;
-                            new_children.insert(next_byte, new_hash);
+                    };
+                } else if let Some((&next_byte, rest)) = key[prefix.len()..].split_first() {
+                    match children.get_mut(&next_byte) {
+                        Some(child) => {
+                            let child = self.make_dirty(child)?;
+                            self.insert_node(child, rest, value)?;
+                        }
+                        None => {
+                            children.insert(next_byte, dirty(Node::leaf(rest, value)));
                         }
+                    }
+                } else {
+                    // Key ends at this branch
+                    *branch_value = Some(value);
+                }
+            }
+        }
+        Ok(())
+    }
 
-                        Ok(TrieNode::Branch {
-                            prefix: prefix.clone(),
-                            children: new_children,
-                            value: *branch_value,
-                        })
+    fn get_node(&self, node: &Node, key: &[u8]) -> Result<Option<Hash>> {
+        match node {
+            Node::Empty => Ok(None),
+            Node::Leaf { key_suffix, value } => {
+                Ok((key_suffix.as_slice() == key).then_some(*value))
+            }
+            Node::Branch {
+                prefix,
+                children,
+                value,
+            } => {
+                let Some(after_prefix) = key.strip_prefix(prefix.as_slice()) else {
+                    return Ok(None);
+                };
+                let Some((next_byte, rest)) = after_prefix.split_first() else {
+                    return Ok(*value);
+                };
+                match children.get(next_byte) {
+                    Some(Child::Dirty(child)) => self.get_node(child, rest),
+                    Some(Child::Stored(hash)) => {
+                        let child = self.load_node(hash)?;
+                        self.get_recursive(&child, rest, 0)
                     }
+                    None => Ok(None),
                 }
             }
         }
@@ -285,10 +436,7 @@ impl<'a> MerkleTrie<'a> {
 
                 let next_byte = after_prefix[0];
                 if let Some(child_hash) = children.get(&next_byte) {
-                    // Need to load child - but we don't have mutable access
-                    // For now, load directly (inefficient but correct)
-                    let blob = self.store.get(child_hash)?;
-                    let child: TrieNode = bincode::deserialize(&blob.data)?;
+                    let child = self.load_node(child_hash)?;
                     self.get_recursive(&child, &after_prefix[1..], 0)
                 } else {
                     Ok(None)
@@ -297,145 +445,174 @@ impl<'a> MerkleTrie<'a> {
         }
     }
 
-    fn remove_recursive(
-        &mut self,
-        node: &TrieNode,
-        key: &[u8],
-        depth: usize,
-    ) -> Result<(TrieNode, Option<Hash>)> {
-        let remaining = &key[depth..];
-
+    fn remove_node(&self, node: &mut Node, key: &[u8]) -> Result<Option<Hash>> {
         match node {
-            TrieNode::Empty => Ok((TrieNode::Empty, None)),
-            TrieNode::Leaf { key_suffix, value } => {
-                if key_suffix == remaining {
-                    Ok((TrieNode::Empty, Some(*value)))
-                } else {
-                    Ok((node.clone(), None))
+            Node::Empty => Ok(None),
+            Node::Leaf { key_suffix, value } => {
+                if key_suffix.as_slice() != key {
+                    return Ok(None);
                 }
+                let removed = *value;
+                *node = Node::Empty;
+                Ok(Some(removed))
             }
-            TrieNode::Branch {
+            Node::Branch {
                 prefix,
                 children,
                 value,
             } => {
-                if !remaining.starts_with(prefix) {
-                    return Ok((node.clone(), None));
-                }
-
-                let after_prefix = &remaining[prefix.len()..];
+                let Some(after_prefix) = key.strip_prefix(prefix.as_slice()) else {
+                    return Ok(None);
+                };
 
-                if after_prefix.is_empty() {
+                let removed = match after_prefix.split_first() {
                     // Remove value at this branch
-                    if children.is_empty() {
-                        return Ok((TrieNode::Empty, *value));
-                    }
-                    return Ok((
-                        TrieNode::Branch {
-                            prefix: prefix.clone(),
-                            children: children.clone(),
-                            value: None,
-                        },
-                        *value,
-                    ));
-                }
-
-                let next_byte = after_prefix[0];
-                if let Some(child_hash) = children.get(&next_byte) {
-                    let child = self.load_node(child_hash)?;
-                    let (new_child, removed) =
-                        self.remove_recursive(&child, &after_prefix[1..], 0)?;
-
-                    if removed.is_some() {
-                        let mut new_children = children.clone();
-                        if new_child.is_empty() {
-                            new_children.remove(&next_byte);
-                        } else {
-                            let new_hash = self.persist_node(&new_child)?;
-                            new_children.insert(next_byte, new_hash);
+                    None => value.take(),
+                    Some((next_byte, rest)) => {
+                        let removed = match children.get_mut(next_byte) {
+                            None => None,
+                            Some(Child::Dirty(child)) => self.remove_node(child, rest)?,
+                            Some(Child::Stored(hash)) => {
+                                // Only dirty the child if something changes
+                                let mut loaded = Node::from(&*self.load_node(hash)?);
+                                let removed = self.remove_node(&mut loaded, rest)?;
+                                if removed.is_some() {
+                                    children.insert(*next_byte, dirty(loaded));
+                                }
+                                removed
+                            }
+                        };
+                        if matches!(children.get(next_byte), Some(Child::Dirty(child)) if child.is_empty())
+                        {
+                            children.remove(next_byte);
                         }
-
-                        // Collapse if only one child and no value
-                        if new_children.len() == 1 && value.is_none() {
-                            // Could collapse here for efficiency
-                        }
-
-                        if new_children.is_empty() && value.is_none() {
-                            return Ok((TrieNode::Empty, removed));
-                        }
-
-                        return Ok((
-                            TrieNode::Branch {
-                                prefix: prefix.clone(),
-                                children: new_children,
-                                value: *value,
-                            },
-                            removed,
-                        ));
+                        removed
                     }
-                }
+                };
 
-                Ok((node.clone(), None))
+                if removed.is_some() {
+                    self.collapse(node)?;
+                }
+                Ok(removed)
             }
         }
     }
 
-    fn collect_prefix(
-        &self,
-        node: &TrieNode,
-        prefix: &[u8],
-        _depth: usize,
-        current_key: Vec<u8>,
-        results: &mut Vec<(Vec<u8>, Hash)>,
-    ) -> Result<()> {
-        match node {
-            TrieNode::Empty => {}
-            TrieNode::Leaf { key_suffix, value } => {
-                let mut full_key = current_key;
-                full_key.extend(key_suffix);
-                if full_key.starts_with(prefix) {
-                    results.push((full_key, *value));
+    /// Restore the canonical shape of a branch after a removal
+    ///
+    /// A branch without a value keeps at least two children and one with a
+    /// value at least one; anything less becomes a leaf or merges into its
+    /// only child. `insert` never builds such branches, so the shape of the
+    /// trie, and with it the root hash, depends only on the keys it holds.
+    fn collapse(&self, node: &mut Node) -> Result<()> {
+        let Node::Branch {
+            prefix,
+            children,
+            value,
+        } = node
+        else {
+            return Ok(());
+        };
+
+        match (children.len(), *value) {
+            (0, None) => *node = Node::Empty,
+            (0, Some(value)) => {
+                *node = Node::Leaf {
+                    key_suffix: std::mem::take(prefix),
+                    value,
                 }
             }
-            TrieNode::Branch {
-                prefix: node_prefix,
-                children,
-                value,
-            } => {
-                let mut current = current_key;
-                current.extend(node_prefix);
-
-                if let Some(v) = value {
-                    if current.starts_with(prefix) {
-                        results.push((current.clone(), *v));
+            (1, None) => {
+                // Load first so a failed read leaves the node as it was
+                self.make_dirty(children.values_mut().next().expect("one child"))?;
+                let Some((byte, Child::Dirty(child))) = children.pop_first() else {
+                    unreachable!("child was just loaded");
+                };
+                let mut merged = std::mem::take(prefix);
+                merged.push(byte);
+                *node = match *child {
+                    Node::Leaf { key_suffix, value } => {
+                        merged.extend(key_suffix);
+                        Node::Leaf {
+                            key_suffix: merged,
+                            value,
+                        }
                     }
-                }
-
-                // Only recurse if we're still matching the prefix
-                if current.starts_with(prefix) || prefix.starts_with(&current) {
-                    for (byte, child_hash) in children {
-                        let mut child_key = current.clone();
-                        child_key.push(*byte);
-
-                        let blob = self.store.get(child_hash)?;
-                        let child: TrieNode = bincode::deserialize(&blob.data)?;
-                        self.collect_prefix(&child, prefix, 0, child_key, results)?;
+                    Node::Branch {
+                        prefix,
+                        children,
+                        value,
+                    } => {
+                        merged.extend(prefix);
+                        Node::Branch {
+                            prefix: merged,
+                            children,
+                            value,
+                        }
                     }
-                }
+                    Node::Empty => Node::Empty,
+                };
             }
+            _ => {}
         }
         Ok(())
     }
 }
 
+/// Store a node, caching it since it is likely to be read back soon
+pub(super) fn store_node(store: &dyn Storage, node: &TrieNode) -> Result<Hash> {
+    let data = bincode::serialize(node)?;
+    let hash = store.put(&Blob::new(BlobType::Tree, data))?;
+    if let Some(cache) = store.node_cache() {
+        cache.insert(hash, Arc::new(node.clone()));
+    }
+    Ok(hash)
+}
+
+/// Fetch and decode a node, going through the store's node cache
+pub(crate) fn load_node(store: &dyn Storage, hash: &Hash) -> Result<Arc<TrieNode>> {
+    let cache = store.node_cache();
+    if let Some(node) = cache.and_then(|cache| cache.get(hash)) {
+        return Ok(node);
+    }
+
+    let blob = store.get(hash)?;
+    if blob.blob_type != BlobType::Tree {
+        return Err(crate::Error::Corruption(format!(
+            "Expected Tree, got {:?}",
+            blob.blob_type
+        )));
+    }
+
+    let node: Arc<TrieNode> = Arc::new(bincode::deserialize(&blob.data)?);
+    if let Some(cache) = cache {
+        cache.insert(*hash, node.clone());
+    }
+    Ok(node)
+}
+
+/// Hash of a node as `commit` would store it, without storing anything
+fn hash_node(node: &Node) -> Hash {
+    encode_dirty(node).hash()
+}
+
+/// The stored form of a node, hashing any dirty children
+fn encode_dirty(node: &Node) -> TrieNode {
+    node.encode(|child| match child {
+        Child::Stored(hash) => *hash,
+        Child::Dirty(child) => hash_node(child),
+    })
+}
+
 /// Find the length of the common prefix between two byte slices
-fn common_prefix_len(a: &[u8], b: &[u8]) -> usize {
+pub(super) fn common_prefix_len(a: &[u8], b: &[u8]) -> usize {
     a.iter().zip(b.iter()).take_while(|(x, y)| x == y).count()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::store::ObjectStore;
     use tempfile::tempdir;
 
     fn setup() -> (tempfile::TempDir, ObjectStore) {
@@ -522,4 +699,141 @@ mod tests {
 
         assert_ne!(h2, h3);
     }
+
+    #[test]
+    fn test_trie_commit_writes_only_the_final_nodes() {
+        let store = crate::store::MemoryStore::new();
+        let mut trie = MerkleTrie::new(&store);
+        for i in 0..1000 {
+            let key = format!("t:thought-{}", i);
+            trie.insert(key.as_bytes(), Hash::digest(key.as_bytes()))
+                .unwrap();
+        }
+        assert_eq!(store.object_count(), 0);
+
+        let root = trie.root_hash();
+        assert_eq!(trie.commit().unwrap(), root);
+
+        // Every stored node is part of the committed tree
+        let mut reachable = 0;
+        let mut stack = vec![root];
+        while let Some(hash) = stack.pop() {
+            reachable += 1;
+            if let TrieNode::Branch { children, .. } = &*trie.load_node(&hash).unwrap() {
+                stack.extend(children.values());
+            }
+        }
+        assert_eq!(store.object_count(), reachable);
+
+        // Changes after a commit only rewrite their own path
+        let mut trie = MerkleTrie::from_root(&store, root).unwrap();
+        assert_eq!(trie.root_hash(), root);
+        trie.insert(b"t:thought-7", Hash::digest(b"changed"))
+            .unwrap();
+        trie.remove(b"t:thought-8").unwrap();
+        assert_eq!(trie.remove(b"t:missing").unwrap(), None);
+        let changed = trie.commit().unwrap();
+        assert_ne!(changed, root);
+        assert!(store.object_count() < reachable * 2);
+
+        let trie = MerkleTrie::from_root(&store, changed).unwrap();
+        assert_eq!(
+            trie.get(b"t:thought-7").unwrap(),
+            Some(Hash::digest(b"changed"))
+        );
+        assert_eq!(trie.get(b"t:thought-8").unwrap(), None);
+        assert_eq!(trie.list_prefix(b"t:").unwrap().len(), 999);
+    }
+
+    /// Small deterministic generator so failures reproduce
+    struct XorShift(u64);
+
+    impl XorShift {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % n as u64) as usize
+        }
+    }
+
+    /// Keys sharing prefixes and prefixes of each other, to exercise splits
+    /// and merges
+    fn random_key(rng: &mut XorShift) -> Vec<u8> {
+        let len = rng.below(6);
+        (0..len).map(|_| b"abc"[rng.below(3)]).collect()
+    }
+
+    #[test]
+    fn test_trie_root_depends_only_on_contents() {
+        let store = crate::store::MemoryStore::new();
+        for seed in 1..200u64 {
+            let mut rng = XorShift(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+            let mut expected = std::collections::BTreeMap::new();
+            let mut trie = MerkleTrie::new(&store);
+
+            // Random history of inserts and removes, committing now and then
+            for step in 0..60 {
+                let key = random_key(&mut rng);
+                if rng.below(3) == 0 {
+                    assert_eq!(trie.remove(&key).unwrap(), expected.remove(&key));
+                } else {
+                    let value = Hash::digest(&[step as u8, rng.below(4) as u8]);
+                    trie.insert(&key, value).unwrap();
+                    expected.insert(key, value);
+                }
+                if rng.below(10) == 0 {
+                    let root = trie.commit().unwrap();
+                    trie = MerkleTrie::from_root(&store, root).unwrap();
+                }
+            }
+
+            // The same contents inserted fresh, in a shuffled order
+            let mut entries: Vec<_> = expected.iter().collect();
+            for i in (1..entries.len()).rev() {
+                entries.swap(i, rng.below(i + 1));
+            }
+            let mut fresh = MerkleTrie::new(&store);
+            for (key, value) in entries {
+                fresh.insert(key, *value).unwrap();
+            }
+
+            assert_eq!(trie.root_hash(), fresh.root_hash(), "seed {}", seed);
+            assert_eq!(trie.commit().unwrap(), fresh.commit().unwrap());
+
+            // And built bottom-up from the sorted entries
+            let sorted = expected
+                .iter()
+                .map(|(key, value)| Ok((key.clone(), *value)));
+            let built = MerkleTrie::build_from_sorted(&store, sorted).unwrap();
+            assert_eq!(built.root_hash(), fresh.root_hash(), "seed {}", seed);
+            let mut listed = trie.list_prefix(b"").unwrap();
+            listed.sort_by(|a, b| a.0.cmp(&b.0));
+            let expected: Vec<_> = expected.into_iter().collect();
+            assert_eq!(listed, expected);
+        }
+    }
+
+    #[test]
+    fn test_trie_remove_everything_leaves_empty_root() {
+        let (_dir, store) = setup();
+        let mut trie = MerkleTrie::new(&store);
+        let keys: [&[u8]; 5] = [b"a", b"ab", b"abc", b"abd", b"b"];
+        for key in keys {
+            trie.insert(key, Hash::digest(key)).unwrap();
+        }
+        let root = trie.commit().unwrap();
+
+        // Removing down to one key matches a trie that only ever held it
+        let mut trie = MerkleTrie::from_root(&store, root).unwrap();
+        for key in [b"abd" as &[u8], b"a", b"b", b"abc"] {
+            trie.remove(key).unwrap();
+        }
+        let mut single = MerkleTrie::new(&store);
+        single.insert(b"ab", Hash::digest(b"ab")).unwrap();
+        assert_eq!(trie.root_hash(), single.root_hash());
+
+        trie.remove(b"ab").unwrap();
+        assert_eq!(trie.root_hash(), Hash::ZERO);
+    }
 }
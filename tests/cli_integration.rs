@@ -173,6 +173,40 @@ fn test_cli_list_thoughts() {
     );
 }
 
+#[test]
+fn test_cli_list_pages_with_cursor() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join(".indra");
+    let db_str = db_path.to_str().unwrap();
+
+    run_indra(&["init"], db_str);
+    for id in ["a", "b", "c"] {
+        run_indra(&["create", &format!("Thought {}", id), "--id", id], db_str);
+    }
+    run_indra(&["commit", "Three thoughts"], db_str);
+
+    let (stdout, _stderr, success) = run_indra(&["list", "--limit", "2"], db_str);
+    assert!(success, "list should succeed");
+    let json: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(json["count"], 2);
+    assert_eq!(json["thoughts"][0]["id"], "a");
+    assert_eq!(json["thoughts"][1]["id"], "b");
+    let cursor = json["next_cursor"]
+        .as_str()
+        .expect("should have a next page");
+
+    let (stdout, _stderr, success) =
+        run_indra(&["list", "--limit", "2", "--after", cursor], db_str);
+    assert!(success, "second page should succeed");
+    let json: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(json["count"], 1);
+    assert_eq!(json["thoughts"][0]["id"], "c");
+    assert!(json["next_cursor"].is_null());
+
+    let (_stdout, _stderr, success) = run_indra(&["list", "--after", "zz"], db_str);
+    assert!(!success, "a malformed cursor should fail");
+}
+
 #[test]
 fn test_cli_update_thought() {
     let dir = tempdir().unwrap();
@@ -222,6 +256,53 @@ fn test_cli_delete_thought() {
     assert!(!success, "thought should not exist after delete");
 }
 
+#[test]
+fn test_cli_attach_and_cat_attachment() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join(".indra");
+    let db_str = db_path.to_str().unwrap();
+    let file_path = dir.path().join("notes.md");
+    std::fs::write(&file_path, b"# Findings\n").unwrap();
+
+    run_indra(&["init"], db_str);
+    run_indra(&["create", "Has notes", "--id", "doc"], db_str);
+
+    let (stdout, stderr, success) =
+        run_indra(&["attach", "doc", file_path.to_str().unwrap()], db_str);
+    assert!(success, "attach should succeed: {}", stderr);
+    let json: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(json["attachment"]["name"], "notes.md");
+    assert_eq!(json["attachment"]["mime_type"], "text/markdown");
+    assert_eq!(json["attachment"]["size"], 11);
+
+    let (stdout, _stderr, success) = run_indra(&["get", "doc"], db_str);
+    assert!(success);
+    let json: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(json["attachments"][0]["name"], "notes.md");
+
+    let (stdout, _stderr, success) = run_indra(&["cat-attachment", "doc", "notes.md"], db_str);
+    assert!(success);
+    assert_eq!(stdout, "# Findings\n");
+
+    let copy_path = dir.path().join("copy.md");
+    let (_stdout, _stderr, success) = run_indra(
+        &[
+            "cat-attachment",
+            "doc",
+            "notes.md",
+            "-o",
+            copy_path.to_str().unwrap(),
+        ],
+        db_str,
+    );
+    assert!(success);
+    assert_eq!(std::fs::read(&copy_path).unwrap(), b"# Findings\n");
+
+    let (_stdout, stderr, success) = run_indra(&["cat-attachment", "doc", "missing"], db_str);
+    assert!(!success);
+    assert!(stderr.contains("No attachment"), "{}", stderr);
+}
+
 // ============================================================================
 // Auto-commit Tests (Critical for MCP integration)
 // ============================================================================
@@ -418,6 +499,448 @@ fn test_cli_branches() {
     assert!(stdout.contains("feature"), "should have feature branch");
 }
 
+#[test]
+fn test_cli_reflog() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join(".indra");
+    let db_str = db_path.to_str().unwrap();
+
+    run_indra(&["init"], db_str);
+    run_indra(&["create", "Initial thought"], db_str);
+    run_indra(&["branch", "experiments/try-mongodb"], db_str);
+    let (_stdout, stderr, success) = run_indra(&["checkout", "experiments/try-mongodb"], db_str);
+    assert!(success, "checkout should succeed: {}", stderr);
+
+    let (stdout, stderr, success) = run_indra(&["reflog"], db_str);
+    assert!(success, "reflog should succeed: {}", stderr);
+    let json: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    let entries = json["entries"].as_array().unwrap();
+    assert_eq!(entries[0]["name"], "HEAD");
+    assert_eq!(
+        entries[0]["reason"],
+        "checkout: moving to experiments/try-mongodb"
+    );
+    assert_eq!(entries[1]["name"], "experiments/try-mongodb");
+
+    let (stdout, _stderr, success) = run_indra(&["reflog", "main", "--limit", "1"], db_str);
+    assert!(success);
+    let json: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(json["count"], 1);
+    assert!(json["entries"][0]["reason"]
+        .as_str()
+        .unwrap()
+        .starts_with("commit: "));
+}
+
+// ============================================================================
+// Maintenance Tests
+// ============================================================================
+
+#[test]
+fn test_cli_gc_preserves_data() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join(".indra");
+    let db_str = db_path.to_str().unwrap();
+
+    run_indra(&["init"], db_str);
+    run_indra(&["create", "First draft", "--id", "doc"], db_str);
+    run_indra(&["update", "doc", "Second draft"], db_str);
+    run_indra(&["update", "doc", "Final draft"], db_str);
+
+    let (stdout, _stderr, success) = run_indra(&["gc"], db_str);
+    assert!(success, "gc should succeed");
+
+    // Every object written by single-change commits is still reachable
+    let json: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(json["status"], "ok");
+    assert_eq!(json["objects_removed"], 0);
+    assert_eq!(json["objects_before"], json["objects_after"]);
+
+    // History and current state survive compaction
+    let (stdout, _stderr, _) = run_indra(&["get", "doc"], db_str);
+    assert!(stdout.contains("Final draft"));
+    let (stdout, _stderr, _) = run_indra(&["log"], db_str);
+    assert!(stdout.contains("\"count\":3"));
+}
+
+#[test]
+fn test_cli_fsck_clean_database() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join(".indra");
+    let db_str = db_path.to_str().unwrap();
+
+    run_indra(&["init"], db_str);
+    run_indra(&["create", "Alpha", "--id", "a"], db_str);
+    run_indra(&["create", "Beta", "--id", "b"], db_str);
+    run_indra(&["relate", "a", "b"], db_str);
+
+    let (stdout, stderr, success) = run_indra(&["fsck"], db_str);
+    assert!(success, "fsck should pass on a fresh database: {}", stderr);
+
+    let json: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(json["status"], "ok");
+    assert_eq!(json["problems"].as_array().unwrap().len(), 0);
+    assert!(json["commits_checked"].as_u64().unwrap() >= 3);
+
+    // Deleting a related thought leaves its edge behind as a warning
+    run_indra(&["delete", "b"], db_str);
+    let (stdout, stderr, success) = run_indra(&["fsck"], db_str);
+    assert!(success, "dangling edges are not corruption: {}", stderr);
+    let json: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(json["status"], "ok");
+    assert_eq!(json["warnings"][0]["kind"], "dangling_edge");
+}
+
+#[test]
+fn test_cli_repack_preserves_data() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join(".indra");
+    let db_str = db_path.to_str().unwrap();
+
+    run_indra(&["init"], db_str);
+    run_indra(&["create", "Draft 0", "--id", "doc"], db_str);
+    for i in 1..10 {
+        run_indra(&["update", "doc", &format!("Draft {}", i)], db_str);
+    }
+
+    let (stdout, stderr, success) = run_indra(&["repack"], db_str);
+    assert!(success, "repack should succeed: {}", stderr);
+    let json: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(json["status"], "ok");
+    assert!(json["deltas"].as_u64().unwrap() > 0);
+
+    let (stdout, _stderr, success) = run_indra(&["get", "doc"], db_str);
+    assert!(success);
+    assert!(stdout.contains("Draft 9"));
+
+    let (stdout, _stderr, success) = run_indra(&["fsck"], db_str);
+    assert!(success, "fsck after repack: {}", stdout);
+}
+
+#[test]
+fn test_cli_init_encrypt() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join(".indra");
+    let db_str = db_path.to_str().unwrap();
+    let key_path = dir.path().join("indra.key");
+    std::fs::write(&key_path, b"correct horse battery staple").unwrap();
+    let key_str = key_path.to_str().unwrap();
+
+    // A key is required
+    let (_stdout, stderr, success) = run_indra(&["init", "--encrypt"], db_str);
+    assert!(!success, "init --encrypt without a key should fail");
+    assert!(stderr.contains("key"), "{}", stderr);
+
+    let (stdout, stderr, success) =
+        run_indra(&["--key-file", key_str, "init", "--encrypt"], db_str);
+    assert!(success, "init --encrypt should succeed: {}", stderr);
+    let json: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(json["encrypted"], true);
+
+    let (_stdout, stderr, success) = run_indra(
+        &["--key-file", key_str, "create", "Hidden", "--id", "doc"],
+        db_str,
+    );
+    assert!(success, "create should succeed: {}", stderr);
+    let (stdout, _stderr, success) = run_indra(&["--key-file", key_str, "get", "doc"], db_str);
+    assert!(success);
+    assert!(stdout.contains("Hidden"));
+
+    // Without the key, or with another one, the database does not open
+    let (_stdout, stderr, success) = run_indra(&["get", "doc"], db_str);
+    assert!(!success);
+    assert!(stderr.contains("encrypted"), "{}", stderr);
+
+    std::fs::write(&key_path, b"something else").unwrap();
+    let (_stdout, stderr, success) = run_indra(&["--key-file", key_str, "get", "doc"], db_str);
+    assert!(!success);
+    assert!(stderr.contains("Wrong encryption key"), "{}", stderr);
+}
+
+#[test]
+fn test_cli_migrate_current_database() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join(".indra");
+    let db_str = db_path.to_str().unwrap();
+
+    run_indra(&["init"], db_str);
+    let (stdout, stderr, success) = run_indra(&["migrate"], db_str);
+    assert!(success, "migrate should succeed: {}", stderr);
+
+    let json: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(json["status"], "up_to_date");
+    assert_eq!(json["from"], json["to"]);
+    assert!(json["backup"].is_null());
+}
+
+#[test]
+fn test_cli_backup() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join(".indra");
+    let db_str = db_path.to_str().unwrap();
+    let backup_path = dir.path().join("backup.indra");
+    let backup_str = backup_path.to_str().unwrap();
+
+    run_indra(&["init"], db_str);
+    run_indra(&["create", "Keep me safe", "--id", "a"], db_str);
+    // An edge left behind by a deleted thought does not block backups
+    run_indra(&["create", "Gone soon", "--id", "b"], db_str);
+    run_indra(&["relate", "a", "b"], db_str);
+    run_indra(&["delete", "b"], db_str);
+
+    let (stdout, stderr, success) = run_indra(&["backup", backup_str], db_str);
+    assert!(success, "backup should succeed: {}", stderr);
+    let json: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(json["status"], "ok");
+    assert_eq!(json["head"], "main");
+
+    let (stdout, _stderr, success) = run_indra(&["get", "a"], backup_str);
+    assert!(success);
+    assert!(stdout.contains("Keep me safe"));
+
+    // Never overwrites
+    let (_stdout, stderr, success) = run_indra(&["backup", backup_str], db_str);
+    assert!(!success);
+    assert!(stderr.contains("already exists"), "{}", stderr);
+
+    // A key alone leaves the copy of a plaintext database in the clear
+    let key_path = dir.path().join("indra.key");
+    std::fs::write(&key_path, b"correct horse battery staple").unwrap();
+    let key_str = key_path.to_str().unwrap();
+    let plain_path = dir.path().join("plain.indra");
+    let plain_str = plain_path.to_str().unwrap();
+    let (stdout, stderr, success) =
+        run_indra(&["--key-file", key_str, "backup", plain_str], db_str);
+    assert!(success, "backup should succeed: {}", stderr);
+    let json: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(json["encrypted"], false);
+    let (stdout, _stderr, success) = run_indra(&["get", "a"], plain_str);
+    assert!(success);
+    assert!(stdout.contains("Keep me safe"));
+
+    // Unless asked for
+    let sealed_path = dir.path().join("sealed.indra");
+    let sealed_str = sealed_path.to_str().unwrap();
+    let (stdout, stderr, success) = run_indra(
+        &["--key-file", key_str, "backup", "--encrypt", sealed_str],
+        db_str,
+    );
+    assert!(success, "backup --encrypt should succeed: {}", stderr);
+    let json: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(json["encrypted"], true);
+    let (_stdout, stderr, success) = run_indra(&["get", "a"], sealed_str);
+    assert!(!success);
+    assert!(stderr.contains("encrypted"), "{}", stderr);
+}
+
+#[test]
+fn test_cli_bundle_roundtrip() {
+    let dir = tempdir().unwrap();
+    let source_path = dir.path().join("source.indra");
+    let source = source_path.to_str().unwrap();
+    let target_path = dir.path().join("target.indra");
+    let target = target_path.to_str().unwrap();
+    let bundle_path = dir.path().join("main.bundle");
+    let bundle = bundle_path.to_str().unwrap();
+
+    run_indra(&["init"], source);
+    run_indra(&["create", "Travelling thought", "--id", "t"], source);
+    let (stdout, stderr, success) = run_indra(&["bundle", "create", bundle, "main"], source);
+    assert!(success, "bundle create should succeed: {}", stderr);
+    let json: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    assert!(json["objects"].as_u64().unwrap() > 0);
+
+    run_indra(&["init"], target);
+    let (_stdout, stderr, success) = run_indra(&["bundle", "unbundle", bundle], target);
+    assert!(success, "unbundle should succeed: {}", stderr);
+    let (stdout, _stderr, success) = run_indra(&["get", "t"], target);
+    assert!(success);
+    assert!(stdout.contains("Travelling thought"));
+
+    // Incremental: only what the target does not have yet
+    let (stdout, _stderr, _) = run_indra(&["log"], target);
+    let log: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    let known = log["commits"][0]["hash"].as_str().unwrap().to_string();
+    run_indra(&["create", "Second thought", "--id", "u"], source);
+    let (_stdout, stderr, success) = run_indra(
+        &["bundle", "create", bundle, "main", "--since", &known],
+        source,
+    );
+    assert!(success, "{}", stderr);
+    let (_stdout, stderr, success) = run_indra(&["bundle", "unbundle", bundle], target);
+    assert!(success, "{}", stderr);
+    let (stdout, _stderr, success) = run_indra(&["get", "u"], target);
+    assert!(success);
+    assert!(stdout.contains("Second thought"));
+}
+
+#[test]
+fn test_cli_stats() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join(".indra");
+    let db_str = db_path.to_str().unwrap();
+
+    run_indra(&["init"], db_str);
+    run_indra(&["create", "First", "--id", "a"], db_str);
+    run_indra(&["create", "Second", "--id", "b"], db_str);
+
+    let (stdout, stderr, success) = run_indra(&["stats"], db_str);
+    assert!(success, "stats should succeed: {}", stderr);
+    let json: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(json["objects"]["Thought"]["count"], 2);
+    assert_eq!(json["branches"][0]["name"], "main");
+    assert_eq!(json["branches"][0]["thoughts"], 2);
+    assert_eq!(json["commits"], 2);
+    assert_eq!(json["commits_by_author"]["indra-cli"], 2);
+    assert_eq!(json["embeddings"][0]["thoughts"], 2);
+    assert!(json["stored_bytes"].as_u64().unwrap() > 0);
+    let file_bytes = json["file_bytes"].as_u64().unwrap();
+    assert!(json["reclaimable_bytes"].as_u64().unwrap() < file_bytes);
+}
+
+#[test]
+fn test_cli_prove() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join(".indra");
+    let db_str = db_path.to_str().unwrap();
+
+    run_indra(&["init"], db_str);
+    run_indra(&["create", "First", "--id", "a"], db_str);
+    run_indra(&["create", "Second", "--id", "b"], db_str);
+
+    let (stdout, stderr, success) = run_indra(&["prove", "a", "HEAD~1"], db_str);
+    assert!(success, "prove should succeed: {}", stderr);
+    let json: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(json["key"], "t:a");
+
+    let nodes: Vec<Vec<u8>> = json["nodes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|node| hex::decode(node.as_str().unwrap()).unwrap())
+        .collect();
+    let proof = indra_db::trie::Proof::decode(&nodes).unwrap();
+    let tree = indra_db::Hash::from_hex(json["tree"].as_str().unwrap()).unwrap();
+    let value = indra_db::Hash::from_hex(json["value"].as_str().unwrap()).unwrap();
+    assert!(indra_db::trie::verify_proof(tree, b"t:a", value, &proof));
+
+    // b was created after the first commit
+    let (_, _, success) = run_indra(&["prove", "b", "HEAD~1"], db_str);
+    assert!(!success, "prove should fail for a thought the commit lacks");
+
+    // Edges prove the same way, and branch names resolve directly
+    run_indra(&["relate", "a", "b", "-t", "cites"], db_str);
+    let (stdout, stderr, success) =
+        run_indra(&["prove", "a", "main", "--to", "b", "-t", "cites"], db_str);
+    assert!(success, "edge prove should succeed: {}", stderr);
+    let json: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(json["key"], "e:a:b:cites");
+
+    let proof_path = dir.path().join("proof.json");
+    std::fs::write(&proof_path, &stdout).unwrap();
+    let proof_str = proof_path.to_str().unwrap();
+    let (stdout, stderr, success) = run_indra(&["verify", proof_str], db_str);
+    assert!(success, "verify should accept the proof: {}", stderr);
+    let verified: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(verified["status"], "ok");
+
+    // Pinned to another tree, or claiming another key, the proof fails
+    let (_, _, success) = run_indra(
+        &[
+            "verify",
+            proof_str,
+            "--tree",
+            &indra_db::Hash::ZERO.to_hex(),
+        ],
+        db_str,
+    );
+    assert!(!success, "verify should reject a proof for another tree");
+    let mut forged = json.clone();
+    forged["key"] = "e:a:b:refutes".into();
+    std::fs::write(&proof_path, forged.to_string()).unwrap();
+    let (stdout, _, success) = run_indra(&["verify", proof_str], db_str);
+    assert!(!success, "verify should reject a proof for another key");
+    let verified: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(verified["status"], "invalid");
+}
+
+// ============================================================================
+// Concurrency Tests
+// ============================================================================
+
+#[test]
+fn test_cli_parallel_writers_lose_nothing() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join(".indra");
+    let db_str = db_path.to_str().unwrap().to_string();
+
+    const WRITERS: usize = 8;
+    const PER_WRITER: usize = 3;
+
+    // No init: the first writers also race to create the file
+    let handles: Vec<_> = (0..WRITERS)
+        .map(|w| {
+            let db_str = db_str.clone();
+            std::thread::spawn(move || {
+                for i in 0..PER_WRITER {
+                    let id = format!("w{}-{}", w, i);
+                    let content = format!("Thought {} from writer {}", i, w);
+                    let (_stdout, stderr, success) =
+                        run_indra(&["create", &content, "--id", &id], &db_str);
+                    assert!(success, "writer {} failed: {}", w, stderr);
+
+                    let (_stdout, stderr, success) = run_indra(&["list"], &db_str);
+                    assert!(success, "reader {} failed: {}", w, stderr);
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    let (stdout, _stderr, success) = run_indra(&["list"], &db_str);
+    assert!(success);
+    let json: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(json["count"], WRITERS * PER_WRITER);
+
+    let (stdout, _stderr, _) = run_indra(&["log"], &db_str);
+    let json: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(json["count"], WRITERS * PER_WRITER);
+}
+
+#[test]
+fn test_cli_lock_timeout() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join(".indra");
+    let db_str = db_path.to_str().unwrap();
+
+    run_indra(&["init"], db_str);
+
+    // Hold the exclusive lock from this process
+    let db = indra_db::Database::open(&db_path).unwrap();
+
+    let (_stdout, stderr, success) =
+        run_indra(&["--lock-timeout", "0", "create", "Blocked"], db_str);
+    assert!(!success, "writer should give up while the lock is held");
+    assert!(stderr.contains("locked"), "unexpected error: {}", stderr);
+
+    let (_stdout, _stderr, success) = run_indra(&["--lock-timeout", "0", "list"], db_str);
+    assert!(
+        !success,
+        "reader should give up while a writer holds the lock"
+    );
+
+    drop(db);
+    let (_stdout, stderr, success) =
+        run_indra(&["--lock-timeout", "0", "create", "Unblocked"], db_str);
+    assert!(
+        success,
+        "writer should succeed once the lock is free: {}",
+        stderr
+    );
+}
+
 // ============================================================================
 // Edge Cases
 // ============================================================================